@@ -0,0 +1,164 @@
+//! Congestion control
+//!
+//! A connection consults its `CongestionController` to decide how many bytes of ack-eliciting
+//! packets may be in flight, and informs it of acknowledgements, losses, and other congestion
+//! signals. `NewReno` is used unless `TransportConfig::congestion_controller` selects otherwise.
+
+use std::cmp;
+use std::time::Instant;
+
+use crate::connection::RttInfo;
+use crate::endpoint::TransportConfig;
+
+/// Constructs the congestion controller for a new connection
+///
+/// Called with the connection's transport configuration.
+pub type ControllerFactory =
+    dyn Fn(&TransportConfig) -> Box<dyn CongestionController> + Send + Sync;
+
+/// Connection state accompanying each notification to a `CongestionController`
+pub struct Context<'a> {
+    /// The current time
+    pub now: Instant,
+    /// Bytes of ack-eliciting packets that have been sent but not yet acknowledged or declared
+    /// lost, after accounting for the event being reported
+    pub bytes_in_flight: u64,
+    /// The connection's RTT estimate, if a sample has been taken
+    pub rtt: Option<RttInfo>,
+    /// The connection's transport configuration, including any per-connection overrides
+    pub config: &'a TransportConfig,
+}
+
+/// An algorithm governing how much data a connection may have in flight
+pub trait CongestionController: Send {
+    /// An ack-eliciting packet of `bytes` bytes, sent at `sent`, was acknowledged
+    ///
+    /// `app_limited` is set if the window had room to spare when the packet was sent, with nothing
+    /// more waiting to be sent, so that the acknowledgement says little about the path's capacity.
+    fn on_ack(&mut self, cx: &Context, sent: Instant, bytes: u64, app_limited: bool);
+
+    /// The peer reported congestion affecting packets sent up to `sent`, e.g. via ECN
+    fn on_congestion_event(&mut self, cx: &Context, sent: Instant);
+
+    /// Ack-eliciting packets totalling `bytes` bytes were declared lost, the most recent of which
+    /// was sent at `largest_sent`
+    ///
+    /// Treated as a congestion event by default.
+    fn on_packets_lost(&mut self, cx: &Context, largest_sent: Instant, bytes: u64) {
+        let _ = bytes;
+        self.on_congestion_event(cx, largest_sent);
+    }
+
+    /// Every packet sent over a period of `TransportConfig::persistent_congestion_threshold`
+    /// consecutive probe timeouts was lost
+    ///
+    /// Reported after the corresponding `on_packets_lost`.
+    fn on_persistent_congestion(&mut self, cx: &Context);
+
+    /// The peer moved to a new address, so anything learned about the old path no longer applies
+    fn on_path_change(&mut self, cx: &Context);
+
+    /// Seed the window from a bandwidth-delay product of `bdp` bytes measured on an earlier
+    /// connection; see `Connection::warm_up`
+    ///
+    /// Ignored by default.
+    fn warm_up(&mut self, cx: &Context, bdp: u64) {
+        let _ = (cx, bdp);
+    }
+
+    /// Maximum number of bytes of ack-eliciting packets that may be in flight
+    fn window(&self) -> u64;
+}
+
+/// The NewReno congestion controller described in the QUIC recovery specification
+///
+/// Parameterized by `TransportConfig::initial_window`, `minimum_window`, `max_datagram_size`, and
+/// `loss_reduction_factor`.
+pub struct NewReno {
+    /// Maximum number of bytes in flight that may be sent.
+    window: u64,
+    /// The time when QUIC first detects a loss, causing it to enter recovery. When a packet sent
+    /// after this time is acknowledged, QUIC exits recovery.
+    recovery_start_time: Instant,
+    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
+    /// slow start and the window grows by the number of bytes acknowledged.
+    ssthresh: u64,
+    /// Bytes that must be acknowledged before a window seeded by `warm_up` is trusted
+    warmup: u64,
+}
+
+impl NewReno {
+    /// Construct a controller in slow start, with the configured initial window
+    pub fn new(config: &TransportConfig) -> Self {
+        NewReno {
+            window: config.initial_window,
+            recovery_start_time: Instant::now(),
+            ssthresh: u64::max_value(),
+            warmup: 0,
+        }
+    }
+
+    fn in_recovery(&self, sent_time: Instant) -> bool {
+        sent_time <= self.recovery_start_time
+    }
+}
+
+impl CongestionController for NewReno {
+    fn on_ack(&mut self, cx: &Context, sent: Instant, bytes: u64, app_limited: bool) {
+        self.warmup = self.warmup.saturating_sub(bytes);
+        // Do not increase congestion window in recovery period, nor based on packets sent
+        // while it wasn't the limiting factor.
+        if self.in_recovery(sent) || app_limited {
+            return;
+        }
+        if self.window < self.ssthresh {
+            // Slow start.
+            self.window += bytes;
+        } else {
+            // Congestion avoidance.
+            self.window += cx.config.max_datagram_size * bytes / self.window;
+        }
+    }
+
+    fn on_congestion_event(&mut self, cx: &Context, sent: Instant) {
+        // Start a new recovery epoch if the lost packet is larger than the end of the
+        // previous recovery epoch.
+        if self.in_recovery(sent) {
+            return;
+        }
+        self.recovery_start_time = cx.now;
+        if self.warmup != 0 {
+            // The seeded window was too optimistic for this path; fall back to a normal slow start
+            self.on_path_change(cx);
+            return;
+        }
+        // *= factor
+        let window = (self.window * cx.config.loss_reduction_factor as u64) >> 16;
+        self.window = cmp::max(window, cx.config.minimum_window);
+        self.ssthresh = self.window;
+    }
+
+    fn on_persistent_congestion(&mut self, cx: &Context) {
+        self.window = cx.config.minimum_window;
+    }
+
+    fn on_path_change(&mut self, cx: &Context) {
+        self.window = cx.config.initial_window;
+        self.ssthresh = u64::max_value();
+        self.warmup = 0;
+    }
+
+    fn warm_up(&mut self, cx: &Context, bdp: u64) {
+        let window = bdp / 2;
+        if window <= cx.config.initial_window {
+            return;
+        }
+        self.window = window;
+        self.ssthresh = bdp;
+        self.warmup = window;
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+}
@@ -1,5 +1,5 @@
 use std::collections::{hash_map, BTreeMap, HashMap, VecDeque};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{cmp, io, mem};
@@ -8,16 +8,19 @@ use bytes::{Buf, Bytes, BytesMut};
 use err_derive::Error;
 use fnv::{FnvHashMap, FnvHashSet};
 use rand::{rngs::OsRng, Rng};
+use rustls::{CipherSuite, ProtocolVersion};
 use slog::Logger;
 
 use crate::coding::{BufExt, BufMutExt};
+use crate::congestion::{self, CongestionController, NewReno};
 use crate::crypto::{
     self, reset_token_for, Crypto, CryptoClientConfig, CryptoSession, HeaderCrypto,
-    RingHeaderCrypto, TlsSession, ACK_DELAY_EXPONENT,
+    RingHeaderCrypto, TlsSession,
 };
 use crate::dedup::Dedup;
-use crate::endpoint::{Event, Timer, TransportConfig};
+use crate::endpoint::{Event, MigrationKind, Timer, TransportConfig};
 use crate::frame::FrameStruct;
+use crate::handoff::{ConnectionState, ExportError};
 use crate::packet::{
     set_payload_length, ConnectionId, EcnCodepoint, Header, LongType, Packet, PacketNumber,
     PartialDecode, SpaceId, LONG_RESERVED_BITS, SHORT_RESERVED_BITS,
@@ -27,7 +30,8 @@ use crate::stream::{self, ReadError, Stream, Streams, WriteError};
 use crate::transport_parameters::{self, TransportParameters};
 use crate::{
     frame, Directionality, EndpointConfig, Frame, Side, StreamId, Transmit, TransportError,
-    MIN_INITIAL_SIZE, MIN_MTU, RESET_TOKEN_SIZE, TIMER_GRANULARITY, VERSION,
+    TransportErrorCode, MAX_STREAM_COUNT, MIN_INITIAL_SIZE, MIN_MTU, RESET_TOKEN_SIZE,
+    TIMER_GRANULARITY, VERSION,
 };
 
 pub struct Connection {
@@ -48,11 +52,20 @@ pub struct Connection {
     rem_cid_seq: u64,
     remote: SocketAddr,
     prev_remote: Option<SocketAddr>,
+    /// Local address outgoing packets are pinned to, if any
+    local_ip: Option<IpAddr>,
+    /// The local CID the peer most recently addressed on the current path
+    ///
+    /// A peer that changes address deliberately switches to a fresh CID, so reuse of this one from
+    /// a new address indicates NAT rebinding instead.
+    path_loc_cid: ConnectionId,
     state: State,
     side: Side,
     mtu: u16,
     zero_rtt_crypto: Option<CryptoSpace>,
     key_phase: bool,
+    /// Whether the 1-RTT keys have ever been updated
+    key_updated: bool,
     params: TransportParameters,
     /// Streams on which writing was blocked on *connection-level* flow or congestion control
     blocked_streams: FnvHashSet<StreamId>,
@@ -62,21 +75,52 @@ pub struct Connection {
     data_sent: u64,
     /// Sum of end offsets of all receive streams. Includes gaps, so it's an upper bound.
     data_recvd: u64,
-    /// Limit on incoming data
+    /// Limit on incoming data, before `TransportConfig::max_unread_data` is applied
     local_max_data: u64,
+    /// Limit on incoming data last sent to the peer, which its flow control is based on
+    max_data_sent: u64,
+    /// Bytes of incoming stream data that no longer occupy receive buffers, having been read by
+    /// the application or discarded
+    data_consumed: u64,
     /// Stream data we're sending that hasn't been acknowledged or reset yet
     unacked_data: u64,
+    /// Whether `unacked_data` has reached `TransportConfig::send_low_water_mark` since the last
+    /// `Event::SendBufferLow`
+    send_above_low_water: bool,
+    /// Whether nothing was in flight or waiting to be sent when last checked, if `Event::Idle` is
+    /// enabled
+    quiescent: bool,
     client_config: Option<ClientConfig>,
     /// ConnectionId sent by this client on the first Initial, if a Retry was received.
     orig_rem_cid: Option<ConnectionId>,
+    /// Total number of packets sent
+    packets_sent: u64,
+    /// Total number of numbered packets received and authenticated
+    packets_received: u64,
+    /// Total number of received packets numbered below one already received in the same space
+    reordered_packets_received: u64,
     /// Total number of outgoing packets that have been deemed lost
     lost_packets: u64,
+    /// Total number of outgoing packets that did not elicit an acknowledgement
+    ack_only_packets: u64,
+    /// Largest distance in packet numbers by which an acknowledgement was observed out of order
+    max_reordering: u64,
+    /// Total number of outgoing packets acknowledged after a later packet in the same space
+    reordered_packets_acked: u64,
+    /// Total number of outgoing packets that elicited an acknowledgement
+    ack_eliciting_packets: u64,
     io: IoQueue,
     events: VecDeque<Event>,
     /// Number of local connection IDs that have been issued in NEW_CONNECTION_ID frames.
     cids_issued: u64,
     /// Outgoing spin bit state
     spin: bool,
+    /// Spin bit of the most recent in-order short header packet received
+    rx_spin: bool,
+    /// When `rx_spin` last changed
+    rx_spin_edge: Option<Instant>,
+    /// Most recent RTT sample derived from the interval between incoming spin bit edges
+    spin_rtt: Option<Duration>,
     /// Packet number spaces: initial, handshake, 1-RTT
     spaces: [PacketSpace; 3],
     /// Highest usable packet number space
@@ -85,13 +129,29 @@ pub struct Connection {
     prev_crypto: Option<PrevCrypto>,
     /// Latest PATH_CHALLENGE token issued to the peer along the current path
     path_challenge: Option<u64>,
-    /// Whether the remote endpoint has opened any streams the application doesn't know about yet
-    stream_opened: bool,
+    /// Validation of an alternate path requested by the application, independent of the current
+    /// path
+    path_probe: Option<PathProbe>,
+    /// Streams opened by the remote endpoint that the application doesn't know about yet
+    opened_streams: VecDeque<StreamId>,
+    /// Whether to permit the peer to open new streams as the application accepts existing ones
+    accepting_streams: bool,
     accepted_0rtt: bool,
+    /// Whether the server refused our 0-RTT data, invalidating any streams opened in 0-RTT
+    rejected_0rtt: bool,
+    /// Number of bidirectional and unidirectional streams that were open when 0-RTT was rejected
+    rejected_0rtt_streams: (u64, u64),
     /// Whether the idle timer should be reset the next time an ack-eliciting packet is transmitted.
+    ///
+    /// Set only on receipt of a packet, so that retransmissions, probes, and keep-alives sent into
+    /// an unresponsive path can't postpone the idle timeout indefinitely.
     permit_idle_reset: bool,
     /// Negotiated idle timeout
     idle_timeout: u64,
+    /// Interval of inactivity after which a keep-alive packet is sent, if any
+    keep_alive_interval: Option<Duration>,
+    /// Fixed length of the closing and draining periods, overriding the PTO-derived default
+    draining_period: Option<Duration>,
 
     //
     // Queued non-retransmittable 1-RTT data
@@ -109,6 +169,8 @@ pub struct Connection {
     /// The number of times all unacknowledged CRYPTO data has been retransmitted without receiving
     /// an ack.
     crypto_count: u32,
+    /// Bytes of CRYPTO data queued for retransmission after being deemed lost
+    crypto_retransmitted: u64,
     /// The number of times a PTO has been sent without receiving an ack.
     pto_count: u32,
     /// The time at which the next packet will be considered lost based on early transmit or
@@ -118,6 +180,9 @@ pub struct Connection {
     time_of_last_sent_ack_eliciting_packet: Instant,
     /// The time the most recently sent handshake packet was sent.
     time_of_last_sent_crypto_packet: Instant,
+    /// The time a handshake packet was most recently received from the server, used to hold off
+    /// the client's anti-deadlock probe while the handshake is making progress.
+    time_of_last_handshake_progress: Instant,
     rtt: RttEstimator,
 
     //
@@ -125,18 +190,12 @@ pub struct Connection {
     //
     /// Summary statistics of packets that have been sent, but not yet acked or deemed lost
     in_flight: InFlight,
-    /// Maximum number of bytes in flight that may be sent.
-    congestion_window: u64,
-    /// The time when QUIC first detects a loss, causing it to enter recovery. When a packet sent
-    /// after this time is acknowledged, QUIC exits recovery.
-    recovery_start_time: Instant,
-    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
-    /// slow start and the window grows by the number of bytes acknowledged.
-    ssthresh: u64,
+    /// Determines the maximum number of bytes in flight that may be sent
+    congestion: Box<dyn CongestionController>,
     /// Explicit congestion notification (ECN) counters
     ecn_counters: frame::EcnCounts,
-    /// Whether we're enabling ECN on outgoing packets
-    sending_ecn: bool,
+    /// Progress of ECN validation, which determines whether outgoing packets are marked
+    ecn_state: EcnState,
     /// Whether the most recently received packet had an ECN codepoint set
     receiving_ecn: bool,
     remote_validated: bool,
@@ -171,7 +230,7 @@ impl Connection {
 
         let initial_space = PacketSpace {
             crypto: Some(CryptoSpace::new(Crypto::new_initial(&init_cid, side))),
-            ..PacketSpace::new()
+            ..PacketSpace::new(config.dedup_window)
         };
         let mut streams = FnvHashMap::default();
         for i in 0..config.stream_window_uni {
@@ -186,6 +245,7 @@ impl Connection {
                 Stream::new_bi(),
             );
         }
+        let params = TransportParameters::new(&config);
         let mut loc_cids = HashMap::new();
         loc_cids.insert(0, loc_cid);
         let state = State::Handshake(state::Handshake {
@@ -206,33 +266,63 @@ impl Connection {
             rem_cid_seq: 0,
             remote,
             prev_remote: None,
+            path_loc_cid: loc_cid,
             side,
             state,
-            mtu: MIN_MTU,
+            mtu: config.initial_mtu,
             zero_rtt_crypto: None,
             key_phase: false,
-            params: TransportParameters::new(&config),
+            key_updated: false,
+            max_data_sent: params.initial_max_data,
+            local_ip: None,
+            params,
             blocked_streams: FnvHashSet::default(),
             max_data: 0,
             data_sent: 0,
             data_recvd: 0,
             local_max_data: config.receive_window as u64,
+            data_consumed: 0,
             unacked_data: 0,
+            send_above_low_water: false,
+            quiescent: false,
             client_config,
             orig_rem_cid: None,
+            packets_sent: 0,
+            packets_received: 0,
+            reordered_packets_received: 0,
             lost_packets: 0,
+            ack_only_packets: 0,
+            max_reordering: 0,
+            reordered_packets_acked: 0,
+            ack_eliciting_packets: 0,
             io: IoQueue::new(),
             events: VecDeque::new(),
             cids_issued: 0,
             spin: false,
-            spaces: [initial_space, PacketSpace::new(), PacketSpace::new()],
+            rx_spin: false,
+            rx_spin_edge: None,
+            spin_rtt: None,
+            spaces: [
+                initial_space,
+                PacketSpace::new(config.dedup_window),
+                PacketSpace::new(config.dedup_window),
+            ],
             highest_space: SpaceId::Initial,
             prev_crypto: None,
             path_challenge: None,
-            stream_opened: false,
+            path_probe: None,
+            opened_streams: VecDeque::new(),
+            accepting_streams: true,
             accepted_0rtt: false,
+            rejected_0rtt: false,
+            rejected_0rtt_streams: (0, 0),
             permit_idle_reset: true,
             idle_timeout: config.idle_timeout,
+            keep_alive_interval: match config.keep_alive_interval {
+                0 => None,
+                x => Some(Duration::from_secs(u64::from(x))),
+            },
+            draining_period: None,
 
             path_challenge_pending: false,
             ping_pending: false,
@@ -240,18 +330,21 @@ impl Connection {
             offpath_responses: Vec::new(),
 
             crypto_count: 0,
+            crypto_retransmitted: 0,
             pto_count: 0,
             loss_time: None,
             time_of_last_sent_ack_eliciting_packet: Instant::now(),
             time_of_last_sent_crypto_packet: Instant::now(),
+            time_of_last_handshake_progress: Instant::now(),
             rtt: RttEstimator::new(),
 
             in_flight: InFlight::new(),
-            congestion_window: config.initial_window,
-            recovery_start_time: Instant::now(),
-            ssthresh: u64::max_value(),
+            congestion: match config.congestion_controller {
+                Some(ref build) => build(&config),
+                None => Box::new(NewReno::new(&config)),
+            },
             ecn_counters: frame::EcnCounts::ZERO,
-            sending_ecn: true,
+            ecn_state: EcnState::Testing,
             receiving_ecn: false,
             remote_validated,
             total_recvd: 0,
@@ -281,6 +374,154 @@ impl Connection {
         this
     }
 
+    /// Reconstruct a server-side connection captured by `export_state`
+    pub(crate) fn import(
+        log: Logger,
+        endpoint_config: Arc<EndpointConfig>,
+        config: Arc<TransportConfig>,
+        state: ConnectionState,
+        crypto: Crypto,
+        tls: TlsSession,
+        now: Instant,
+    ) -> Self {
+        let loc_cid = state.loc_cids[0].1;
+        let mut this = Self::new(
+            log,
+            endpoint_config,
+            config,
+            loc_cid,
+            loc_cid,
+            state.rem_cid,
+            state.remote,
+            None,
+            tls,
+            true,
+        );
+        this.loc_cids = state.loc_cids.into_iter().collect();
+        this.cids_issued = state.cids_issued;
+        this.rem_cid_seq = state.rem_cid_seq;
+        this.state = State::Established;
+        this.highest_space = SpaceId::Data;
+        this.spaces[SpaceId::Initial as usize].crypto = None;
+        {
+            let space = &mut this.spaces[SpaceId::Data as usize];
+            space.crypto = Some(CryptoSpace::new(crypto));
+            space.next_packet_number = state.next_packet_number;
+            space.largest_acked_packet = state.largest_acked_packet;
+            space.rx_packet = state.rx_packet;
+            // Packets received by the previous process must not be processed again
+            space.dedup.fill_to(state.rx_packet);
+        }
+        this.max_data = state.max_data;
+        this.data_sent = state.data_sent;
+        this.data_recvd = state.data_recvd;
+        this.local_max_data = state.local_max_data;
+        this.max_data_sent = state.local_max_data;
+        // Exported connections are idle, so everything received has been read
+        this.data_consumed = state.data_recvd;
+
+        // Every stream was closed on export, leaving only those the peer may yet open
+        let side = this.side;
+        this.streams.streams.clear();
+        for i in state.next_remote_uni..state.max_remote_uni {
+            this.streams.streams.insert(
+                StreamId::new(!side, Directionality::Uni, i),
+                stream::Recv::new().into(),
+            );
+        }
+        for i in state.next_remote_bi..state.max_remote_bi {
+            let mut stream = Stream::new_bi();
+            stream.send_mut().unwrap().max_data = state.params.initial_max_stream_data_bidi_local;
+            this.streams
+                .streams
+                .insert(StreamId::new(!side, Directionality::Bi, i), stream);
+        }
+        this.streams.next_uni = state.next_uni;
+        this.streams.next_bi = state.next_bi;
+        this.streams.max_uni = state.max_uni;
+        this.streams.max_bi = state.max_bi;
+        this.streams.max_remote_uni = state.max_remote_uni;
+        this.streams.max_remote_bi = state.max_remote_bi;
+        this.streams.next_remote_uni = state.next_remote_uni;
+        this.streams.next_remote_bi = state.next_remote_bi;
+        this.streams.next_reported_remote_uni = state.next_remote_uni;
+        this.streams.next_reported_remote_bi = state.next_remote_bi;
+
+        this.params = state.params;
+        this.idle_timeout = state.idle_timeout;
+        this.set_mtu(state.mtu);
+        this.reset_idle_timeout(now);
+        this.reset_keep_alive(now);
+        this
+    }
+
+    /// Capture the state needed to resume this connection in another process
+    ///
+    /// Only established server-side connections which have never updated their keys can be
+    /// exported, and only while idle: every stream must be closed, and nothing may be in flight or
+    /// awaiting transmission. See `ConnectionState` for the security implications.
+    pub fn export_state(&self) -> Result<ConnectionState, ExportError> {
+        match self.state {
+            State::Established if self.side.is_server() && !self.key_updated => {}
+            _ => return Err(ExportError::Unsupported),
+        }
+        let streams = &self.streams;
+        let streams_open = streams.streams.keys().any(|id| {
+            let next_remote = match id.directionality() {
+                Directionality::Uni => streams.next_remote_uni,
+                Directionality::Bi => streams.next_remote_bi,
+            };
+            id.initiator() == self.side || id.index() < next_remote
+        });
+        if streams_open
+            || self.unacked_data != 0
+            || self.in_flight.ack_eliciting != 0
+            || self.prev_remote.is_some()
+            || !self.opened_streams.is_empty()
+            || self.spaces.iter().any(|space| !space.pending.is_empty())
+        {
+            return Err(ExportError::Busy);
+        }
+
+        let space = &self.spaces[SpaceId::Data as usize];
+        let crypto = &space.crypto.as_ref().unwrap().packet;
+        let (local_secret, remote_secret) = crypto.secrets();
+        let mut loc_cids = self
+            .loc_cids
+            .iter()
+            .map(|(&sequence, &cid)| (sequence, cid))
+            .collect::<Vec<_>>();
+        loc_cids.sort_unstable_by_key(|&(sequence, _)| sequence);
+        Ok(ConnectionState {
+            remote: self.remote,
+            loc_cids,
+            cids_issued: self.cids_issued,
+            rem_cid: self.rem_cid,
+            rem_cid_seq: self.rem_cid_seq,
+            suite: crypto.suite(),
+            local_secret: local_secret.into(),
+            remote_secret: remote_secret.into(),
+            next_packet_number: space.next_packet_number,
+            largest_acked_packet: space.largest_acked_packet,
+            rx_packet: space.rx_packet,
+            params: self.params,
+            max_data: self.max_data,
+            data_sent: self.data_sent,
+            data_recvd: self.data_recvd,
+            local_max_data: self.max_data(),
+            next_uni: streams.next_uni,
+            next_bi: streams.next_bi,
+            max_uni: streams.max_uni,
+            max_bi: streams.max_bi,
+            max_remote_uni: streams.max_remote_uni,
+            max_remote_bi: streams.max_remote_bi,
+            next_remote_uni: streams.next_remote_uni,
+            next_remote_bi: streams.next_remote_bi,
+            idle_timeout: self.idle_timeout,
+            mtu: self.mtu,
+        })
+    }
+
     /// Returns I/O actions to execute immediately
     ///
     /// Connections should be polled for I/O after:
@@ -308,8 +549,8 @@ impl Connection {
     /// - an incoming packet is handled, or
     /// - the idle timer expires
     pub fn poll(&mut self) -> Option<Event> {
-        if mem::replace(&mut self.stream_opened, false) {
-            return Some(Event::StreamOpened);
+        if let Some(stream) = self.opened_streams.pop_front() {
+            return Some(Event::StreamOpened { stream });
         }
 
         if let Some(x) = self.events.pop_front() {
@@ -319,6 +560,16 @@ impl Connection {
         None
     }
 
+    /// Returns all pending application-facing events at once
+    ///
+    /// Equivalent to calling `poll` until it returns `None`.
+    pub fn poll_events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.opened_streams
+            .drain(..)
+            .map(|stream| Event::StreamOpened { stream })
+            .chain(self.events.drain(..))
+    }
+
     fn on_packet_sent(
         &mut self,
         now: Instant,
@@ -333,6 +584,12 @@ impl Connection {
             ..
         } = packet;
 
+        self.packets_sent += 1;
+        if ack_eliciting {
+            self.ack_eliciting_packets += 1;
+        } else {
+            self.ack_only_packets += 1;
+        }
         self.in_flight.insert(&packet);
         self.space_mut(space)
             .sent_packets
@@ -362,17 +619,26 @@ impl Connection {
         }
     }
 
-    fn on_ack_received(&mut self, now: Instant, space: SpaceId, ack: frame::Ack) {
+    fn on_ack_received(
+        &mut self,
+        now: Instant,
+        space: SpaceId,
+        ack: frame::Ack,
+    ) -> Result<(), TransportError> {
         trace!(self.log, "handling ack"; "ranges" => ?ack.iter().collect::<Vec<_>>());
+        if ack.largest >= self.space(space).next_packet_number {
+            debug!(self.log, "peer acknowledged unsent packet {number}", number = ack.largest);
+            return Err(TransportError::PROTOCOL_VIOLATION("unsent packet acked"));
+        }
         let was_blocked = self.blocked();
+        let prev_largest = self.space(space).largest_acked_packet;
         let new_largest = {
             let space = self.space_mut(space);
             if ack.largest > space.largest_acked_packet {
                 space.largest_acked_packet = ack.largest;
                 if let Some(info) = space.sent_packets.get(&ack.largest) {
-                    // This should always succeed, but a misbehaving peer might ACK a packet we
-                    // haven't sent. At worst, that will result in us spuriously reducing the
-                    // congestion window.
+                    // The packet may have been declared lost already, or discarded along with
+                    // rejected 0-RTT data
                     space.largest_acked_packet_sent = info.time_sent;
                 }
                 true
@@ -383,9 +649,12 @@ impl Connection {
 
         if let Some(info) = self.space(space).sent_packets.get(&ack.largest) {
             if info.ack_eliciting {
-                let delay = Duration::from_micros(ack.delay << self.params.ack_delay_exponent);
+                let exponent = ack_delay_exponent(space, self.params.ack_delay_exponent);
+                let delay = Duration::from_micros(ack.delay << exponent);
                 self.rtt
                     .update(cmp::min(delay, self.max_ack_delay()), now - info.time_sent);
+                let latest = self.rtt.latest;
+                self.space_mut(space).latest_rtt = Some(latest);
             }
         }
 
@@ -394,11 +663,12 @@ impl Connection {
             .iter()
             .flat_map(|range| self.space(space).sent_packets.range(range).map(|(&n, _)| n))
             .collect::<Vec<_>>();
+        self.record_reordering(space, prev_largest, &ack, &newly_acked);
         if newly_acked.is_empty() {
-            return;
+            return Ok(());
         }
         for &packet in &newly_acked {
-            self.on_packet_acked(space, packet);
+            self.on_packet_acked(now, space, packet);
         }
 
         if space == SpaceId::Handshake
@@ -418,7 +688,7 @@ impl Connection {
         self.pto_count = 0;
 
         // Explicit congestion notification
-        if self.sending_ecn {
+        if self.ecn_state != EcnState::Failed {
             if let Some(ecn) = ack.ecn {
                 // We only examine ECN counters from ACKs that we are certain we received in transmit
                 // order, allowing us to compute an increase in ECN counts to compare against the number
@@ -431,7 +701,7 @@ impl Connection {
             } else {
                 // We always start out sending ECN, so any ack that doesn't acknowledge it disables it.
                 debug!(self.log, "ECN not acknowledged by peer");
-                self.sending_ecn = false;
+                self.ecn_state = EcnState::Failed;
             }
         }
 
@@ -441,6 +711,57 @@ impl Connection {
                 self.events.push_back(Event::StreamWritable { stream });
             }
         }
+        self.check_send_low_water();
+        self.check_quiescent();
+        Ok(())
+    }
+
+    /// Notify the application if unacknowledged stream data fell below the low-water mark
+    fn check_send_low_water(&mut self) {
+        if self.send_above_low_water && self.unacked_data < self.config.send_low_water_mark {
+            self.send_above_low_water = false;
+            self.events.push_back(Event::SendBufferLow);
+        }
+    }
+
+    /// Notify the application if the connection just became quiescent
+    fn check_quiescent(&mut self) {
+        if !self.config.idle_events {
+            return;
+        }
+        let quiescent = match self.state {
+            State::Established => {
+                self.in_flight.bytes == 0
+                    && self.unacked_data == 0
+                    && !self.can_send_1rtt()
+                    && self.spaces.iter().all(|x| !x.can_send())
+            }
+            _ => false,
+        };
+        if quiescent && !self.quiescent {
+            self.events.push_back(Event::Idle);
+        }
+        self.quiescent = quiescent;
+    }
+
+    /// Handle frames as if they had arrived from the peer in a 1-RTT packet
+    #[cfg(test)]
+    pub(crate) fn inject_payload(
+        &mut self,
+        now: Instant,
+        payload: Bytes,
+    ) -> Result<(), TransportError> {
+        let number = self.space(SpaceId::Data).rx_packet + 1;
+        let remote = self.remote;
+        let dst_cid = self.path_loc_cid;
+        self.process_payload(now, remote, dst_cid, number, payload)
+    }
+
+    /// Switch to a spare remote CID, as a peer migrating deliberately would
+    #[cfg(test)]
+    pub(crate) fn switch_rem_cid(&mut self) {
+        let cid = self.take_rem_cid().expect("no spare remote CIDs");
+        self.update_rem_cid(cid);
     }
 
     /// Process a new ECN block from an in-order ACK
@@ -452,25 +773,31 @@ impl Connection {
         ecn: frame::EcnCounts,
         largest_sent_time: Instant,
     ) {
-        match self.space_mut(space).detect_ecn(newly_acked, ecn) {
+        let codepoint = self.config.ecn_codepoint;
+        match self.space_mut(space).detect_ecn(newly_acked, ecn, codepoint) {
             Err(e) => {
                 debug!(
                     self.log,
                     "halting ECN due to verification failure: {error}",
                     error = e
                 );
-                self.sending_ecn = false;
+                self.ecn_state = EcnState::Failed;
+                return;
             }
             Ok(false) => {}
             Ok(true) => {
-                self.congestion_event(now, largest_sent_time);
+                self.congestion_notify(now, |cc, cx| cc.on_congestion_event(cx, largest_sent_time));
             }
         }
+        if self.ecn_state == EcnState::Testing && newly_acked != 0 {
+            trace!(self.log, "ECN validated");
+            self.ecn_state = EcnState::Validated;
+        }
     }
 
     // Not timing-aware, so it's safe to call this for inferred acks, such as arise from
     // high-latency handshakes
-    fn on_packet_acked(&mut self, space: SpaceId, packet: u64) {
+    fn on_packet_acked(&mut self, now: Instant, space: SpaceId, packet: u64) {
         let info = if let Some(x) = self.space_mut(space).sent_packets.remove(&packet) {
             x
         } else {
@@ -478,18 +805,8 @@ impl Connection {
         };
         self.in_flight.remove(&info);
         if info.ack_eliciting {
-            // Congestion control
-            // Do not increase congestion window in recovery period.
-            if !self.in_recovery(info.time_sent) {
-                if self.congestion_window < self.ssthresh {
-                    // Slow start.
-                    self.congestion_window += info.size as u64;
-                } else {
-                    // Congestion avoidance.
-                    self.congestion_window +=
-                        self.config.max_datagram_size * info.size as u64 / self.congestion_window;
-                }
-            }
+            let (sent, bytes, app_limited) = (info.time_sent, info.size as u64, info.app_limited);
+            self.congestion_notify(now, |cc, cx| cc.on_ack(cx, sent, bytes, app_limited));
         }
 
         // Update state for confirmed delivery of frames
@@ -504,7 +821,7 @@ impl Connection {
                 }
             }
         }
-        for frame in info.retransmits.stream {
+        for frame in info.retransmits.stream.into_frames() {
             let ss = if let Some(x) = self.streams.get_send_mut(frame.id) {
                 x
             } else {
@@ -523,6 +840,7 @@ impl Connection {
     }
 
     pub fn timeout(&mut self, now: Instant, timer: Timer) -> bool {
+        self.io.deadlines[timer as usize] = None;
         match timer {
             Timer::Close => {
                 self.state = State::Drained;
@@ -536,11 +854,35 @@ impl Connection {
                 return self.app_closed;
             }
             Timer::KeepAlive => {
-                trace!(self.log, "sending keep-alive");
-                self.ping();
+                if self.config.opportunistic_keep_alive
+                    && !self.space(SpaceId::Data).pending.is_empty()
+                {
+                    // Pending data will serve as the keep-alive; check again if it stays queued
+                    trace!(self.log, "keep-alive deferred to pending data");
+                    self.reset_keep_alive(now);
+                } else {
+                    trace!(self.log, "sending keep-alive");
+                    self.ping();
+                }
+            }
+            Timer::AckDelay => {
+                trace!(self.log, "sending delayed ack");
+                self.space_mut(SpaceId::Data).permit_ack_only = true;
             }
             Timer::LossDetection => {
                 self.on_loss_detection_timeout(now);
+                let count_exceeded = self.config.max_crypto_retransmits != 0
+                    && self.crypto_count > self.config.max_crypto_retransmits;
+                let bytes_exceeded = self.config.max_crypto_retransmit_bytes != 0
+                    && self.crypto_retransmitted > self.config.max_crypto_retransmit_bytes;
+                if count_exceeded || (bytes_exceeded && self.state.is_handshake()) {
+                    debug!(self.log, "handshake retransmission limit exceeded");
+                    self.close_common(now);
+                    self.io.timer_stop(Timer::Close);
+                    self.events.push_back(ConnectionError::TimedOut.into());
+                    self.state = State::Drained;
+                    return self.app_closed;
+                }
             }
             Timer::KeyDiscard => {
                 if self.spaces[SpaceId::Handshake as usize].crypto.is_some() {
@@ -558,6 +900,15 @@ impl Connection {
                     }
                 }
             }
+            Timer::PathProbe => {
+                if let Some(probe) = self.path_probe.take() {
+                    debug!(self.log, "path probe to {remote} timed out", remote = probe.remote);
+                    self.events.push_back(Event::PathProbed {
+                        remote: probe.remote,
+                        validated: false,
+                    });
+                }
+            }
             Timer::PathValidation => {
                 debug!(self.log, "path validation failed");
                 self.path_challenge = None;
@@ -594,14 +945,20 @@ impl Connection {
                 self.lost_packets += sent_packets.len() as u64;
                 for (_, packet) in sent_packets {
                     self.in_flight.remove(&packet);
+                    self.crypto_retransmitted += packet.retransmits.crypto_bytes();
                     self.space_mut(space_id).pending += packet.retransmits;
                 }
             }
             self.crypto_count = self.crypto_count.saturating_add(1);
         } else if self.state.is_handshake() && self.side.is_client() {
-            trace!(self.log, "sending anti-deadlock handshake packet");
-            self.io.probes += 1;
-            self.crypto_count = self.crypto_count.saturating_add(1);
+            if now < self.time_of_last_handshake_progress + self.crypto_timeout() {
+                // The server is still delivering its flight; no risk of deadlock yet
+                trace!(self.log, "deferring anti-deadlock handshake packet");
+            } else {
+                trace!(self.log, "sending anti-deadlock handshake packet");
+                self.io.probes += 1;
+                self.crypto_count = self.crypto_count.saturating_add(1);
+            }
         } else if self.loss_time.is_some() {
             // Time threshold loss Detection
             self.detect_lost_packets(now);
@@ -613,6 +970,42 @@ impl Connection {
         self.set_loss_detection_timer();
     }
 
+    /// Account for packets acknowledged only after a later packet was
+    ///
+    /// Packets that were already deemed lost count too, since those are the ones a larger
+    /// `packet_threshold` would have saved from spurious retransmission.
+    fn record_reordering(
+        &mut self,
+        space: SpaceId,
+        prev_largest: u64,
+        ack: &frame::Ack,
+        newly_acked: &[u64],
+    ) {
+        let mut reordering = 0;
+        for &packet in newly_acked.iter().filter(|&&n| n < prev_largest) {
+            reordering = cmp::max(reordering, prev_largest - packet);
+            self.reordered_packets_acked += 1;
+        }
+        let declared_lost = &mut self.spaces[space as usize].declared_lost;
+        if !declared_lost.is_empty() {
+            for acked in ack.iter() {
+                for lost in declared_lost.iter() {
+                    if lost.start < acked.end && acked.start < lost.end {
+                        let first = cmp::max(lost.start, acked.start);
+                        let end = cmp::min(lost.end, acked.end);
+                        reordering = cmp::max(reordering, prev_largest.saturating_sub(first));
+                        self.reordered_packets_acked += end - first;
+                    }
+                }
+                declared_lost.remove(acked);
+            }
+        }
+        if reordering > self.max_reordering {
+            trace!(self.log, "reordering by {distance} packets", distance = reordering);
+            self.max_reordering = reordering;
+        }
+    }
+
     fn detect_lost_packets(&mut self, now: Instant) {
         self.loss_time = None;
         let mut lost_packets = Vec::<u64>::new();
@@ -623,7 +1016,7 @@ impl Connection {
         let loss_delay = rtt + ((rtt * self.config.time_threshold as u32) / 65536);
         let lost_send_time = now - loss_delay;
 
-        let mut lost_ack_eliciting = false;
+        let mut lost_bytes = 0;
         let mut largest_lost_time = None;
         let mut in_persistent_congestion = false;
         let persistent_congestion_period =
@@ -645,22 +1038,41 @@ impl Connection {
             }
 
             // OnPacketsLost
-            if let Some(largest_lost) = lost_packets.last().cloned() {
+            if !lost_packets.is_empty() {
                 let old_bytes_in_flight = self.in_flight.bytes;
-                let largest_lost_sent = space.sent_packets[&largest_lost].time_sent;
+                let mut largest_lost_sent = None;
+                let in_flight = &mut self.in_flight;
+                let crypto_retransmitted = &mut self.crypto_retransmitted;
+                // Packets may already have been removed by an earlier ack; tolerate that rather
+                // than trusting the snapshot taken above.
+                lost_packets.retain(|packet| match space.sent_packets.remove(packet) {
+                    Some(info) => {
+                        largest_lost_sent = Some(info.time_sent);
+                        in_flight.remove(&info);
+                        *crypto_retransmitted += info.retransmits.crypto_bytes();
+                        space.pending += info.retransmits;
+                        true
+                    }
+                    None => false,
+                });
+                let largest_lost_sent = match largest_lost_sent {
+                    Some(x) => x,
+                    None => continue,
+                };
                 largest_lost_time =
                     Some(largest_lost_time.map_or(largest_lost_sent, |lost_time| {
                         cmp::max(lost_time, largest_lost_sent)
                     }));
                 self.lost_packets += lost_packets.len() as u64;
                 trace!(self.log, "packets lost: {:?}", lost_packets);
-                for packet in &lost_packets {
-                    let info = space.sent_packets.remove(&packet).unwrap();
-                    self.in_flight.remove(&info);
-                    space.pending += info.retransmits;
+                for &packet in &lost_packets {
+                    space.declared_lost.insert_one(packet);
+                }
+                while space.declared_lost.len() > MAX_DECLARED_LOST_RANGES {
+                    space.declared_lost.pop_min();
                 }
                 // Don't apply congestion penalty for lost ack-only packets
-                lost_ack_eliciting |= old_bytes_in_flight != self.in_flight.bytes;
+                lost_bytes += old_bytes_in_flight - self.in_flight.bytes;
 
                 // InPersistentCongestion: Determine if all packets in the window before the newest
                 // lost packet, including the edges, are marked lost
@@ -668,46 +1080,62 @@ impl Connection {
                     < largest_lost_time.unwrap() - persistent_congestion_period;
             }
         }
-        if lost_ack_eliciting {
-            self.congestion_event(now, largest_lost_time.unwrap());
+        if lost_bytes != 0 {
+            let largest_lost_time = largest_lost_time.unwrap();
+            self.congestion_notify(now, |cc, cx| {
+                cc.on_packets_lost(cx, largest_lost_time, lost_bytes)
+            });
             if in_persistent_congestion {
-                self.congestion_window = self.config.minimum_window;
+                self.congestion_notify(now, |cc, cx| cc.on_persistent_congestion(cx));
             }
         }
     }
 
-    fn congestion_event(&mut self, now: Instant, sent_time: Instant) {
-        // Start a new recovery epoch if the lost packet is larger than the end of the
-        // previous recovery epoch.
-        if self.in_recovery(sent_time) {
-            return;
+    /// Pass an event to the congestion controller, notifying the application if the window
+    /// collapses to the minimum as a result
+    fn congestion_notify<F>(&mut self, now: Instant, f: F)
+    where
+        F: FnOnce(&mut dyn CongestionController, &congestion::Context),
+    {
+        let old = self.congestion.window();
+        let rtt = self.rtt_info();
+        let cx = congestion::Context {
+            now,
+            bytes_in_flight: self.in_flight.bytes,
+            rtt,
+            config: &self.config,
+        };
+        f(&mut *self.congestion, &cx);
+        let new = self.congestion.window();
+        if new == self.config.minimum_window && old > new {
+            self.events
+                .push_back(Event::CongestionWindowReduced { old, new });
         }
-        self.recovery_start_time = now;
-        // *= factor
-        self.congestion_window =
-            (self.congestion_window * self.config.loss_reduction_factor as u64) >> 16;
-        self.congestion_window = cmp::max(self.congestion_window, self.config.minimum_window);
-        self.ssthresh = self.congestion_window;
     }
 
-    fn in_recovery(&self, sent_time: Instant) -> bool {
-        sent_time <= self.recovery_start_time
+    fn crypto_timeout(&self) -> Duration {
+        let timeout = if let Some(smoothed) = self.rtt.smoothed {
+            2 * smoothed
+        } else {
+            2 * Duration::from_micros(self.config.initial_rtt)
+        };
+        cmp::max(timeout, TIMER_GRANULARITY)
+            * 2u32.pow(cmp::min(self.crypto_count, MAX_BACKOFF_EXPONENT))
     }
 
     fn set_loss_detection_timer(&mut self) {
         if self.in_flight.crypto != 0 || (self.state.is_handshake() && self.side.is_client()) {
             // Handshake retransmission alarm.
-            let timeout = if let Some(smoothed) = self.rtt.smoothed {
-                2 * smoothed
+            let base = if self.in_flight.crypto != 0 {
+                self.time_of_last_sent_crypto_packet
             } else {
-                2 * Duration::from_micros(self.config.initial_rtt)
+                // Anti-deadlock alarm: only probe once the server has gone quiet
+                cmp::max(
+                    self.time_of_last_sent_crypto_packet,
+                    self.time_of_last_handshake_progress,
+                )
             };
-            let timeout = cmp::max(timeout, TIMER_GRANULARITY)
-                * 2u32.pow(cmp::min(self.crypto_count, MAX_BACKOFF_EXPONENT));
-            self.io.timer_start(
-                Timer::LossDetection,
-                self.time_of_last_sent_crypto_packet + timeout,
-            );
+            self.io.timer_start(Timer::LossDetection, base + self.crypto_timeout());
             return;
         }
 
@@ -746,9 +1174,12 @@ impl Connection {
         space_id: SpaceId,
         ecn: Option<EcnCodepoint>,
         packet: Option<u64>,
-        spin: bool,
+        spin: Option<bool>,
     ) {
         self.remote_validated |= self.state.is_handshake() && space_id == SpaceId::Handshake;
+        if self.state.is_handshake() && self.side.is_client() {
+            self.time_of_last_handshake_progress = now;
+        }
         self.reset_keep_alive(now);
         self.reset_idle_timeout(now);
         self.permit_idle_reset = true;
@@ -762,6 +1193,7 @@ impl Connection {
         } else {
             return;
         };
+        self.packets_received += 1;
         trace!(
             self.log,
             "{space:?} packet {packet} authenticated",
@@ -780,12 +1212,35 @@ impl Connection {
         if space.pending_acks.len() > MAX_ACK_BLOCKS {
             space.pending_acks.pop_min();
         }
+        // Reordering and loss should be reported to the peer promptly
+        space.rx_reordered = packet < space.rx_packet || packet > space.rx_packet + 1;
+        if packet < space.rx_packet {
+            self.reordered_packets_received += 1;
+        }
         if packet >= space.rx_packet {
             space.rx_packet = packet;
             space.rx_packet_time = now;
             // Update outgoing spin bit, inverting iff we're the client
-            self.spin = self.side.is_client() ^ spin;
+            self.spin = self.side.is_client() ^ spin.unwrap_or(false);
+            if let Some(spin) = spin {
+                self.on_spin(now, spin);
+            }
+        }
+    }
+
+    /// Sample the RTT from edges in the incoming spin bit
+    ///
+    /// Each endpoint flips its outgoing spin bit in response to an edge from the peer, so edges
+    /// arrive roughly one round trip apart, plus any time either side spent with nothing to send.
+    fn on_spin(&mut self, now: Instant, spin: bool) {
+        if spin == self.rx_spin {
+            return;
         }
+        self.rx_spin = spin;
+        if let Some(prev) = self.rx_spin_edge {
+            self.spin_rtt = Some(now - prev);
+        }
+        self.rx_spin_edge = Some(now);
     }
 
     fn reset_idle_timeout(&mut self, now: Instant) {
@@ -801,13 +1256,11 @@ impl Connection {
     }
 
     fn reset_keep_alive(&mut self, now: Instant) {
-        if self.config.keep_alive_interval == 0 || self.state.is_closed() {
-            return;
-        }
-        self.io.timer_start(
-            Timer::KeepAlive,
-            now + Duration::new(self.config.keep_alive_interval as u64, 0),
-        );
+        let interval = match self.keep_alive_interval {
+            Some(x) if !self.state.is_closed() => x,
+            _ => return,
+        };
+        self.io.timer_start(Timer::KeepAlive, now + interval);
     }
 
     fn queue_stream_data(&mut self, stream: StreamId, data: Bytes) {
@@ -818,6 +1271,10 @@ impl Connection {
         ss.bytes_in_flight += data.len() as u64;
         self.data_sent += data.len() as u64;
         self.unacked_data += data.len() as u64;
+        let low_water = self.config.send_low_water_mark;
+        if low_water != 0 && self.unacked_data >= low_water {
+            self.send_above_low_water = true;
+        }
         self.space_mut(SpaceId::Data)
             .pending
             .stream
@@ -873,7 +1330,7 @@ impl Connection {
         let len = packet.header_data.len() + packet.payload.len();
         self.total_recvd = len as u64;
 
-        self.on_packet_authenticated(now, SpaceId::Initial, ecn, Some(packet_number), false);
+        self.on_packet_authenticated(now, SpaceId::Initial, ecn, Some(packet_number), None);
         self.process_early_payload(now, packet)?;
         if self.state.is_closed() {
             return Ok(());
@@ -925,18 +1382,26 @@ impl Connection {
         } else {
             SpaceId::Handshake
         };
-        if space < expected
-            && crypto.offset + crypto.data.len() as u64 > self.space(space).crypto_stream.offset()
-        {
+        let end = crypto.offset + crypto.data.len() as u64;
+        if space < expected && end > self.space(space).crypto_stream.offset() {
             warn!(
                 self.log,
                 "received new {actual:?} CRYPTO data when expecting {expected:?}",
                 actual = space,
                 expected = expected
             );
-            return Err(TransportError::PROTOCOL_VIOLATION(
-                "new data at unexpected encryption level",
-            ));
+            if !self.config.lenient_crypto_levels
+                || end - self.space(space).crypto_stream.offset() > MAX_STALE_CRYPTO
+            {
+                return Err(TransportError::PROTOCOL_VIOLATION(
+                    "new data at unexpected encryption level",
+                ));
+            }
+            // The TLS session has moved on, so the data can only be buffered, never consumed
+            self.space_mut(space)
+                .crypto_stream
+                .insert(crypto.offset, &crypto.data);
+            return Ok(());
         }
 
         let space = &mut self.spaces[space as usize];
@@ -1018,7 +1483,10 @@ impl Connection {
         first_decode: PartialDecode,
         remaining: Option<BytesMut>,
     ) {
-        if remote != self.remote && self.side.is_client() {
+        if remote != self.remote
+            && self.side.is_client()
+            && self.path_probe.map_or(true, |x| x.remote != remote)
+        {
             trace!(
                 self.log,
                 "discarding packet from unknown server {address}",
@@ -1113,6 +1581,7 @@ impl Connection {
             connection = packet.header.dst_cid(),
         );
         let was_closed = self.state.is_closed();
+        let space = packet.header.space();
 
         let stateless_reset = self.params.stateless_reset_token.map_or(false, |token| {
             packet.payload.len() >= RESET_TOKEN_SIZE
@@ -1155,9 +1624,9 @@ impl Connection {
                 } else {
                     if !self.state.is_closed() {
                         let spin = if let Header::Short { spin, .. } = packet.header {
-                            spin
+                            Some(spin)
                         } else {
-                            false
+                            None
                         };
                         self.on_packet_authenticated(now, packet.header.space(), ecn, number, spin);
                     }
@@ -1167,7 +1636,10 @@ impl Connection {
         };
 
         // State transitions for error cases
-        if let Err(conn_err) = result {
+        if let Err(mut conn_err) = result {
+            if let ConnectionError::TransportError(ref mut err) = conn_err {
+                err.space = err.space.or(Some(space));
+            }
             self.events.push_back(conn_err.clone().into());
             self.state = match conn_err {
                 ConnectionError::ApplicationClosed { reason } => State::closed(reason),
@@ -1232,7 +1704,7 @@ impl Connection {
                         self.orig_rem_cid = Some(self.rem_cid);
                         self.rem_cid = rem_cid;
                         self.rem_handshake_cid = rem_cid;
-                        self.on_packet_acked(SpaceId::Initial, 0);
+                        self.on_packet_acked(now, SpaceId::Initial, 0);
 
                         // Reset to initial state
                         let client_config = self.client_config.as_ref().unwrap();
@@ -1248,7 +1720,7 @@ impl Connection {
                             crypto: Some(CryptoSpace::new(Crypto::new_initial(
                                 &rem_cid, self.side,
                             ))),
-                            ..PacketSpace::new()
+                            ..PacketSpace::new(self.config.dedup_window)
                         };
 
                         self.write_tls();
@@ -1312,13 +1784,16 @@ impl Connection {
                                         )
                                         .into());
                                     }
+                                    self.events.push_back(Event::ZeroRttAccepted);
                                 }
                             }
                             self.set_params(params)?;
                         }
                         self.events.push_back(Event::Connected);
                         self.state = State::Established;
-                        trace!(self.log, "established");
+                        trace!(self.log, "established";
+                               "local CID length" => self.loc_cid_len(),
+                               "remote CID length" => self.rem_cid_len());
                         Ok(())
                     }
                     Header::Initial {
@@ -1346,7 +1821,13 @@ impl Connection {
                         ty: LongType::ZeroRtt,
                         ..
                     } => {
-                        self.process_payload(now, remote, number.unwrap(), packet.payload.into())?;
+                        self.process_payload(
+                            now,
+                            remote,
+                            *packet.header.dst_cid(),
+                            number.unwrap(),
+                            packet.payload.into(),
+                        )?;
                         Ok(())
                     }
                     Header::VersionNegotiate { .. } => {
@@ -1377,7 +1858,13 @@ impl Connection {
             State::Established => {
                 match packet.header.space() {
                     SpaceId::Data => {
-                        self.process_payload(now, remote, number.unwrap(), packet.payload.into())?
+                        self.process_payload(
+                            now,
+                            remote,
+                            *packet.header.dst_cid(),
+                            number.unwrap(),
+                            packet.payload.into(),
+                        )?
                     }
                     _ => self.process_early_payload(now, packet)?,
                 }
@@ -1401,6 +1888,15 @@ impl Connection {
                     });
                     trace!(self.log, "draining");
                     self.state = State::Draining;
+                    // The peer is closing too, so it won't send anything further. Only packets it
+                    // sent beforehand remain to be absorbed, and those arrive within a PTO. A fixed
+                    // draining period is kept as is, so that teardown stays reproducible.
+                    let deadline = now + self.pto();
+                    if self.draining_period.is_none()
+                        && self.io.deadlines[Timer::Close as usize].map_or(false, |x| x > deadline)
+                    {
+                        self.io.timer_start(Timer::Close, deadline);
+                    }
                     return Ok(());
                 }
                 Ok(())
@@ -1414,9 +1910,23 @@ impl Connection {
         &mut self,
         now: Instant,
         packet: Packet,
+    ) -> Result<(), TransportError> {
+        let mut current_frame = None;
+        self.process_early_frames(now, packet, &mut current_frame)
+            .map_err(|e| e.in_frame(current_frame))
+    }
+
+    /// Process the frames of an Initial or Handshake packet, tracking the type of the frame being
+    /// processed in `current_frame`
+    fn process_early_frames(
+        &mut self,
+        now: Instant,
+        packet: Packet,
+        current_frame: &mut Option<frame::Type>,
     ) -> Result<(), TransportError> {
         debug_assert_ne!(packet.header.space(), SpaceId::Data);
         for frame in frame::Iter::new(packet.payload.into()) {
+            *current_frame = frame_type(&frame);
             match frame {
                 Frame::Padding => {}
                 _ => {
@@ -1435,7 +1945,7 @@ impl Connection {
                     self.read_tls(packet.header.space(), &frame)?;
                 }
                 Frame::Ack(ack) => {
-                    self.on_ack_received(now, packet.header.space(), ack);
+                    self.on_ack_received(now, packet.header.space(), ack)?;
                 }
                 Frame::ConnectionClose(reason) => {
                     trace!(
@@ -1454,6 +1964,9 @@ impl Connection {
                     self.state = State::Draining;
                     return Ok(());
                 }
+                Frame::Invalid { ty, offset, reason } => {
+                    return Err(invalid_frame(ty, offset, reason));
+                }
                 _ => {
                     return Err(TransportError::PROTOCOL_VIOLATION(
                         "illegal frame type in handshake",
@@ -1461,10 +1974,21 @@ impl Connection {
                 }
             }
         }
+        *current_frame = None;
         self.write_tls();
-        Ok(())
+        self.check_retransmit_buffer()
+    }
+
+    /// Number of additional local CIDs to issue to reach `EndpointConfig::local_cid_count`
+    ///
+    /// Retired CIDs no longer count, so a replacement is due each time the peer retires one.
+    pub(crate) fn cids_needed(&self) -> usize {
+        self.endpoint_config
+            .local_cid_count
+            .saturating_sub(self.loc_cids.len())
     }
 
+    /// Issue `cid` to the peer as a new local CID, with the next sequence number
     pub fn issue_cid(&mut self, cid: ConnectionId) {
         let token = reset_token_for(&self.endpoint_config.reset_key, &cid);
         self.cids_issued += 1;
@@ -1484,12 +2008,31 @@ impl Connection {
         &mut self,
         now: Instant,
         remote: SocketAddr,
+        dst_cid: ConnectionId,
+        number: u64,
+        payload: Bytes,
+    ) -> Result<(), TransportError> {
+        let mut current_frame = None;
+        self.process_frames(now, remote, dst_cid, number, payload, &mut current_frame)
+            .map_err(|e| e.in_frame(current_frame))
+    }
+
+    /// Process the frames of a 0-RTT or 1-RTT packet, tracking the type of the frame being
+    /// processed in `current_frame`
+    fn process_frames(
+        &mut self,
+        now: Instant,
+        remote: SocketAddr,
+        dst_cid: ConnectionId,
         number: u64,
         payload: Bytes,
+        current_frame: &mut Option<frame::Type>,
     ) -> Result<(), TransportError> {
         let is_0rtt = self.space(SpaceId::Data).crypto.is_none();
         let mut is_probing_packet = true;
+        let mut ack_eliciting = false;
         for frame in frame::Iter::new(payload) {
+            *current_frame = frame_type(&frame);
             match frame {
                 Frame::Padding => {}
                 _ => {
@@ -1509,7 +2052,10 @@ impl Connection {
             match frame {
                 Frame::Ack(_) | Frame::Padding => {}
                 _ => {
-                    self.space_mut(SpaceId::Data).permit_ack_only = true;
+                    if !ack_eliciting {
+                        ack_eliciting = true;
+                        self.on_ack_eliciting(now);
+                    }
                 }
             }
             match frame {
@@ -1522,8 +2068,8 @@ impl Connection {
                 }
             }
             match frame {
-                Frame::Invalid { reason, .. } => {
-                    return Err(TransportError::FRAME_ENCODING_ERROR(reason));
+                Frame::Invalid { ty, offset, reason } => {
+                    return Err(invalid_frame(ty, offset, reason));
                 }
                 Frame::Crypto(frame) => {
                     self.read_tls(SpaceId::Data, &frame)?;
@@ -1531,7 +2077,8 @@ impl Connection {
                 Frame::Stream(frame) => {
                     trace!(self.log, "got stream"; "id" => frame.id.0, "offset" => frame.offset, "len" => frame.data.len(), "fin" => frame.fin);
                     let data_recvd = self.data_recvd;
-                    let max_data = self.local_max_data;
+                    // Credit granted since the last MAX_DATA isn't known to the peer yet
+                    let max_data = self.max_data_sent;
                     match self.streams.get_recv_stream(self.side, frame.id) {
                         Err(e) => {
                             debug!(self.log, "received illegal stream frame"; "stream" => frame.id.0);
@@ -1545,12 +2092,9 @@ impl Connection {
                     }
                     let rs = self.streams.get_recv_mut(frame.id).unwrap();
                     let was_blocked = rs.is_blocked();
-                    if rs.is_finished() {
-                        trace!(self.log, "dropping frame for finished stream");
-                        continue;
-                    }
-
                     let end = frame.offset + frame.data.len() as u64;
+                    // Validated even for finished streams, so that data beyond the final offset
+                    // is never silently dropped
                     if let Some(final_offset) = rs.final_offset() {
                         if end > final_offset || (frame.fin && end != final_offset) {
                             debug!(self.log, "final offset error"; "frame end" => end, "final offset" => final_offset);
@@ -1558,8 +2102,20 @@ impl Connection {
                         }
                     }
                     let prev_end = rs.limit();
+                    if frame.fin && end < prev_end {
+                        debug!(self.log, "final offset error"; "frame end" => end, "received" => prev_end);
+                        return Err(TransportError::FINAL_OFFSET_ERROR(
+                            "final offset below received data",
+                        ));
+                    }
+                    if rs.is_finished() {
+                        trace!(self.log, "dropping frame for finished stream");
+                        continue;
+                    }
+
                     let new_bytes = end.saturating_sub(prev_end);
-                    let stream_max_data = rs.bytes_read + self.config.stream_receive_window;
+                    // Credit released since the last MAX_STREAM_DATA isn't known to the peer yet
+                    let stream_max_data = rs.released_sent + self.config.stream_receive_window;
                     if end > stream_max_data || data_recvd + new_bytes > max_data {
                         debug!(self.log, "flow control error";
                                    "stream" => frame.id.0, "recvd" => data_recvd, "new bytes" => new_bytes,
@@ -1572,6 +2128,7 @@ impl Connection {
                         }
                     }
                     rs.recvd.insert(frame.offset..end);
+                    let stopped = rs.stopped;
                     rs.buffer(frame.data, frame.offset);
                     if let stream::RecvState::Recv { size: Some(size) } = rs.state {
                         if rs.recvd.len() == 1 && rs.recvd.iter().next().unwrap() == (0..size) {
@@ -1581,9 +2138,14 @@ impl Connection {
 
                     self.on_stream_frame(was_blocked, frame.id);
                     self.data_recvd += new_bytes;
+                    if stopped && new_bytes > 0 {
+                        // Discarded on arrival, so the credit can be reissued immediately
+                        self.on_data_consumed(new_bytes);
+                        self.add_read_credits(frame.id, new_bytes, false);
+                    }
                 }
                 Frame::Ack(ack) => {
-                    self.on_ack_received(now, SpaceId::Data, ack);
+                    self.on_ack_received(now, SpaceId::Data, ack)?;
                 }
                 Frame::Padding | Frame::Ping => {}
                 Frame::ConnectionClose(reason) => {
@@ -1615,6 +2177,18 @@ impl Connection {
                     }
                 }
                 Frame::PathResponse(token) => {
+                    if let Some(probe) = self.path_probe {
+                        if probe.token == token && probe.remote == remote {
+                            trace!(self.log, "path probe to {remote} succeeded", remote = remote);
+                            self.io.timer_stop(Timer::PathProbe);
+                            self.path_probe = None;
+                            self.events.push_back(Event::PathProbed {
+                                remote,
+                                validated: true,
+                            });
+                            continue;
+                        }
+                    }
                     if self.path_challenge != Some(token) || remote != self.remote {
                         continue;
                     }
@@ -1668,6 +2242,11 @@ impl Connection {
                     directionality,
                     count,
                 } => {
+                    if count > MAX_STREAM_COUNT {
+                        return Err(TransportError::STREAM_LIMIT_ERROR(
+                            "unrepresentable stream limit",
+                        ));
+                    }
                     let current = match directionality {
                         Directionality::Uni => &mut self.streams.max_uni,
                         Directionality::Bi => &mut self.streams.max_bi,
@@ -1696,6 +2275,12 @@ impl Connection {
                     };
                     let was_blocked = rs.is_blocked();
                     let limit = rs.limit();
+                    let newly_reset = match rs.state {
+                        stream::RecvState::Recv { .. } | stream::RecvState::DataRecvd { .. } => {
+                            true
+                        }
+                        _ => false,
+                    };
 
                     // Validate final_offset
                     if let Some(offset) = rs.final_offset() {
@@ -1707,20 +2292,35 @@ impl Connection {
                             "lower than high water mark",
                         ));
                     }
+                    if final_offset > rs.released_sent + self.config.stream_receive_window
+                        || self.data_recvd + (final_offset - limit) > self.max_data_sent
+                    {
+                        debug!(self.log, "flow control error"; "stream" => id.0, "final offset" => final_offset);
+                        return Err(TransportError::FLOW_CONTROL_ERROR(""));
+                    }
 
                     // State transition
-                    rs.reset(error_code, final_offset);
+                    let discarded = rs.reset(error_code, final_offset);
 
                     // Update flow control
-                    if rs.bytes_read != final_offset {
+                    if rs.released != final_offset {
                         self.data_recvd += final_offset - limit;
-                        // bytes_read is always <= limit, so this won't underflow.
-                        self.local_max_data += final_offset - rs.bytes_read;
+                        // released is always <= limit, so this won't underflow.
+                        self.local_max_data += final_offset - rs.released;
+                        // Any credit the application might still release has now been returned
+                        rs.released = final_offset;
                         self.space_mut(SpaceId::Data).pending.max_data = true;
                     }
+                    self.on_data_consumed(discarded);
 
                     // Notify application
                     self.on_stream_frame(was_blocked, id);
+                    if newly_reset {
+                        self.events.push_back(Event::StreamReset {
+                            stream: id,
+                            error_code,
+                        });
+                    }
                 }
                 Frame::DataBlocked { offset } => {
                     debug!(self.log, "peer claims to be blocked at connection level"; "offset" => offset);
@@ -1742,6 +2342,11 @@ impl Connection {
                     directionality,
                     limit,
                 } => {
+                    if limit > MAX_STREAM_COUNT {
+                        return Err(TransportError::STREAM_LIMIT_ERROR(
+                            "unrepresentable stream limit",
+                        ));
+                    }
                     debug!(self.log, "peer claims to be blocked opening more than {limit} {directionality} streams", limit=limit, directionality=directionality);
                 }
                 Frame::StopSending { id, error_code } => {
@@ -1806,7 +2411,14 @@ impl Connection {
                             "NEW_CONNECTION_ID when CIDs aren't in use",
                         ));
                     }
-                    if self.params.stateless_reset_token.is_none() {
+                    if frame.sequence <= self.rem_cid_seq
+                        || self.rem_cids.iter().any(|x| x.sequence == frame.sequence)
+                    {
+                        // Retransmission of a CID that's in use, retired, or already known. Spare
+                        // CIDs are consumed in sequence order, so anything at or below the current
+                        // sequence number must never be used again.
+                        trace!(self.log, "ignoring duplicate NEW_CONNECTION_ID");
+                    } else if self.params.stateless_reset_token.is_none() {
                         // We're a server using the initial remote CID for the client, so let's
                         // switch immediately to enable clientside stateless resets.
                         debug_assert!(self.side.is_server());
@@ -1826,21 +2438,42 @@ impl Connection {
             }
         }
 
-        if remote != self.remote && !is_probing_packet {
-            debug_assert!(
-                self.side.is_server(),
-                "packets from unknown remote should be dropped by clients"
-            );
-            self.migrate(now, remote);
-            // Break linkability, if possible
-            if let Some(cid) = self.rem_cids.pop() {
+        *current_frame = None;
+        self.check_retransmit_buffer()?;
+
+        // Clients never migrate, only accepting packets from other addresses in answer to a probe
+        if remote != self.remote && !is_probing_packet && self.side.is_server() {
+            self.migrate(now, remote, dst_cid);
+            // Break linkability, if possible. Retiring the old CID prompts the peer to issue a
+            // replacement, keeping a fresh one available for future migrations.
+            if let Some(cid) = self.take_rem_cid() {
                 self.update_rem_cid(cid);
+            } else if !self.rem_cid.is_empty() {
+                debug!(self.log, "no spare remote CIDs; migrated path is linkable");
             }
+        } else if remote == self.remote && !is_probing_packet {
+            self.path_loc_cid = dst_cid;
         }
 
         Ok(())
     }
 
+    /// Arrange for an ack-eliciting 1-RTT packet to be acknowledged
+    ///
+    /// The acknowledgement is deferred by up to our `max_ack_delay` unless the packet was
+    /// reordered or another ack-eliciting packet is already awaiting acknowledgement.
+    fn on_ack_eliciting(&mut self, now: Instant) {
+        let max_ack_delay = self.config.max_ack_delay;
+        let space = &mut self.spaces[SpaceId::Data as usize];
+        space.unacked_ack_eliciting += 1;
+        if max_ack_delay == 0 || space.rx_reordered || space.unacked_ack_eliciting >= 2 {
+            space.permit_ack_only = true;
+            self.io.timer_stop(Timer::AckDelay);
+        } else if space.unacked_ack_eliciting == 1 {
+            self.io.timer_start(Timer::AckDelay, now + Duration::from_millis(max_ack_delay));
+        }
+    }
+
     /// Notify the application that new streams were opened or a stream became readable.
     fn on_stream_frame(&mut self, notify_readable: bool, stream: StreamId) {
         if stream.initiator() == self.side {
@@ -1850,45 +2483,76 @@ impl Connection {
             }
             return;
         }
-        let next = match stream.directionality() {
+        let directionality = stream.directionality();
+        let next = match directionality {
             Directionality::Bi => &mut self.streams.next_remote_bi,
             Directionality::Uni => &mut self.streams.next_remote_uni,
         };
         if stream.index() >= *next {
+            // Lower-numbered streams of the same directionality are opened implicitly
+            let side = !self.side;
+            self.opened_streams.extend(
+                (*next..=stream.index()).map(|index| StreamId::new(side, directionality, index)),
+            );
             *next = stream.index() + 1;
-            self.stream_opened = true;
         } else if notify_readable {
             self.events.push_back(Event::StreamReadable { stream });
         }
     }
 
-    fn migrate(&mut self, now: Instant, remote: SocketAddr) {
+    fn migrate(&mut self, now: Instant, remote: SocketAddr, dst_cid: ConnectionId) {
+        // A zero-length CID gives the peer no way to signal a deliberate migration
+        let kind = if dst_cid == self.path_loc_cid || dst_cid.is_empty() {
+            MigrationKind::Rebinding
+        } else {
+            MigrationKind::Active
+        };
         trace!(
             self.log,
             "migration initiated from {remote}",
-            remote = remote
+            remote = remote;
+            "kind" => ?kind
         );
+        self.path_loc_cid = dst_cid;
+        self.events.push_back(Event::Migrated { remote, kind });
         if remote.ip() != self.remote.ip() {
             // Reset rtt/congestion state for new path
             self.rtt = RttEstimator::new();
-            self.congestion_window = self.config.initial_window;
-            self.ssthresh = u64::max_value();
+            self.congestion_notify(now, |cc, cx| cc.on_path_change(cx));
         }
         self.prev_remote = Some(mem::replace(&mut self.remote, remote));
+        // An interval spanning the path change says nothing about either path
+        self.rx_spin_edge = None;
         self.remote_validated = false;
 
         // Initiate path validation
-        self.io.timer_start(
-            Timer::PathValidation,
-            now + 3 * cmp::max(
-                self.pto(),
-                Duration::from_micros(2 * self.config.initial_rtt),
-            ),
-        );
+        let timeout = self.path_validation_timeout();
+        self.io.timer_start(Timer::PathValidation, now + timeout);
         self.path_challenge = Some(self.rng.gen());
         self.path_challenge_pending = true;
     }
 
+    /// How long to wait for a PATH_RESPONSE before giving up on a path
+    fn path_validation_timeout(&self) -> Duration {
+        if self.config.path_validation_timeout != 0 {
+            return Duration::from_micros(self.config.path_validation_timeout);
+        }
+        3 * cmp::max(
+            self.pto(),
+            Duration::from_micros(2 * self.config.initial_rtt),
+        )
+    }
+
+    /// Remove the spare remote CID with the lowest sequence number from the pool
+    fn take_rem_cid(&mut self) -> Option<frame::NewConnectionId> {
+        let (i, _) = self
+            .rem_cids
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, x)| x.sequence)?;
+        Some(self.rem_cids.swap_remove(i))
+    }
+
     fn update_rem_cid(&mut self, new: frame::NewConnectionId) {
         trace!(
             self.log,
@@ -1912,6 +2576,7 @@ impl Connection {
         space_id: SpaceId,
         buf: &mut Vec<u8>,
     ) -> (Retransmits, RangeSet) {
+        let max_data = self.max_data();
         let space = &mut self.spaces[space_id as usize];
         let mut sent = Retransmits::default();
         let zero_rtt_crypto = self.zero_rtt_crypto.as_ref();
@@ -1942,15 +2607,21 @@ impl Connection {
         // 0-RTT packets must never carry acks (which would have to be of handshake packets)
         let acks = if !space.pending_acks.is_empty() {
             debug_assert!(space.crypto.is_some(), "tried to send ACK in 0-RTT");
-            let delay = micros_from(now - space.rx_packet_time) >> ACK_DELAY_EXPONENT;
+            let exponent = ack_delay_exponent(space_id, self.config.ack_delay_exponent as u64);
+            let delay = micros_from(now - space.rx_packet_time) >> exponent;
             trace!(self.log, "ACK"; "ranges" => ?space.pending_acks.iter().collect::<Vec<_>>(), "delay" => delay);
             let ecn = if self.receiving_ecn {
                 Some(&self.ecn_counters)
             } else {
                 None
             };
-            frame::Ack::encode(delay, &space.pending_acks, ecn, buf);
-            space.pending_acks.clone()
+            let count =
+                frame::Ack::encode(delay, &space.pending_acks, ecn, max_size - buf.len(), buf);
+            let mut acks = RangeSet::new();
+            for range in space.pending_acks.iter().rev().take(count) {
+                acks.insert(range);
+            }
+            acks
         } else {
             RangeSet::new()
         };
@@ -2061,11 +2732,12 @@ impl Connection {
 
         // MAX_DATA
         if space.pending.max_data && buf.len() + 9 < max_size {
-            trace!(self.log, "MAX_DATA"; "value" => self.local_max_data);
+            trace!(self.log, "MAX_DATA"; "value" => max_data);
             space.pending.max_data = false;
             sent.max_data = true;
+            self.max_data_sent = max_data;
             buf.write(frame::Type::MAX_DATA);
-            buf.write_var(self.local_max_data);
+            buf.write_var(max_data);
         }
 
         // MAX_STREAM_DATA
@@ -2076,8 +2748,8 @@ impl Connection {
                 break;
             };
             space.pending.max_stream_data.remove(&id);
-            let rs = if let Some(x) = self.streams.streams.get(&id) {
-                x.recv().unwrap()
+            let rs = if let Some(x) = self.streams.streams.get_mut(&id) {
+                x.recv_mut().unwrap()
             } else {
                 continue;
             };
@@ -2085,7 +2757,8 @@ impl Connection {
                 continue;
             }
             sent.max_stream_data.insert(id);
-            let max = rs.bytes_read + self.config.stream_receive_window;
+            rs.released_sent = rs.released;
+            let max = rs.released + self.config.stream_receive_window;
             trace!(
                 self.log,
                 "MAX_STREAM_DATA: {stream} = {max}",
@@ -2115,6 +2788,29 @@ impl Connection {
             buf.write_var(self.streams.max_remote_bi);
         }
 
+        // STREAMS_BLOCKED_UNI
+        if space.pending.uni_stream_blocked && buf.len() + 9 < max_size {
+            space.pending.uni_stream_blocked = false;
+            // A stale report is useless; only send it if we're still at the peer's limit
+            if self.streams.next_uni == self.streams.max_uni {
+                sent.uni_stream_blocked = true;
+                trace!(self.log, "STREAMS_BLOCKED (unidirectional)"; "limit" => self.streams.max_uni);
+                buf.write(frame::Type::STREAMS_BLOCKED_UNI);
+                buf.write_var(self.streams.max_uni);
+            }
+        }
+
+        // STREAMS_BLOCKED_BIDI
+        if space.pending.bi_stream_blocked && buf.len() + 9 < max_size {
+            space.pending.bi_stream_blocked = false;
+            if self.streams.next_bi == self.streams.max_bi {
+                sent.bi_stream_blocked = true;
+                trace!(self.log, "STREAMS_BLOCKED (bidirectional)"; "limit" => self.streams.max_bi);
+                buf.write(frame::Type::STREAMS_BLOCKED_BIDI);
+                buf.write_var(self.streams.max_bi);
+            }
+        }
+
         // NEW_CONNECTION_ID
         while buf.len() + 44 < max_size {
             let frame = if let Some(x) = space.pending.new_cids.pop() {
@@ -2146,39 +2842,60 @@ impl Connection {
         }
 
         // STREAM
+        //
+        // Streams are scheduled by deficit round robin: each turn at the head of the queue entitles
+        // a stream to `STREAM_QUANTUM` bytes, after which it moves to the back of the queue, so
+        // that a stream whose data was queued first can't monopolize packets. A stream with nothing
+        // left to send forfeits the remainder of its turn.
         while buf.len() + frame::Stream::SIZE_BOUND < max_size {
-            let mut stream = if let Some(x) = space.pending.stream.pop_front() {
+            // Only share the packet if some other stream is waiting for it
+            let contended = space.pending.stream.streams.len() > 1;
+            let head = if let Some(x) = space.pending.stream.streams.front_mut() {
                 x
             } else {
                 break;
             };
-            if self
+            let mut stream = head.frames.pop_front().unwrap();
+            let reset = self
                 .streams
                 .streams
                 .get(&stream.id)
-                .map_or(true, |s| s.send().unwrap().state.was_reset())
-            {
+                .map_or(true, |s| s.send().unwrap().state.was_reset());
+            if reset {
                 self.unacked_data -= stream.data.len() as u64;
-                continue;
+            } else {
+                if head.deficit == 0 {
+                    head.deficit = STREAM_QUANTUM;
+                }
+                let mut len = cmp::min(
+                    stream.data.len(),
+                    max_size as usize - buf.len() - frame::Stream::SIZE_BOUND,
+                );
+                if contended {
+                    len = cmp::min(len, head.deficit as usize);
+                    head.deficit -= len as u64;
+                }
+                let data = stream.data.split_to(len);
+                let fin = stream.fin && stream.data.is_empty();
+                trace!(self.log, "STREAM"; "id" => stream.id.0, "off" => stream.offset, "len" => len, "fin" => fin);
+                let frame = frame::Stream {
+                    id: stream.id,
+                    offset: stream.offset,
+                    fin,
+                    data,
+                };
+                frame.encode(true, buf);
+                sent.stream.push_back(frame);
+                if !stream.data.is_empty() {
+                    stream.offset += len as u64;
+                    head.frames.push_front(stream);
+                }
             }
-            let len = cmp::min(
-                stream.data.len(),
-                max_size as usize - buf.len() - frame::Stream::SIZE_BOUND,
-            );
-            let data = stream.data.split_to(len);
-            let fin = stream.fin && stream.data.is_empty();
-            trace!(self.log, "STREAM"; "id" => stream.id.0, "off" => stream.offset, "len" => len, "fin" => fin);
-            let frame = frame::Stream {
-                id: stream.id,
-                offset: stream.offset,
-                fin,
-                data,
-            };
-            frame.encode(true, buf);
-            sent.stream.push_back(frame);
-            if !stream.data.is_empty() {
-                stream.offset += len as u64;
-                space.pending.stream.push_front(stream);
+            if head.frames.is_empty() {
+                space.pending.stream.streams.pop_front();
+            } else if contended && head.deficit == 0 {
+                let turn_over = space.pending.stream.streams.pop_front().unwrap();
+                space.pending.stream.streams.push_back(turn_over);
             }
         }
 
@@ -2192,6 +2909,21 @@ impl Connection {
     /// - an incoming packet is handled
     /// - the LossDetection timer expires
     pub fn poll_transmit(&mut self, now: Instant) -> Option<Transmit> {
+        // Frames queued by the application, e.g. by `reset`, weren't checked as they were queued.
+        // That's no fault of the peer's, so the connection is closed as an internal error.
+        if !self.state.is_closed() && self.retransmit_buffer_exceeded() {
+            let err = TransportError::INTERNAL_ERROR("retransmit buffer limit exceeded");
+            debug!(
+                self.log,
+                "closing connection due to transport error: {error}",
+                error = &err
+            );
+            self.events
+                .push_back(ConnectionError::TransportError(err.clone()).into());
+            self.state = State::closed(err);
+            self.close_common(now);
+            self.io.close = true;
+        }
         let (space_id, close) = match self.state {
             State::Draining | State::Drained => {
                 return None;
@@ -2221,7 +2953,14 @@ impl Connection {
                         } else {
                             None
                         }
-                    })?;
+                    });
+                let id = match id {
+                    Some(x) => x,
+                    None => {
+                        self.check_quiescent();
+                        return None;
+                    }
+                };
                 (id, false)
             }
         };
@@ -2312,6 +3051,18 @@ impl Connection {
             let max_len =
                 self.mtu as usize - header_len - space.crypto.as_ref().unwrap().packet.tag_len();
             match self.state {
+                State::Closed(state::Closed {
+                    reason: state::CloseReason::Application(_),
+                }) if space_id != SpaceId::Data => {
+                    // Application closes may only be sent in 1-RTT packets. Withhold the error
+                    // code and reason, which unauthenticated observers could otherwise read.
+                    frame::ConnectionClose {
+                        error_code: TransportErrorCode::APPLICATION_ERROR,
+                        frame_type: None,
+                        reason: Bytes::new(),
+                    }
+                    .encode(&mut buf, max_len)
+                }
                 State::Closed(state::Closed {
                     reason: state::CloseReason::Application(ref x),
                 }) => x.encode(&mut buf, max_len),
@@ -2328,6 +3079,16 @@ impl Connection {
             buf.write(frame::Type::PATH_RESPONSE);
             buf.write(token);
             (remote, None)
+        } else if let Some(probe) = self
+            .path_probe
+            .as_mut()
+            .filter(|x| x.pending && space_id == SpaceId::Data)
+        {
+            probe.pending = false;
+            trace!(self.log, "PATH_CHALLENGE {token:08x}", token = probe.token);
+            buf.write(frame::Type::PATH_CHALLENGE);
+            buf.write(probe.token);
+            (probe.remote, None)
         } else {
             (
                 self.remote,
@@ -2376,6 +3137,13 @@ impl Connection {
             // false needlessly prevents us from ACKing the next packet if it's ACK-only, but saves
             // the need for subtler logic to avoid double-transmitting acks all the time.
             space.permit_ack_only &= acks.is_empty();
+            if !acks.is_empty() && space.unacked_ack_eliciting != 0 {
+                space.unacked_ack_eliciting = 0;
+                self.io.timer_stop(Timer::AckDelay);
+            }
+            // Nothing left to send despite room in the congestion window
+            let app_limited = self.spaces[space_id as usize].pending.is_empty()
+                && self.in_flight.bytes + (buf.len() as u64) < self.congestion.window();
 
             self.on_packet_sent(
                 now,
@@ -2391,6 +3159,7 @@ impl Connection {
                     },
                     is_crypto_packet: space_id != SpaceId::Data && !ack_only,
                     ack_eliciting: !ack_only,
+                    app_limited,
                     retransmits: sent,
                 },
             );
@@ -2403,12 +3172,14 @@ impl Connection {
             remote = remote
         );
         self.total_sent = self.total_sent.wrapping_add(buf.len() as u64);
+        self.check_quiescent();
 
         Some(Transmit {
             destination: remote,
+            source: self.local_ip,
             packet: buf.into(),
-            ecn: if self.sending_ecn {
-                Some(EcnCodepoint::ECT0)
+            ecn: if self.ecn_state != EcnState::Failed {
+                Some(self.config.ecn_codepoint)
             } else {
                 None
             },
@@ -2443,8 +3214,18 @@ impl Connection {
         self.io.timer_stop(Timer::Idle);
         self.io.timer_stop(Timer::KeyDiscard);
         self.io.timer_stop(Timer::PathValidation);
+        self.path_challenge = None;
+        self.io.timer_stop(Timer::PathProbe);
         self.io.timer_stop(Timer::KeepAlive);
-        self.io.timer_start(Timer::Close, now + 3 * self.pto());
+        self.io.timer_stop(Timer::AckDelay);
+        let period = self.draining_period();
+        self.io.timer_start(Timer::Close, now + period);
+    }
+
+    /// Length of the closing and draining periods of this connection
+    pub(crate) fn draining_period(&self) -> Duration {
+        self.draining_period
+            .unwrap_or_else(|| self.config.draining_ptos * self.pto())
     }
 
     fn set_params(&mut self, params: TransportParameters) -> Result<(), TransportError> {
@@ -2476,6 +3257,9 @@ impl Connection {
             cmp::min(self.config.idle_timeout, params.idle_timeout)
         };
         self.params = params;
+        // Respect the peer's limit
+        let mtu = self.mtu;
+        self.set_mtu(mtu);
         Ok(())
     }
 
@@ -2496,8 +3280,13 @@ impl Connection {
                 )
             }
             _ => {
+                let pending = &mut self.spaces[SpaceId::Data as usize].pending;
+                match direction {
+                    Directionality::Uni => pending.uni_stream_blocked = true,
+                    Directionality::Bi => pending.bi_stream_blocked = true,
+                }
                 return None;
-            } // TODO: Queue STREAM_ID_BLOCKED
+            }
         };
         stream.send_mut().unwrap().max_data = match direction {
             Directionality::Uni => self.params.initial_max_stream_data_uni,
@@ -2515,14 +3304,40 @@ impl Connection {
         self.ping_pending = true;
     }
 
-    /// Discard state for a stream if it's fully closed.
+    /// Check whether the peer can be reached at `remote`, without migrating to it
     ///
-    /// Called when one side of a stream transitions to a closed state
-    pub fn maybe_cleanup(&mut self, id: StreamId) {
-        match self.streams.streams.entry(id) {
-            hash_map::Entry::Vacant(_) => unreachable!(),
-            hash_map::Entry::Occupied(e) => {
-                if e.get().is_closed() {
+    /// Sends a PATH_CHALLENGE to `remote`, reporting the outcome with an `Event::PathProbed` once
+    /// the peer responds or the attempt times out. The current path is unaffected either way. Only
+    /// one probe may be outstanding; starting another abandons the previous one unreported.
+    ///
+    /// Paths can only be probed once 1-RTT keys are available and until the connection is closed.
+    /// Otherwise, the probe fails immediately.
+    pub fn probe_path(&mut self, now: Instant, remote: SocketAddr) {
+        if !self.has_1rtt() || self.state.is_closed() {
+            debug!(self.log, "path can't be probed in this state");
+            self.events.push_back(Event::PathProbed {
+                remote,
+                validated: false,
+            });
+            return;
+        }
+        self.path_probe = Some(PathProbe {
+            remote,
+            token: self.rng.gen(),
+            pending: true,
+        });
+        let timeout = self.path_validation_timeout();
+        self.io.timer_start(Timer::PathProbe, now + timeout);
+    }
+
+    /// Discard state for a stream if it's fully closed.
+    ///
+    /// Called when one side of a stream transitions to a closed state
+    pub fn maybe_cleanup(&mut self, id: StreamId) {
+        match self.streams.streams.entry(id) {
+            hash_map::Entry::Vacant(_) => unreachable!(),
+            hash_map::Entry::Occupied(e) => {
+                if e.get().is_closed() {
                     e.remove_entry();
                 }
             }
@@ -2531,6 +3346,14 @@ impl Connection {
 
     /// Permit an additional remote `ty` stream.
     fn alloc_remote_stream(&mut self, ty: Directionality) {
+        let max = match ty {
+            Directionality::Bi => self.streams.max_remote_bi,
+            Directionality::Uni => self.streams.max_remote_uni,
+        };
+        if max == MAX_STREAM_COUNT {
+            // The stream ID space is exhausted; the peer will have to use a new connection
+            return;
+        }
         let space = &mut self.spaces[SpaceId::Data as usize];
         let (id, stream) = match ty {
             Directionality::Bi => {
@@ -2573,10 +3396,120 @@ impl Connection {
         } else {
             return None;
         };
-        self.alloc_remote_stream(id.directionality());
+        if self.accepting_streams {
+            self.alloc_remote_stream(id.directionality());
+        }
         Some(id)
     }
 
+    /// Stop permitting the peer to open new streams
+    ///
+    /// Streams the peer is already permitted to open may still be opened and accepted, but the
+    /// peer's stream limits will no longer be raised as they are. Useful for draining a connection
+    /// gracefully.
+    pub fn stop_accepting_streams(&mut self) {
+        self.accepting_streams = false;
+    }
+
+    /// Send all further packets from `ip`, or let the operating system choose if `None`
+    ///
+    /// On hosts with multiple interfaces, determines the path outgoing packets take. Must be an
+    /// address the endpoint's socket is bound to, or that it can send from if bound to a wildcard
+    /// address. Honored by the socket layer, which may not support it on every platform. Set right
+    /// after `Endpoint::connect` to pin the initial path.
+    pub fn set_local_ip(&mut self, ip: Option<IpAddr>) {
+        self.local_ip = ip;
+    }
+
+    /// Local address outgoing packets are pinned to, if any
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        self.local_ip
+    }
+
+    /// Override `TransportConfig::packet_threshold` for this connection
+    ///
+    /// Maximum reordering in packet number space before a packet is considered lost.
+    pub fn set_packet_threshold(&mut self, value: u32) {
+        Arc::make_mut(&mut self.config).packet_threshold = value;
+    }
+
+    /// Override `TransportConfig::time_threshold` for this connection
+    ///
+    /// Maximum reordering in time before a packet is considered lost, in 0.16 format.
+    pub fn set_time_threshold(&mut self, value: u16) {
+        Arc::make_mut(&mut self.config).time_threshold = value;
+    }
+
+    /// Override `TransportConfig::loss_reduction_factor` for this connection
+    ///
+    /// Reduction in congestion window on a new loss event, in 0.16 format.
+    pub fn set_loss_reduction_factor(&mut self, value: u16) {
+        Arc::make_mut(&mut self.config).loss_reduction_factor = value;
+    }
+
+    /// Override `TransportConfig::persistent_congestion_threshold` for this connection
+    ///
+    /// Number of consecutive PTOs indicating persistent congestion.
+    pub fn set_persistent_congestion_threshold(&mut self, value: u32) {
+        Arc::make_mut(&mut self.config).persistent_congestion_threshold = value;
+    }
+
+    /// Override `TransportConfig::minimum_window` for this connection
+    ///
+    /// Minimum congestion window in bytes.
+    pub fn set_minimum_window(&mut self, value: u64) {
+        Arc::make_mut(&mut self.config).minimum_window = value;
+    }
+
+    /// Change the largest UDP payload to send
+    ///
+    /// Overrides `TransportConfig::initial_mtu` for this connection. Values below 1232 are raised
+    /// to it, and values above the peer's advertised maximum packet size are lowered to that.
+    pub fn set_mtu(&mut self, mtu: u16) {
+        let mtu = cmp::min(u64::from(mtu), self.params.max_packet_size) as u16;
+        self.mtu = cmp::max(mtu, MIN_MTU);
+    }
+
+    /// Seed congestion control from a previous connection to the same peer
+    ///
+    /// `bdp` is the bandwidth-delay product, in bytes, measured on an earlier connection over this
+    /// path. The congestion window starts at half of it (but never below
+    /// `TransportConfig::initial_window`) and slow start continues up to the full estimate. If a
+    /// loss is detected before the seeded window has been acknowledged, the estimate is presumed
+    /// stale and congestion control falls back to a normal slow start. Should be called before the
+    /// first packet is sent. This describes `congestion::NewReno`; other controllers may ignore it.
+    pub fn warm_up(&mut self, now: Instant, bdp: u64) {
+        let old = self.congestion.window();
+        self.congestion_notify(now, |cc, cx| cc.warm_up(cx, bdp));
+        let window = self.congestion.window();
+        if window != old {
+            trace!(self.log, "warming up congestion window"; "window" => window);
+        }
+    }
+
+    /// Fix the length of the closing and draining periods of this connection
+    ///
+    /// Overrides the duration derived from `TransportConfig::draining_ptos` and the measured RTT,
+    /// for when teardown timing must be reproducible, as in load tests. Takes effect the next time
+    /// the connection is closed, and is not shortened when the peer closes the connection too.
+    /// `None` restores the default.
+    pub fn set_draining_period(&mut self, period: Option<Duration>) {
+        self.draining_period = period;
+    }
+
+    /// Change the interval of inactivity after which a keep-alive packet is sent
+    ///
+    /// Overrides `TransportConfig::keep_alive_interval` for this connection. `None` disables
+    /// keep-alive, allowing an idle connection to time out.
+    pub fn set_keep_alive(&mut self, now: Instant, interval: Option<Duration>) {
+        self.keep_alive_interval = interval;
+        if interval.is_some() {
+            self.reset_keep_alive(now);
+        } else {
+            self.io.timer_stop(Timer::KeepAlive);
+        }
+    }
+
     pub fn finish(&mut self, id: StreamId) {
         let ss = self
             .streams
@@ -2585,7 +3518,7 @@ impl Connection {
         assert_eq!(ss.state, stream::SendState::Ready);
         ss.state = stream::SendState::DataSent;
         let space = &mut self.spaces[SpaceId::Data as usize];
-        for frame in &mut space.pending.stream {
+        for frame in space.pending.stream.iter_mut() {
             if frame.id == id && frame.offset + frame.data.len() as u64 == ss.offset {
                 frame.fin = true;
                 return;
@@ -2600,18 +3533,105 @@ impl Connection {
     }
 
     pub fn read_unordered(&mut self, id: StreamId) -> Result<(Bytes, u64), ReadError> {
-        let (buf, len, more) = self.streams.read_unordered(id)?;
-        self.add_read_credits(id, len, more);
-        Ok((buf, len))
+        let (buf, offset, more) = self.streams.read_unordered(id)?;
+        self.on_data_consumed(buf.len() as u64);
+        if !self.config.manual_credit {
+            self.release_stream_credit(id, buf.len() as u64, more);
+        }
+        Ok((buf, offset))
     }
 
     pub fn read(&mut self, id: StreamId, buf: &mut [u8]) -> Result<usize, ReadError> {
         let (len, more) = self.streams.read(id, buf)?;
-        self.add_read_credits(id, len as u64, more);
+        self.on_data_consumed(len as u64);
+        if !self.config.manual_credit {
+            self.release_stream_credit(id, len as u64, more);
+        }
         Ok(len)
     }
 
+    /// Permit the peer to send `bytes` more data, having fully processed data read from `id`
+    ///
+    /// Only needed when `TransportConfig::manual_credit` is set; otherwise credit is released as
+    /// soon as data is read. Credit is never released beyond the amount read from a stream that is
+    /// still open, but for a stream that has been closed after being read to completion, the
+    /// application must take care not to release more than it read. Credit for data discarded due
+    /// to a reset or `stop_sending` is released automatically.
+    pub fn release_credit(&mut self, id: StreamId, bytes: u64) {
+        let (bytes, more) = match self.streams.get_recv_mut(id) {
+            Some(rs) => (
+                cmp::min(bytes, rs.bytes_read.saturating_sub(rs.released)),
+                rs.receiving_unknown_size(),
+            ),
+            None => (bytes, false),
+        };
+        if bytes != 0 {
+            self.release_stream_credit(id, bytes, more);
+        }
+    }
+
+    /// Advertise all flow control credit granted so far in the next outgoing flight
+    ///
+    /// Schedules MAX_DATA, and MAX_STREAM_DATA for every stream the peer may still send on,
+    /// regardless of whether an earlier advertisement is believed to be in flight. Useful right
+    /// before waiting on the peer, so that it can't end up blocked on credit we've yet to announce.
+    /// Credit withheld under `TransportConfig::manual_credit` is not released.
+    pub fn grant_credit_now(&mut self) {
+        if self.state.is_closed() {
+            return;
+        }
+        let space = &mut self.spaces[SpaceId::Data as usize];
+        space.pending.max_data = true;
+        for (&id, stream) in &self.streams.streams {
+            if stream.recv().map_or(false, |rs| rs.receiving_unknown_size()) {
+                space.pending.max_stream_data.insert(id);
+            }
+        }
+    }
+
+    fn release_stream_credit(&mut self, id: StreamId, len: u64, more: bool) {
+        if let Some(rs) = self.streams.get_recv_mut(id) {
+            rs.released += len;
+        }
+        self.add_read_credits(id, len, more);
+    }
+
+    /// Hint that stream `id` is expected to carry `size` bytes in total
+    ///
+    /// Preallocates reassembly buffers to avoid repeated reallocation as data arrives. The
+    /// allocation is bounded by the stream receive window. Has no effect on flow control, and is
+    /// ignored for unknown streams.
+    pub fn reserve_recv(&mut self, id: StreamId, size: u64) {
+        let window = self.config.stream_receive_window;
+        if let Some(rs) = self.streams.get_recv_mut(id) {
+            let remaining = size.saturating_sub(rs.assembler.offset());
+            rs.assembler.reserve(cmp::min(remaining, window) as usize);
+        }
+    }
+
+    /// Limit on incoming data currently advertised to the peer
+    fn max_data(&self) -> u64 {
+        match self.config.max_unread_data {
+            0 => self.local_max_data,
+            x => cmp::min(self.local_max_data, self.data_consumed + x),
+        }
+    }
+
+    /// Account for `len` bytes of incoming data leaving receive buffers
+    fn on_data_consumed(&mut self, len: u64) {
+        let old = self.max_data();
+        self.data_consumed += len;
+        if self.max_data() != old && !self.state.is_closed() {
+            // Buffer space freed up, which the peer may have been waiting on
+            self.space_mut(SpaceId::Data).pending.max_data = true;
+        }
+    }
+
     fn add_read_credits(&mut self, id: StreamId, len: u64, more: bool) {
+        if self.state.is_closed() {
+            // The peer can't make use of more credit, and we couldn't tell it about it anyway
+            return;
+        }
         self.local_max_data += len;
         let space = &mut self.spaces[SpaceId::Data as usize];
         space.pending.max_data = true;
@@ -2629,20 +3649,63 @@ impl Connection {
         let stream = self
             .streams
             .streams
-            .get(&id)
+            .get_mut(&id)
             .expect("stream must have begun sending to be stopped")
-            .recv()
+            .recv_mut()
             .unwrap();
+        // Free buffered data right away, returning its credit so it isn't lost to the connection
+        let bytes_read = stream.bytes_read;
+        let freed = stream.stop();
+        let discarded = stream.bytes_read - bytes_read;
+        let finished = stream.is_finished();
+        self.on_data_consumed(discarded);
+        if freed > 0 {
+            self.add_read_credits(id, freed, false);
+        }
         // Only bother if there's data we haven't received yet
-        if !stream.is_finished() {
+        if !finished {
             let space = &mut self.spaces[SpaceId::Data as usize];
             space.pending.stop_sending.push((id, error_code));
         }
     }
 
+    /// Approximate memory held by CRYPTO and control frames awaiting transmission or
+    /// acknowledgement
+    fn control_bytes(&self) -> u64 {
+        let queued = self
+            .spaces
+            .iter()
+            .map(|space| space.pending.control_bytes())
+            .sum::<u64>();
+        queued + self.in_flight.control + self.offpath_responses.len() as u64 * CONTROL_FRAME_COST
+    }
+
+    /// Whether `control_bytes` exceeds `TransportConfig::max_retransmit_buffer`
+    fn retransmit_buffer_exceeded(&self) -> bool {
+        let limit = self.config.max_retransmit_buffer;
+        let bytes = self.control_bytes();
+        if limit != 0 && bytes > limit {
+            debug!(self.log, "retransmit buffer limit exceeded"; "bytes" => bytes);
+            return true;
+        }
+        false
+    }
+
+    /// Fail if the peer's frames have pushed `control_bytes` past
+    /// `TransportConfig::max_retransmit_buffer`
+    fn check_retransmit_buffer(&self) -> Result<(), TransportError> {
+        if self.retransmit_buffer_exceeded() {
+            return Err(TransportError::PROTOCOL_VIOLATION(
+                "retransmit buffer limit exceeded",
+            ));
+        }
+        Ok(())
+    }
+
     fn congestion_blocked(&self) -> bool {
         if let State::Established = self.state {
-            self.congestion_window.saturating_sub(self.in_flight.bytes) < self.mtu as u64
+            let window = self.congestion.window();
+            window.saturating_sub(self.in_flight.bytes) < self.mtu as u64
         } else {
             false
         }
@@ -2779,11 +3842,18 @@ impl Connection {
             return Err(WriteError::Blocked);
         }
 
-        let budget_res = self
-            .streams
-            .get_send_mut(stream)
-            .expect("stream already closed")
-            .write_budget();
+        let budget_res = match self.streams.get_send_mut(stream) {
+            Some(ss) => ss.write_budget(),
+            None if self.opened_in_rejected_0rtt(stream) => {
+                trace!(
+                    self.log,
+                    "write on {stream} rejected with 0-RTT data",
+                    stream = stream
+                );
+                return Err(WriteError::ZeroRttRejected);
+            }
+            None => panic!("stream already closed"),
+        };
 
         let stream_budget = match budget_res {
             Ok(budget) => budget,
@@ -2799,11 +3869,14 @@ impl Connection {
                 );
                 return Err(e);
             }
+            Err(WriteError::ZeroRttRejected) => unreachable!(),
         };
 
+        // Saturating because `reject_0rtt` zeroes `data_sent` and `unacked_data` wholesale rather
+        // than through the usual acknowledgement accounting
         let conn_budget = cmp::min(
-            self.max_data - self.data_sent,
-            self.config.send_window - self.unacked_data,
+            self.max_data.saturating_sub(self.data_sent),
+            self.config.send_window.saturating_sub(self.unacked_data),
         );
         let n = conn_budget.min(stream_budget).min(data.len() as u64) as usize;
         self.queue_stream_data(stream, (&data[0..n]).into());
@@ -2832,6 +3905,7 @@ impl Connection {
             update_unacked: remote,
         });
         self.key_phase = !self.key_phase;
+        self.key_updated = true;
     }
 
     pub fn is_handshaking(&self) -> bool {
@@ -2842,14 +3916,88 @@ impl Connection {
         self.state.is_closed()
     }
 
+    /// Whether the peer has closed the connection and we're waiting out the draining period
+    ///
+    /// Nothing further will be sent on a draining connection, but stream data that was received
+    /// before the peer closed it remains readable.
+    pub fn is_draining(&self) -> bool {
+        self.state.is_draining()
+    }
+
+    /// Whether the peer's new address is still being validated following a migration
+    ///
+    /// If validation doesn't complete within the path validation timeout, the connection reverts
+    /// to the peer's previous address.
+    pub fn is_path_validating(&self) -> bool {
+        self.path_challenge.is_some()
+    }
+
+    /// Whether the peer has proven it can receive packets at its current address
+    ///
+    /// False on a server until the client's address is validated during the handshake, during
+    /// which at most three times the amount of data received may be sent, and following a
+    /// migration until the new path is validated.
+    pub fn is_path_validated(&self) -> bool {
+        self.remote_validated
+    }
+
     pub fn accepted_0rtt(&self) -> bool {
         self.accepted_0rtt
     }
 
+    /// Number of `dir` streams currently open, whether initiated locally or by the peer
+    ///
+    /// Streams that have been closed or reset are not counted.
+    pub fn open_stream_count(&self, dir: Directionality) -> u64 {
+        self.streams.open_count(self.side, dir)
+    }
+
+    /// Number of additional `dir` streams the peer currently permits us to open
+    ///
+    /// Once this reaches zero, `open` fails until the peer raises its limit, so applications that
+    /// open streams at a high rate may want to react before then.
+    pub fn remaining_local_streams(&self, dir: Directionality) -> u64 {
+        match dir {
+            Directionality::Uni => self.streams.max_uni - self.streams.next_uni,
+            Directionality::Bi => self.streams.max_bi - self.streams.next_bi,
+        }
+    }
+
+    /// Total number of `dir` streams we currently permit the peer to open over the connection's
+    /// lifetime
+    pub fn remote_stream_limit(&self, dir: Directionality) -> u64 {
+        match dir {
+            Directionality::Uni => self.streams.max_remote_uni,
+            Directionality::Bi => self.streams.max_remote_bi,
+        }
+    }
+
+    /// Number of additional `dir` streams the peer may open before reaching our limit
+    pub fn remaining_remote_streams(&self, dir: Directionality) -> u64 {
+        match dir {
+            Directionality::Uni => self.streams.max_remote_uni - self.streams.next_remote_uni,
+            Directionality::Bi => self.streams.max_remote_bi - self.streams.next_remote_bi,
+        }
+    }
+
+    /// Whether 0-RTT keys are available, i.e. whether 0-RTT is being attempted
+    ///
+    /// For clients, this is known as soon as the connection is created.
     pub fn has_0rtt(&self) -> bool {
         self.zero_rtt_crypto.is_some()
     }
 
+    /// Whether application data written now would be sent as 0-RTT data
+    ///
+    /// True for a client attempting 0-RTT until the handshake completes or the server rejects it.
+    /// Streams may be opened and written to before `Event::Connected` while this holds.
+    pub fn can_send_0rtt(&self) -> bool {
+        self.side.is_client()
+            && self.zero_rtt_crypto.is_some()
+            && self.state.is_handshake()
+            && !self.rejected_0rtt
+    }
+
     pub fn has_1rtt(&self) -> bool {
         self.spaces[SpaceId::Data as usize].crypto.is_some()
     }
@@ -2873,6 +4021,24 @@ impl Connection {
         self.rem_cid
     }
 
+    /// Number of spare connection IDs the peer has issued to us
+    ///
+    /// Each migration to a new path consumes one, when available, to prevent observers from
+    /// linking the paths. The peer replaces those we retire.
+    pub fn rem_cids_available(&self) -> usize {
+        self.rem_cids.len()
+    }
+
+    /// Length of the connection IDs we issue, as carried in packets sent to us
+    pub fn loc_cid_len(&self) -> usize {
+        self.endpoint_config.local_cid_len
+    }
+
+    /// Length of the connection ID the peer chose, as carried in packets we send
+    pub fn rem_cid_len(&self) -> usize {
+        self.rem_cid.len()
+    }
+
     pub fn remote(&self) -> SocketAddr {
         self.remote
     }
@@ -2881,15 +4047,86 @@ impl Connection {
         self.tls.alpn_protocol()
     }
 
+    /// The TLS cipher suite negotiated by the handshake
+    ///
+    /// `None` until enough of the handshake has completed to select one.
+    pub fn negotiated_cipher_suite(&self) -> Option<CipherSuite> {
+        self.tls.negotiated_cipher_suite().map(|x| x.suite)
+    }
+
+    /// The TLS protocol version negotiated by the handshake
+    ///
+    /// Always TLS 1.3 once known, as QUIC does not support earlier versions.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.tls.protocol_version()
+    }
+
+    /// The highest offset of data received on `stream`, including data not yet read
+    ///
+    /// Gaps may remain below this offset if data arrived out of order. `None` if `stream` is not
+    /// a known receive stream.
+    pub fn recv_offset(&self, stream: StreamId) -> Option<u64> {
+        let rs = self.streams.streams.get(&stream)?.recv()?;
+        Some(rs.limit())
+    }
+
     /// The number of bytes of packets containing retransmittable frames that have not been
     /// acknowledged or declared lost.
     pub fn bytes_in_flight(&self) -> u64 {
         self.in_flight.bytes
     }
 
+    /// Number of unacknowledged Initial and Handshake packets carrying handshake data
+    pub fn crypto_packets_in_flight(&self) -> u64 {
+        self.in_flight.crypto
+    }
+
+    /// Bytes of handshake data queued for transmission or retransmission, across all spaces
+    pub fn pending_crypto_bytes(&self) -> u64 {
+        self.spaces.iter().map(|x| x.pending.crypto_bytes()).sum()
+    }
+
+    /// Total bytes of handshake data queued for retransmission after being deemed lost
+    ///
+    /// Limited by `TransportConfig::max_crypto_retransmit_bytes`.
+    pub fn crypto_bytes_retransmitted(&self) -> u64 {
+        self.crypto_retransmitted
+    }
+
+    /// Number of packets sent in `space` that are still tracked pending acknowledgement or loss
+    ///
+    /// Each entry records the frames its packet carried, to be retransmitted if it's lost, so this
+    /// governs the memory used for loss recovery. Packets counted in `bytes_in_flight` are bounded
+    /// by the congestion window divided by the size of the smallest such packet; on paths with a
+    /// large bandwidth-delay product, the window, and hence this count, is in turn bounded by
+    /// `TransportConfig::send_window` for packets carrying stream data. Ack-only packets occupy no
+    /// window, but are forgotten once the peer acknowledges a sufficiently later packet.
+    pub fn sent_packets_tracked(&self, space: SpaceId) -> usize {
+        self.spaces[space as usize].sent_packets.len()
+    }
+
+    /// The number of bytes of stream and handshake data queued for transmission or
+    /// retransmission, plus `bytes_in_flight`
+    ///
+    /// Unlike `bytes_in_flight`, this includes data that has been written but not yet sent,
+    /// reflecting the full send backlog.
+    pub fn pending_bytes(&self) -> u64 {
+        let queued = self
+            .spaces
+            .iter()
+            .map(|space| {
+                let stream = space.pending.stream.iter().map(|x| x.data.len());
+                let crypto = space.pending.crypto.iter().map(|x| x.data.len());
+                stream.chain(crypto).sum::<usize>()
+            })
+            .sum::<usize>();
+        queued as u64 + self.in_flight.bytes
+    }
+
     /// Number of bytes worth of non-ack-only packets that may be sent
     pub fn congestion_state(&self) -> u64 {
-        self.congestion_window.saturating_sub(self.in_flight.bytes)
+        let window = self.congestion.window();
+        window.saturating_sub(self.in_flight.bytes)
     }
 
     /// The name a client supplied via SNI
@@ -2899,14 +4136,128 @@ impl Connection {
         self.tls.sni_hostname()
     }
 
+    /// Total number of packets sent, across all packet number spaces
+    ///
+    /// Together with `lost_packets`, gives the packet loss rate of the path.
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent
+    }
+
+    /// Total number of packets received from the peer, across all packet number spaces
+    ///
+    /// Packets that could not be authenticated are not counted.
+    pub fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+
+    /// Total number of packets received from the peer after a later-numbered packet
+    ///
+    /// Together with `packets_received`, gives the rate at which the path reorders packets toward
+    /// us.
+    pub fn reordered_packets_received(&self) -> u64 {
+        self.reordered_packets_received
+    }
+
+    /// Total number of outgoing packets acknowledged only after a later-numbered packet
+    ///
+    /// Includes packets that had already been deemed lost. Together with `packets_sent`, gives the
+    /// rate at which the path reorders packets away from us.
+    pub fn reordered_packets_acked(&self) -> u64 {
+        self.reordered_packets_acked
+    }
+
     /// Total number of outgoing packets that have been deemed lost
     pub fn lost_packets(&self) -> u64 {
         self.lost_packets
     }
 
+    /// Total number of outgoing packets that carried only acknowledgements
+    ///
+    /// A high ratio of these to `ack_eliciting_packets` suggests acknowledgements could be batched
+    /// more aggressively.
+    pub fn ack_only_packets(&self) -> u64 {
+        self.ack_only_packets
+    }
+
+    /// Largest number of packets by which an acknowledgement arrived out of order
+    ///
+    /// Includes packets that were deemed lost before turning up. Reordering at or beyond the
+    /// packet threshold causes spurious loss detection, which `set_packet_threshold` can remedy.
+    pub fn max_reordering(&self) -> u64 {
+        self.max_reordering
+    }
+
+    /// Total number of outgoing packets that elicited an acknowledgement from the peer
+    pub fn ack_eliciting_packets(&self) -> u64 {
+        self.ack_eliciting_packets
+    }
+
+    /// The earliest time at which a timer of this connection will expire, if any are running
+    ///
+    /// Allows a driver to schedule a single wakeup rather than tracking each `TimerUpdate`.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.io.deadlines.iter().filter_map(|&x| x).min()
+    }
+
+    /// Time left at `now` before the connection times out due to inactivity
+    ///
+    /// `None` if no idle timeout is running, as when neither peer configured one or the connection
+    /// is closed. The deadline moves back whenever a packet is received, and again when a reply is
+    /// sent, so applications sending their own heartbeats should do so before this elapses.
+    pub fn idle_timeout_remaining(&self, now: Instant) -> Option<Duration> {
+        let deadline = self.io.deadlines[Timer::Idle as usize]?;
+        Some(if deadline > now {
+            deadline - now
+        } else {
+            Duration::new(0, 0)
+        })
+    }
+
+    /// Current smoothed round-trip time estimate, used for congestion control and loss detection
+    ///
+    /// `None` until the first RTT sample is taken.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt.smoothed
+    }
+
+    /// Smoothed RTT along with its variance and the minimum RTT observed
+    ///
+    /// Cheap enough to poll frequently, e.g. to size a jitter buffer. `None` until the first RTT
+    /// sample is taken.
+    pub fn rtt_info(&self) -> Option<RttInfo> {
+        Some(RttInfo {
+            smoothed: self.rtt.smoothed?,
+            var: self.rtt.var,
+            min: self.rtt.min,
+        })
+    }
+
+    /// The most recent RTT sample derived from the spin bit
+    ///
+    /// Independent of acknowledgements, so comparing it with `rtt` can reveal a peer that
+    /// misreports its ack delay. Coarse, since it also includes any time either endpoint went
+    /// without sending, and so overestimates the RTT of connections that are mostly idle. `None`
+    /// until two spin bit edges have been received.
+    pub fn spin_rtt(&self) -> Option<Duration> {
+        self.spin_rtt
+    }
+
+    /// The most recent RTT sample taken from an acknowledgement in `space`
+    ///
+    /// Handshake and application data samples may differ, e.g. after a path change. `None` if no
+    /// ack-eliciting packet in `space` has been acknowledged yet.
+    pub fn latest_rtt(&self, space: SpaceId) -> Option<Duration> {
+        self.space(space).latest_rtt
+    }
+
     /// Whether explicit congestion notification is in use on outgoing packets.
     pub fn using_ecn(&self) -> bool {
-        self.sending_ecn
+        self.ecn_state != EcnState::Failed
+    }
+
+    /// Progress of explicit congestion notification validation on this connection's path
+    pub fn ecn_state(&self) -> EcnState {
+        self.ecn_state
     }
 
     fn max_ack_delay(&self) -> Duration {
@@ -2929,6 +4280,7 @@ impl Connection {
             || self.ping_pending
             || self.path_response.is_some()
             || !self.offpath_responses.is_empty()
+            || self.path_probe.map_or(false, |x| x.pending)
     }
 
     /// Reset state to account for 0-RTT being ignored by the server
@@ -2936,6 +4288,9 @@ impl Connection {
         debug_assert!(self.side.is_client());
         debug!(self.log, "0-RTT rejected");
         self.accepted_0rtt = false;
+        self.rejected_0rtt = true;
+        self.rejected_0rtt_streams = (self.streams.next_bi, self.streams.next_uni);
+        self.events.push_back(Event::ZeroRttRejected);
         // Reset all outgoing streams
         for i in 0..self.streams.next_bi {
             self.streams
@@ -2961,8 +4316,43 @@ impl Connection {
         for (_, packet) in sent_packets {
             self.in_flight.remove(&packet);
         }
+        // Stream data sent or queued in 0-RTT is gone for good, so must no longer count against the
+        // send window
         self.data_sent = 0;
+        self.unacked_data = 0;
         self.blocked_streams.clear();
+        self.check_send_low_water();
+    }
+
+    /// Whether `stream` was one of ours discarded by `reject_0rtt`
+    fn opened_in_rejected_0rtt(&self, stream: StreamId) -> bool {
+        if !self.rejected_0rtt || stream.initiator() != self.side {
+            return false;
+        }
+        let (bi, uni) = self.rejected_0rtt_streams;
+        let count = match stream.directionality() {
+            Directionality::Bi => bi,
+            Directionality::Uni => uni,
+        };
+        stream.index() < count
+    }
+}
+
+/// Type of `frame`, if known
+fn frame_type(frame: &Frame) -> Option<frame::Type> {
+    match *frame {
+        Frame::Invalid { ty, .. } => ty,
+        _ => Some(frame.ty()),
+    }
+}
+
+/// Describe a malformed frame encountered at `offset` within a packet's payload
+fn invalid_frame(ty: Option<frame::Type>, offset: usize, reason: &str) -> TransportError {
+    TransportError {
+        code: TransportErrorCode::FRAME_ENCODING_ERROR,
+        frame: ty,
+        space: None,
+        reason: format!("{} at offset {}", reason, offset),
     }
 }
 
@@ -3005,7 +4395,9 @@ struct Retransmits {
     max_data: bool,
     max_uni_stream_id: bool,
     max_bi_stream_id: bool,
-    stream: VecDeque<frame::Stream>,
+    uni_stream_blocked: bool,
+    bi_stream_blocked: bool,
+    stream: StreamQueue,
     rst_stream: Vec<(StreamId, u16)>,
     stop_sending: Vec<(StreamId, u16)>,
     max_stream_data: FnvHashSet<StreamId>,
@@ -3015,10 +4407,28 @@ struct Retransmits {
 }
 
 impl Retransmits {
+    /// Approximate memory held by queued CRYPTO and control frames, excluding stream data
+    fn control_bytes(&self) -> u64 {
+        let crypto = self.crypto_bytes();
+        let frames = self.rst_stream.len()
+            + self.stop_sending.len()
+            + self.max_stream_data.len()
+            + self.new_cids.len()
+            + self.retire_cids.len();
+        crypto + frames as u64 * CONTROL_FRAME_COST
+    }
+
+    /// Bytes of queued CRYPTO data
+    fn crypto_bytes(&self) -> u64 {
+        self.crypto.iter().map(|x| x.data.len() as u64).sum()
+    }
+
     fn is_empty(&self) -> bool {
         !self.max_data
             && !self.max_uni_stream_id
             && !self.max_bi_stream_id
+            && !self.uni_stream_blocked
+            && !self.bi_stream_blocked
             && self.stream.is_empty()
             && self.rst_stream.is_empty()
             && self.stop_sending.is_empty()
@@ -3035,7 +4445,9 @@ impl Default for Retransmits {
             max_data: false,
             max_uni_stream_id: false,
             max_bi_stream_id: false,
-            stream: VecDeque::new(),
+            uni_stream_blocked: false,
+            bi_stream_blocked: false,
+            stream: StreamQueue::default(),
             rst_stream: Vec::new(),
             stop_sending: Vec::new(),
             max_stream_data: FnvHashSet::default(),
@@ -3053,7 +4465,9 @@ impl ::std::ops::AddAssign for Retransmits {
         self.max_data |= rhs.max_data;
         self.max_uni_stream_id |= rhs.max_uni_stream_id;
         self.max_bi_stream_id |= rhs.max_bi_stream_id;
-        for stream in rhs.stream.into_iter().rev() {
+        self.uni_stream_blocked |= rhs.uni_stream_blocked;
+        self.bi_stream_blocked |= rhs.bi_stream_blocked;
+        for stream in rhs.stream.into_frames().rev() {
             self.stream.push_front(stream);
         }
         self.rst_stream.extend_from_slice(&rhs.rst_stream);
@@ -3067,6 +4481,71 @@ impl ::std::ops::AddAssign for Retransmits {
     }
 }
 
+/// STREAM frames queued for transmission, grouped by stream for round-robin scheduling
+#[derive(Debug, Clone, Default)]
+struct StreamQueue {
+    /// Streams with frames queued, in the order they'll take turns
+    streams: VecDeque<QueuedStream>,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedStream {
+    id: StreamId,
+    /// Bytes remaining in this stream's current turn, if it has begun
+    deficit: u64,
+    /// Never empty
+    frames: VecDeque<frame::Stream>,
+}
+
+impl StreamQueue {
+    /// Queue `frame` after any other data from the same stream
+    fn push_back(&mut self, frame: frame::Stream) {
+        match self.streams.iter_mut().find(|x| x.id == frame.id) {
+            Some(stream) => stream.frames.push_back(frame),
+            None => self.streams.push_back(QueuedStream::new(frame)),
+        }
+    }
+
+    /// Queue `frame` before any other data from the same stream
+    ///
+    /// A stream that had nothing queued is served next.
+    fn push_front(&mut self, frame: frame::Stream) {
+        match self.streams.iter_mut().find(|x| x.id == frame.id) {
+            Some(stream) => stream.frames.push_front(frame),
+            None => self.streams.push_front(QueuedStream::new(frame)),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &frame::Stream> {
+        self.streams.iter().flat_map(|x| x.frames.iter())
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut frame::Stream> {
+        self.streams.iter_mut().flat_map(|x| x.frames.iter_mut())
+    }
+
+    fn into_frames(self) -> impl DoubleEndedIterator<Item = frame::Stream> {
+        self.streams.into_iter().flat_map(|x| x.frames)
+    }
+}
+
+impl QueuedStream {
+    fn new(frame: frame::Stream) -> Self {
+        let mut frames = VecDeque::new();
+        let id = frame.id;
+        frames.push_back(frame);
+        Self {
+            id,
+            deficit: 0,
+            frames,
+        }
+    }
+}
+
 impl ::std::iter::FromIterator<Retransmits> for Retransmits {
     fn from_iter<T>(iter: T) -> Self
     where
@@ -3080,6 +4559,32 @@ impl ::std::iter::FromIterator<Retransmits> for Retransmits {
     }
 }
 
+/// Explicit congestion notification (ECN) validation state of a connection
+///
+/// Packets are marked from the start of a connection. Any inconsistency in the peer's reported
+/// ECN counts, including failure to report them at all, silently disables marking for the rest of
+/// the connection without otherwise affecting it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EcnState {
+    /// Marked packets have not yet been acknowledged, so support for ECN is unknown
+    Testing,
+    /// The peer's ECN counts have confirmed that marked packets arrive intact
+    Validated,
+    /// The path or peer mishandled ECN, so packets are no longer marked
+    Failed,
+}
+
+/// A snapshot of a connection's round-trip time estimate
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RttInfo {
+    /// Smoothed RTT, as returned by `Connection::rtt`
+    pub smoothed: Duration,
+    /// Mean deviation of RTT samples from the smoothed RTT, computed as described in RFC6298
+    pub var: Duration,
+    /// The minimum RTT seen on the connection, ignoring ack delay
+    pub min: Duration,
+}
+
 /// Reasons why a connection might be lost.
 #[derive(Debug, Clone, Error)]
 pub enum ConnectionError {
@@ -3103,6 +4608,43 @@ pub enum ConnectionError {
     TimedOut,
 }
 
+impl ConnectionError {
+    /// Whether a new connection to the same peer might reasonably be expected to succeed
+    ///
+    /// Timeouts, resets, and transient conditions such as the peer being too busy are retryable.
+    /// Version mismatches, protocol violations, and closes requested by the peer's application are
+    /// not, since another attempt is likely to fail in the same way.
+    pub fn is_retryable(&self) -> bool {
+        use self::ConnectionError::*;
+        match *self {
+            TimedOut | Reset => true,
+            ConnectionClosed { ref reason } => is_transient(reason.error_code),
+            TransportError(ref e) => is_transient(e.code),
+            VersionMismatch | ApplicationClosed { .. } => false,
+        }
+    }
+
+    /// The TLS alert that ended the handshake, whether raised locally or by the peer
+    ///
+    /// See `TransportErrorCode::tls_alert`. When the failure was detected locally, the error's
+    /// `reason` also holds the TLS implementation's description of what went wrong, such as which
+    /// certificate check failed.
+    pub fn tls_alert(&self) -> Option<u8> {
+        match *self {
+            ConnectionError::TransportError(ref e) => e.code.tls_alert(),
+            ConnectionError::ConnectionClosed { ref reason } => reason.error_code.tls_alert(),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a transport error code reflects a condition that may not persist across connections
+fn is_transient(code: TransportErrorCode) -> bool {
+    code == TransportErrorCode::NO_ERROR
+        || code == TransportErrorCode::INTERNAL_ERROR
+        || code == TransportErrorCode::SERVER_BUSY
+}
+
 impl From<TransportError> for ConnectionError {
     fn from(x: TransportError) -> Self {
         ConnectionError::TransportError(x)
@@ -3169,6 +4711,14 @@ impl State {
         }
     }
 
+    fn is_draining(&self) -> bool {
+        if let State::Draining = *self {
+            true
+        } else {
+            false
+        }
+    }
+
     fn is_drained(&self) -> bool {
         if let State::Drained = *self {
             true
@@ -3232,6 +4782,9 @@ struct SentPacket {
     size: u16,
     /// Whether an acknowledgement is expected directly in response to this packet.
     ack_eliciting: bool,
+    /// Whether the congestion window had room to spare after this packet, with nothing more
+    /// waiting to be sent
+    app_limited: bool,
     /// Whether the packet contains cryptographic handshake messages critical to the completion of
     /// the QUIC handshake.
     // FIXME: Implied by retransmits + space
@@ -3243,6 +4796,22 @@ struct SentPacket {
 /// Ensures we can always fit all our ACKs in a single minimum-MTU packet with room to spare
 const MAX_ACK_BLOCKS: usize = 64;
 
+/// Maximum amount of CRYPTO data tolerated at a stale encryption level in lenient mode
+const MAX_STALE_CRYPTO: u64 = 4096;
+
+/// Bound on the lost packet ranges remembered per space for measuring reordering
+const MAX_DECLARED_LOST_RANGES: usize = 64;
+
+/// Number of bytes a stream may send per turn when other streams also have data queued
+const STREAM_QUANTUM: u64 = 512;
+
+/// Memory charged for each queued control frame against `TransportConfig::max_retransmit_buffer`
+///
+/// An estimate rather than an exact figure: the in-memory representations range from a few bytes
+/// for a MAX_STREAM_DATA stream ID to over 40 for a NEW_CONNECTION_ID frame, plus the overhead of
+/// the containers holding them.
+const CONTROL_FRAME_COST: u64 = 32;
+
 /// I/O operations to be immediately executed the backend.
 #[derive(Debug)]
 pub enum Io {
@@ -3264,6 +4833,8 @@ struct IoQueue {
     /// Note that this ordering exactly matches the values of the `Timer` enum for convenient
     /// indexing.
     timers: [Option<TimerSetting>; Timer::COUNT],
+    /// Expiry time of each currently running timer, regardless of whether the change was reported
+    deadlines: [Option<Instant>; Timer::COUNT],
     retired_cids: Vec<ConnectionId>,
 }
 
@@ -3273,6 +4844,7 @@ impl IoQueue {
             probes: 0,
             close: false,
             timers: [None; Timer::COUNT],
+            deadlines: [None; Timer::COUNT],
             retired_cids: Vec::new(),
         }
     }
@@ -3280,11 +4852,13 @@ impl IoQueue {
     /// Start or reset a timer associated with this connection.
     fn timer_start(&mut self, timer: Timer, time: Instant) {
         self.timers[timer as usize] = Some(TimerSetting::Start(time));
+        self.deadlines[timer as usize] = Some(time);
     }
 
     /// Start one of the timers associated with this connection.
     fn timer_stop(&mut self, timer: Timer) {
         self.timers[timer as usize] = Some(TimerSetting::Stop);
+        self.deadlines[timer as usize] = None;
     }
 }
 
@@ -3318,6 +4892,12 @@ struct PacketSpace {
     pending_acks: RangeSet,
     /// Set iff we have received a non-ack frame since the last ack-only packet we sent
     permit_ack_only: bool,
+    /// Number of ack-eliciting packets received since we last sent an ACK
+    unacked_ack_eliciting: u64,
+    /// Whether the most recently authenticated packet arrived out of order or revealed a gap
+    rx_reordered: bool,
+    /// Recently sent packets that were deemed lost, in case they turn out to have been reordered
+    declared_lost: RangeSet,
 
     /// The packet number of the next packet that will be sent, if any.
     next_packet_number: u64,
@@ -3334,6 +4914,8 @@ struct PacketSpace {
     /// distinguishing between ECN bleaching and counts having been updated by a near-simultaneous
     /// ACK already processed in another space.
     ecn_feedback: frame::EcnCounts,
+    /// The most recent RTT sample taken from an ACK in this space, adjusted for ack delay
+    latest_rtt: Option<Duration>,
 
     /// Incoming cryptographic handshake stream
     crypto_stream: stream::Assembler,
@@ -3342,22 +4924,26 @@ struct PacketSpace {
 }
 
 impl PacketSpace {
-    fn new() -> Self {
+    fn new(dedup_window: u64) -> Self {
         Self {
             crypto: None,
-            dedup: Dedup::new(),
+            dedup: Dedup::with_window(dedup_window),
             rx_packet: 0,
             rx_packet_time: Instant::now(),
 
             pending: Retransmits::default(),
             pending_acks: RangeSet::new(),
             permit_ack_only: false,
+            unacked_ack_eliciting: 0,
+            rx_reordered: false,
+            declared_lost: RangeSet::new(),
 
             next_packet_number: 0,
             largest_acked_packet: 0,
             largest_acked_packet_sent: Instant::now(),
             sent_packets: BTreeMap::new(),
             ecn_feedback: frame::EcnCounts::ZERO,
+            latest_rtt: None,
 
             crypto_stream: stream::Assembler::new(),
             crypto_offset: 0,
@@ -3377,10 +4963,14 @@ impl PacketSpace {
     }
 
     /// Verifies sanity of an ECN block and returns whether congestion was encountered.
+    ///
+    /// `codepoint` is the ECT codepoint our packets were marked with; feedback reporting the other
+    /// ECT codepoint indicates the path or peer is mangling the marks.
     fn detect_ecn(
         &mut self,
         newly_acked: u64,
         ecn: frame::EcnCounts,
+        codepoint: EcnCodepoint,
     ) -> Result<bool, &'static str> {
         let ect0_increase = ecn
             .ect0
@@ -3398,12 +4988,17 @@ impl PacketSpace {
         if total_increase < newly_acked {
             return Err("ECN bleaching");
         }
-        if (ect0_increase + ce_increase) < newly_acked || ect1_increase != 0 {
+        let (sent_increase, other_increase) = match codepoint {
+            EcnCodepoint::ECT0 => (ect0_increase, ect1_increase),
+            EcnCodepoint::ECT1 => (ect1_increase, ect0_increase),
+            EcnCodepoint::CE => unreachable!("CE is never sent"),
+        };
+        if (sent_increase + ce_increase) < newly_acked || other_increase != 0 {
             return Err("ECN corruption");
         }
         // If total_increase > newly_acked (which happens when ACKs are lost), this is required by
         // the draft so that long-term drift does not occur. If =, then the only question is whether
-        // to count CE packets as CE or ECT0. Recording them as CE is more consistent and keeps the
+        // to count CE packets as CE or ECT. Recording them as CE is more consistent and keeps the
         // congestion check obvious.
         self.ecn_feedback = ecn;
         Ok(ce_increase != 0)
@@ -3447,6 +5042,9 @@ struct InFlight {
     /// considered "in flight" by congestion control. However, if this is nonzero, bytes will always
     /// also be nonzero.
     ack_eliciting: u64,
+    /// Approximate memory held by CRYPTO and control frames in these packets, as given by
+    /// `Retransmits::control_bytes`
+    control: u64,
 }
 
 impl InFlight {
@@ -3455,6 +5053,7 @@ impl InFlight {
             bytes: 0,
             crypto: 0,
             ack_eliciting: 0,
+            control: 0,
         }
     }
 
@@ -3462,6 +5061,7 @@ impl InFlight {
         self.bytes += packet.size as u64;
         self.crypto += packet.is_crypto_packet as u64;
         self.ack_eliciting += packet.ack_eliciting as u64;
+        self.control += packet.retransmits.control_bytes();
     }
 
     /// Update counters to account for a packet becoming acknowledged, lost, or abandoned
@@ -3469,6 +5069,7 @@ impl InFlight {
         self.bytes -= packet.size as u64;
         self.crypto -= packet.is_crypto_packet as u64;
         self.ack_eliciting -= packet.ack_eliciting as u64;
+        self.control -= packet.retransmits.control_bytes();
     }
 }
 
@@ -3517,12 +5118,31 @@ impl RttEstimator {
     }
 }
 
+#[derive(Copy, Clone)]
+struct PathProbe {
+    remote: SocketAddr,
+    token: u64,
+    /// Whether the PATH_CHALLENGE has yet to be sent
+    pending: bool,
+}
+
 struct PathResponse {
     /// The packet number the corresponding PATH_CHALLENGE was received in
     packet: u64,
     token: u64,
 }
 
+/// The exponent by which the delay in an ACK frame sent in `space` is scaled
+///
+/// `advertised` is the value of the sender's transport parameter, which applies only to
+/// application data; acks of handshake packets may be sent before it is known.
+fn ack_delay_exponent(space: SpaceId, advertised: u64) -> u64 {
+    match space {
+        SpaceId::Data => advertised,
+        _ => TransportParameters::default().ack_delay_exponent,
+    }
+}
+
 fn micros_from(x: Duration) -> u64 {
     x.as_secs() * 1000 * 1000 + x.subsec_micros() as u64
 }
@@ -11,9 +11,11 @@ use ring::digest;
 use ring::hkdf;
 use ring::hmac::{self, SigningKey};
 use rustls::quic::{ClientQuicExt, Secrets, ServerQuicExt};
-use rustls::ProtocolVersion;
+use rustls::{ProtocolVersion, SupportedCipherSuite};
 pub use rustls::{Certificate, NoClientAuth, PrivateKey, TLSError};
 pub use rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession, Session};
+pub(crate) use rustls::NoServerSessionStorage;
+use rustls::ProducesTickets;
 use webpki::DNSNameRef;
 
 use crate::coding::{BufExt, BufMutExt};
@@ -59,6 +61,14 @@ impl CryptoSession for TlsSession {
         }
     }
 
+    fn negotiated_cipher_suite(&self) -> Option<&'static SupportedCipherSuite> {
+        self.get_negotiated_ciphersuite()
+    }
+
+    fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.get_protocol_version()
+    }
+
     fn read_handshake(&mut self, buf: &[u8]) -> Result<(), TransportError> {
         self.read_hs(buf).map_err(|e| {
             if let Some(alert) = self.get_alert() {
@@ -105,6 +115,8 @@ pub trait CryptoSession {
     fn early_crypto(&self) -> Option<Crypto>;
     fn early_data_accepted(&self) -> Option<bool>;
     fn is_handshaking(&self) -> bool;
+    fn negotiated_cipher_suite(&self) -> Option<&'static SupportedCipherSuite>;
+    fn protocol_version(&self) -> Option<ProtocolVersion>;
     fn read_handshake(&mut self, buf: &[u8]) -> Result<(), TransportError>;
     fn sni_hostname(&self) -> Option<&str>;
     fn transport_parameters(&self) -> Result<Option<TransportParameters>, TransportError>;
@@ -174,14 +186,30 @@ pub fn build_server_config() -> ServerConfig {
     cfg
 }
 
+/// Ticketer that never issues stateless session tickets
+pub(crate) struct NoTickets;
+
+impl ProducesTickets for NoTickets {
+    fn enabled(&self) -> bool {
+        false
+    }
+    fn get_lifetime(&self) -> u32 {
+        0
+    }
+    fn encrypt(&self, _: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+    fn decrypt(&self, _: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
 fn to_vec(side: Side, params: &TransportParameters) -> Vec<u8> {
     let mut bytes = Vec::new();
     params.write(side, &mut bytes);
     bytes
 }
 
-/// Value used in ACKs we transmit
-pub const ACK_DELAY_EXPONENT: u8 = 3;
 /// Magic value used to indicate 0-RTT support in NewSessionTicket
 //pub const TLS_MAX_EARLY_DATA: u32 = 0xffff_ffff;
 
@@ -335,16 +363,72 @@ impl Crypto {
         }
     }
 
+    /// Reconstruct keys from secrets exported alongside a TLS 1.3 cipher suite identifier
+    pub fn from_suite(side: Side, suite: u16, local: Vec<u8>, remote: Vec<u8>) -> Option<Self> {
+        let (digest, cipher) = match suite {
+            0x1301 => (&digest::SHA256, &aead::AES_128_GCM),
+            0x1302 => (&digest::SHA384, &aead::AES_256_GCM),
+            0x1303 => (&digest::SHA256, &aead::CHACHA20_POLY1305),
+            _ => return None,
+        };
+        let secrets = if side.is_client() {
+            Secrets {
+                client: local,
+                server: remote,
+            }
+        } else {
+            Secrets {
+                client: remote,
+                server: local,
+            }
+        };
+        Some(Self::new(side, digest, cipher, secrets))
+    }
+
+    /// TLS 1.3 cipher suite identifier of the algorithms in use
+    pub fn suite(&self) -> u16 {
+        let cipher = self.sealing_key.algorithm();
+        if cipher == &aead::AES_256_GCM {
+            0x1302
+        } else if cipher == &aead::CHACHA20_POLY1305 {
+            0x1303
+        } else {
+            0x1301
+        }
+    }
+
+    /// Secrets from which the local and remote keys are derived
+    pub fn secrets(&self) -> (&[u8], &[u8]) {
+        (&self.local_secret, &self.remote_secret)
+    }
+
     pub fn update(&self, side: Side, tls: &TlsSession) -> Self {
         let (client_secret, server_secret) = match side {
             Side::Client => (&self.local_secret, &self.remote_secret),
             Side::Server => (&self.remote_secret, &self.local_secret),
         };
+        let suite = match tls.get_negotiated_ciphersuite() {
+            Some(suite) => suite,
+            None => {
+                // Imported connections have a TLS session that took no part in the handshake
+                let secrets = Secrets {
+                    client: self.next_secret(client_secret),
+                    server: self.next_secret(server_secret),
+                };
+                return Self::new(side, self.digest, self.sealing_key.algorithm(), secrets);
+            }
+        };
         let secrets = tls.update_secrets(client_secret, server_secret);
-        let suite = tls.get_negotiated_ciphersuite().unwrap();
         Self::new(side, suite.get_hash(), suite.get_aead_alg(), secrets)
     }
 
+    fn next_secret(&self, secret: &[u8]) -> Vec<u8> {
+        let key = SigningKey::new(self.digest, secret);
+        let mut out = vec![0; self.digest.output_len];
+        hkdf_expand(&key, b"traffic upd", &mut out);
+        out
+    }
+
     pub fn tag_len(&self) -> usize {
         self.sealing_key.algorithm().tag_len()
     }
@@ -608,6 +692,34 @@ mod test {
         assert_eq!(&payload, &plaintext[HEADER_LEN..]);
     }
 
+    #[test]
+    fn chacha20_roundtrip() {
+        let secrets = || Secrets {
+            client: vec![0x42; 32],
+            server: vec![0x17; 32],
+        };
+        let (digest, cipher) = (&digest::SHA256, &aead::CHACHA20_POLY1305);
+        let client = Crypto::new(Side::Client, digest, cipher, secrets());
+        let client_header = client.header_crypto();
+        let server = Crypto::new(Side::Server, digest, cipher, secrets());
+        let server_header = server.header_crypto();
+        assert_eq!(client_header.sample_size(), 16);
+
+        // Short header with a two-byte packet number
+        const HEADER: &[u8] = &[0x41, 0x00, 0x07];
+        let mut packet = HEADER.to_vec();
+        packet.extend_from_slice(b"payload that is long enough to sample");
+        client.encrypt(7, &mut packet, HEADER.len());
+        client_header.encrypt(1, &mut packet);
+
+        server_header.decrypt(1, &mut packet);
+        let (header, payload) = packet.split_at(HEADER.len());
+        assert_eq!(header, HEADER);
+        let mut payload = BytesMut::from(payload);
+        server.decrypt(7, header, &mut payload).unwrap();
+        assert_eq!(&payload[..], &b"payload that is long enough to sample"[..]);
+    }
+
     #[test]
     fn token_sanity() {
         use std::net::Ipv6Addr;
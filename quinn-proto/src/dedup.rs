@@ -14,24 +14,42 @@ use std::{cmp, mem};
 /// window highest next
 /// ```
 pub struct Dedup {
-    window: Window,
+    /// Little-endian bitfield: bit `i` of the whole represents packet `highest - 1 - i`.
+    window: Box<[Word]>,
     /// Lowest packet number higher than all yet authenticated.
     next: u64,
 }
 
-/// Inner bitfield type.
+/// Inner bitfield word type.
 ///
-/// Because QUIC never reuses packet numbers, this only needs to be large enough to deal with
+/// Because QUIC never reuses packet numbers, the window only needs to be large enough to deal with
 /// packets that are reordered but still delivered in a timely manner.
-type Window = u128;
+type Word = u64;
 
-/// Number of packets tracked by `Dedup`.
-const WINDOW_SIZE: u64 = 1 + mem::size_of::<Window>() as u64 * 8;
+/// Number of bits in a `Word`.
+const WORD_BITS: u64 = mem::size_of::<Word>() as u64 * 8;
+
+/// Number of packets below the highest that are tracked by default.
+pub const DEFAULT_WINDOW: u64 = 2 * WORD_BITS;
 
 impl Dedup {
-    /// Construct an empty window positioned at the start.
+    /// Construct an empty window positioned at the start, tracking the default number of packets.
     pub fn new() -> Self {
-        Self { window: 0, next: 0 }
+        Self::with_window(DEFAULT_WINDOW)
+    }
+
+    /// Construct an empty window tracking at least `window` packets below the highest.
+    pub fn with_window(window: u64) -> Self {
+        let words = cmp::max(1, (window + WORD_BITS - 1) / WORD_BITS);
+        Self {
+            window: vec![0; words as usize].into_boxed_slice(),
+            next: 0,
+        }
+    }
+
+    /// Number of packets tracked, including the highest.
+    fn size(&self) -> u64 {
+        1 + self.window.len() as u64 * WORD_BITS
     }
 
     /// Highest packet number authenticated.
@@ -39,24 +57,33 @@ impl Dedup {
         self.next - 1
     }
 
+    /// Treat every packet number up to and including `packet` as already authenticated.
+    pub fn fill_to(&mut self, packet: u64) {
+        for word in self.window.iter_mut() {
+            *word = !0;
+        }
+        self.next = packet + 1;
+    }
+
     /// Record a newly authenticated packet number.
     ///
     /// Returns whether the packet might be a duplicate.
     pub fn insert(&mut self, packet: u64) -> bool {
         if let Some(diff) = packet.checked_sub(self.next) {
             // Right of window
-            self.window = (self.window << 1 | 1)
-                .checked_shl(cmp::min(diff, u64::from(u32::max_value())) as u32)
-                .unwrap_or(0);
+            self.shift(1);
+            self.window[0] |= 1;
+            self.shift(diff);
             self.next = packet + 1;
             false
-        } else if self.highest() - packet < WINDOW_SIZE {
+        } else if self.highest() - packet < self.size() {
             // Within window
             if let Some(bit) = (self.highest() - packet).checked_sub(1) {
                 // < highest
-                let mask = 1 << bit;
-                let duplicate = self.window & mask != 0;
-                self.window |= mask;
+                let word = &mut self.window[(bit / WORD_BITS) as usize];
+                let mask = 1 << (bit % WORD_BITS);
+                let duplicate = *word & mask != 0;
+                *word |= mask;
                 duplicate
             } else {
                 // == highest
@@ -67,6 +94,40 @@ impl Dedup {
             true
         }
     }
+
+    /// Move every bit `n` places towards older packets, discarding those that fall off the end.
+    fn shift(&mut self, n: u64) {
+        let words = self.window.len();
+        if n >= words as u64 * WORD_BITS {
+            for word in self.window.iter_mut() {
+                *word = 0;
+            }
+            return;
+        }
+        let word_shift = (n / WORD_BITS) as usize;
+        let bit_shift = (n % WORD_BITS) as u32;
+        // Iterate downwards so each source word is read before it's overwritten
+        for i in (0..words).rev() {
+            let high = i
+                .checked_sub(word_shift)
+                .map_or(0, |src| self.window[src] << bit_shift);
+            let low = match i.checked_sub(word_shift + 1) {
+                Some(src) if bit_shift != 0 => self.window[src] >> (WORD_BITS as u32 - bit_shift),
+                _ => 0,
+            };
+            self.window[i] = high | low;
+        }
+    }
+
+    /// The first 128 bits of the window, for testing.
+    #[cfg(test)]
+    fn bits(&self) -> u128 {
+        self.window
+            .iter()
+            .take(2)
+            .enumerate()
+            .fold(0, |acc, (i, &word)| acc | u128::from(word) << (i as u64 * WORD_BITS))
+    }
 }
 
 #[cfg(test)]
@@ -78,34 +139,36 @@ mod test {
         let mut dedup = Dedup::new();
         assert!(!dedup.insert(0));
         assert_eq!(dedup.next, 1);
-        assert_eq!(dedup.window, 0b1);
+        assert_eq!(dedup.bits(), 0b1);
         assert!(dedup.insert(0));
         assert_eq!(dedup.next, 1);
-        assert_eq!(dedup.window, 0b1);
+        assert_eq!(dedup.bits(), 0b1);
         assert!(!dedup.insert(1));
         assert_eq!(dedup.next, 2);
-        assert_eq!(dedup.window, 0b11);
+        assert_eq!(dedup.bits(), 0b11);
         assert!(!dedup.insert(2));
         assert_eq!(dedup.next, 3);
-        assert_eq!(dedup.window, 0b111);
+        assert_eq!(dedup.bits(), 0b111);
         assert!(!dedup.insert(4));
         assert_eq!(dedup.next, 5);
-        assert_eq!(dedup.window, 0b11110);
+        assert_eq!(dedup.bits(), 0b11110);
         assert!(!dedup.insert(7));
         assert_eq!(dedup.next, 8);
-        assert_eq!(dedup.window, 0b11110100);
+        assert_eq!(dedup.bits(), 0b11110100);
         assert!(dedup.insert(4));
         assert!(!dedup.insert(3));
         assert_eq!(dedup.next, 8);
-        assert_eq!(dedup.window, 0b11111100);
+        assert_eq!(dedup.bits(), 0b11111100);
         assert!(!dedup.insert(6));
         assert_eq!(dedup.next, 8);
-        assert_eq!(dedup.window, 0b11111101);
+        assert_eq!(dedup.bits(), 0b11111101);
         assert!(!dedup.insert(5));
         assert_eq!(dedup.next, 8);
-        assert_eq!(dedup.window, 0b11111111);
+        assert_eq!(dedup.bits(), 0b11111111);
     }
 
+    const WINDOW_SIZE: u64 = 1 + DEFAULT_WINDOW;
+
     #[test]
     fn happypath() {
         let mut dedup = Dedup::new();
@@ -123,9 +186,21 @@ mod test {
         dedup.insert(2 * WINDOW_SIZE);
         assert!(dedup.insert(WINDOW_SIZE));
         assert_eq!(dedup.next, 2 * WINDOW_SIZE + 1);
-        assert_eq!(dedup.window, 0);
+        assert_eq!(dedup.bits(), 0);
         assert!(!dedup.insert(WINDOW_SIZE + 1));
         assert_eq!(dedup.next, 2 * WINDOW_SIZE + 1);
-        assert_eq!(dedup.window, 1 << (WINDOW_SIZE - 2));
+        assert_eq!(dedup.bits(), 1 << (WINDOW_SIZE - 2));
+    }
+
+    #[test]
+    fn configured_window() {
+        let mut dedup = Dedup::with_window(256);
+        assert!(!dedup.insert(0));
+        assert!(!dedup.insert(300));
+        // The oldest packet still tracked
+        assert!(!dedup.insert(300 - 256));
+        assert!(dedup.insert(300 - 256));
+        // Too old to tell apart from a replay, so rejected regardless
+        assert!(dedup.insert(300 - 257));
     }
 }
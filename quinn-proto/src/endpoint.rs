@@ -1,6 +1,6 @@
 use std::cmp;
 use std::collections::VecDeque;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::{Index, IndexMut};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
@@ -15,6 +15,7 @@ use slab::Slab;
 use slog::{self, Logger};
 
 use crate::coding::BufMutExt;
+use crate::congestion::ControllerFactory;
 use crate::connection::{
     self, initial_close, ClientConfig, Connection, ConnectionError, TimerUpdate,
 };
@@ -22,12 +23,14 @@ use crate::crypto::{
     self, reset_token_for, Crypto, CryptoClientConfig, CryptoServerConfig, RingHeaderCrypto,
     TokenKey,
 };
+use crate::dedup;
+use crate::handoff::{ConnectionState, ExportError, ImportError};
 use crate::packet::{ConnectionId, EcnCodepoint, Header, Packet, PacketDecodeError, PartialDecode};
 use crate::stream::{ReadError, WriteError};
 use crate::transport_parameters::TransportParameters;
 use crate::{
-    varint, Directionality, Side, StreamId, Transmit, TransportError, MAX_CID_SIZE, MIN_CID_SIZE,
-    MIN_INITIAL_SIZE, RESET_TOKEN_SIZE, VERSION,
+    varint, Directionality, Side, StreamId, Transmit, TransportError, MAX_CID_SIZE,
+    MAX_STREAM_COUNT, MIN_CID_SIZE, MIN_INITIAL_SIZE, MIN_MTU, RESET_TOKEN_SIZE, VERSION,
 };
 
 /// The main entry point to the library
@@ -49,10 +52,22 @@ pub struct Endpoint {
     /// Connections that might have timer updates to apply perform
     dirty_timers: FnvHashSet<ConnectionHandle>,
     /// Connections that might have packets to send
-    needs_transmit: FnvHashSet<ConnectionHandle>,
+    needs_transmit: TransmitQueue,
     /// Connections that might have application-facing events to report
     eventful_conns: FnvHashSet<ConnectionHandle>,
     incoming_handshakes: usize,
+    /// Whether incoming connection attempts are refused regardless of `ServerConfig`
+    reject_new_connections: bool,
+    /// Initial destination CIDs of recently drained incoming connections, and when to forget them
+    ///
+    /// Initial packets from a client that was slow to notice the close would otherwise be taken as
+    /// fresh connection attempts.
+    drained_initial_cids: FnvHashMap<ConnectionId, Instant>,
+    /// `drained_initial_cids` entries in order of expiry
+    drained_expiry: VecDeque<(Instant, ConnectionId)>,
+    /// TLS configuration for incoming connections, adjusted to honor `ServerConfig::accept_0rtt`
+    /// and `ServerConfig::session_tickets`
+    server_tls: Option<Arc<crypto::ServerConfig>>,
 }
 
 impl Endpoint {
@@ -63,6 +78,21 @@ impl Endpoint {
     ) -> Result<Self, ConfigError> {
         config.validate()?;
         let rng = OsRng::new().unwrap();
+        let server_tls = server_config.as_ref().map(|server| {
+            let refuse_0rtt = !server.accept_0rtt && server.tls_config.max_early_data_size != 0;
+            if !refuse_0rtt && server.session_tickets {
+                return server.tls_config.clone();
+            }
+            let mut tls = (*server.tls_config).clone();
+            if refuse_0rtt {
+                tls.max_early_data_size = 0;
+            }
+            if !server.session_tickets {
+                tls.session_storage = Arc::new(crypto::NoServerSessionStorage {});
+                tls.ticketer = Arc::new(crypto::NoTickets);
+            }
+            Arc::new(tls)
+        });
         Ok(Self {
             log,
             rng,
@@ -73,9 +103,13 @@ impl Endpoint {
             connection_remotes: FnvHashMap::default(),
             connections: Slab::new(),
             dirty_timers: FnvHashSet::default(),
-            needs_transmit: FnvHashSet::default(),
+            needs_transmit: TransmitQueue::default(),
             eventful_conns: FnvHashSet::default(),
             incoming_handshakes: 0,
+            reject_new_connections: false,
+            drained_initial_cids: FnvHashMap::default(),
+            drained_expiry: VecDeque::new(),
+            server_tls,
             config,
             server_config,
         })
@@ -99,6 +133,17 @@ impl Endpoint {
         None
     }
 
+    /// Move all pending application-facing events into `events`
+    ///
+    /// Equivalent to calling `poll` until it returns `None`, but avoids per-event overhead when
+    /// many events are queued.
+    pub fn poll_events(&mut self, events: &mut Vec<(ConnectionHandle, Event)>) {
+        events.extend(self.incoming.drain(..).map(|ch| (ch, Event::Handshaking)));
+        for ch in self.eventful_conns.drain() {
+            events.extend(self.connections[ch].poll_events().map(|e| (ch, e)));
+        }
+    }
+
     /// Get a pending timer update
     pub fn poll_timers(&mut self) -> Option<(ConnectionHandle, TimerUpdate)> {
         loop {
@@ -111,9 +156,7 @@ impl Endpoint {
                             connection::Io::TimerUpdate(x) => x,
                             connection::Io::RetireConnectionId { connection_id } => {
                                 self.connection_ids.remove(&connection_id);
-                                let new_cid = self.new_cid();
-                                self.connection_ids.insert(new_cid, ch);
-                                self.connections[ch].issue_cid(new_cid);
+                                self.issue_cids(ch);
                                 continue;
                             }
                         },
@@ -131,15 +174,17 @@ impl Endpoint {
         if let Some(x) = self.transmits.pop_front() {
             return Some(x);
         }
-        loop {
-            let &ch = self.needs_transmit.iter().next()?;
+        while let Some(ch) = self.needs_transmit.pop() {
             if let Some(transmit) = self.connections[ch].poll_transmit(now) {
                 self.dirty_timers.insert(ch);
+                // Go to the back of the line so a busy connection can't starve the others
+                self.needs_transmit.insert(ch);
                 return Some(transmit);
-            } else {
-                self.needs_transmit.remove(&ch);
             }
+            // Running out of work may be reported as an event
+            self.eventful_conns.insert(ch);
         }
+        None
     }
 
     /// Process an incoming UDP datagram
@@ -174,6 +219,7 @@ impl Endpoint {
                 buf.write(VERSION); // supported version
                 self.transmits.push_back(Transmit {
                     destination: remote,
+                    source: None,
                     ecn: None,
                     packet: buf.into(),
                 });
@@ -238,6 +284,15 @@ impl Endpoint {
 
         if partial_decode.has_long_header() {
             if partial_decode.is_initial() {
+                self.purge_drained(now);
+                if self.drained_initial_cids.get(&dst_cid).map_or(false, |&x| x > now) {
+                    debug!(
+                        self.log,
+                        "ignoring initial for drained connection {connection}",
+                        connection = dst_cid
+                    );
+                    return;
+                }
                 if datagram_len < MIN_INITIAL_SIZE {
                     debug!(
                         self.log,
@@ -317,6 +372,7 @@ impl Endpoint {
 
         self.transmits.push_back(Transmit {
             destination: remote,
+            source: None,
             ecn: None,
             packet: buf.into(),
         });
@@ -347,9 +403,18 @@ impl Endpoint {
         Ok(ch)
     }
 
+    /// Generate a local connection ID, applying the configured `cid_generator`
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut cid = ConnectionId::random(&mut self.rng, self.config.local_cid_len);
+        if let Some(ref generator) = self.config.cid_generator {
+            generator(&mut cid);
+        }
+        cid
+    }
+
     fn new_cid(&mut self) -> ConnectionId {
         loop {
-            let cid = ConnectionId::random(&mut self.rng, self.config.local_cid_len);
+            let cid = self.generate_cid();
             if !self.connection_ids.contains_key(&cid) {
                 break cid;
             }
@@ -381,10 +446,9 @@ impl Endpoint {
                     ..params
                 };
                 (
-                    self.server_config
+                    self.server_tls
                         .as_ref()
                         .unwrap()
-                        .tls_config
                         .start_session(&server_params),
                     None,
                 )
@@ -448,14 +512,48 @@ impl Endpoint {
             return;
         };
 
-        // Local CID used for stateless packets
-        let temp_loc_cid = ConnectionId::random(&mut self.rng, self.config.local_cid_len);
         let server_config = self.server_config.as_ref().unwrap();
+        let at_capacity = server_config.max_connections != 0
+            && self.connections.len() >= server_config.max_connections as usize;
+        let action = if self.reject_new_connections || at_capacity {
+            IncomingAction::Refuse
+        } else {
+            match server_config.incoming_filter {
+                Some(ref filter) => filter(&remote, &dst_cid),
+                None => IncomingAction::Accept,
+            }
+        };
+        if action == IncomingAction::Ignore {
+            debug!(self.log, "ignoring connection attempt from {remote}", remote = remote);
+            return;
+        }
+
+        // Local CID used for stateless packets
+        let temp_loc_cid = self.generate_cid();
+
+        if action == IncomingAction::Refuse {
+            debug!(self.log, "refusing connection from {remote}", remote = remote);
+            self.transmits.push_back(Transmit {
+                destination: remote,
+                source: None,
+                ecn: None,
+                packet: initial_close(
+                    crypto,
+                    header_crypto,
+                    &src_cid,
+                    &temp_loc_cid,
+                    0,
+                    TransportError::SERVER_BUSY(""),
+                ),
+            });
+            return;
+        }
 
         if self.incoming_handshakes == server_config.accept_buffer as usize {
             debug!(self.log, "rejecting connection due to full accept buffer");
             self.transmits.push_back(Transmit {
                 destination: remote,
+                source: None,
                 ecn: None,
                 packet: initial_close(
                     crypto,
@@ -469,9 +567,8 @@ impl Endpoint {
             return;
         }
 
-        if dst_cid.len() < 8
-            && (!server_config.use_stateless_retry || dst_cid.len() != self.config.local_cid_len)
-        {
+        let use_retry = server_config.use_stateless_retry || action == IncomingAction::Retry;
+        if dst_cid.len() < 8 && (!use_retry || dst_cid.len() != self.config.local_cid_len) {
             debug!(
                 self.log,
                 "rejecting connection due to invalid DCID length {len}",
@@ -479,6 +576,7 @@ impl Endpoint {
             );
             self.transmits.push_back(Transmit {
                 destination: remote,
+                source: None,
                 ecn: None,
                 packet: initial_close(
                     crypto,
@@ -493,7 +591,7 @@ impl Endpoint {
         }
 
         let mut retry_cid = None;
-        if server_config.use_stateless_retry {
+        if use_retry {
             if let Some((token_dst_cid, token_issued)) =
                 server_config.token_key.check(&remote, &token)
             {
@@ -525,6 +623,7 @@ impl Endpoint {
 
                 self.transmits.push_back(Transmit {
                     destination: remote,
+                    source: None,
                     ecn: None,
                     packet: buf.into(),
                 });
@@ -567,6 +666,7 @@ impl Endpoint {
                 self.forget(ch);
                 self.transmits.push_back(Transmit {
                     destination: remote,
+                    source: None,
                     ecn: None,
                     packet: initial_close(crypto, header_crypto, &src_cid, &temp_loc_cid, 0, e),
                 });
@@ -580,15 +680,16 @@ impl Endpoint {
             self.incoming.push_back(ch);
         }
         if self.config.local_cid_len != 0 && !self.connections[ch].is_closed() {
-            /// Draft 17 §5.1.1: endpoints SHOULD provide and maintain at least eight
-            /// connection IDs
-            const LOCAL_CID_COUNT: usize = 8;
-            // We've already issued one CID as part of the normal handshake process.
-            for _ in 1..LOCAL_CID_COUNT {
-                let cid = self.new_cid();
-                self.connection_ids.insert(cid, ch);
-                self.connections[ch].issue_cid(cid);
-            }
+            self.issue_cids(ch);
+        }
+    }
+
+    /// Top up a connection's local CIDs to `EndpointConfig::local_cid_count`
+    fn issue_cids(&mut self, ch: ConnectionHandle) {
+        for _ in 0..self.connections[ch].cids_needed() {
+            let cid = self.new_cid();
+            self.connection_ids.insert(cid, ch);
+            self.connections[ch].issue_cid(cid);
         }
     }
 
@@ -610,18 +711,51 @@ impl Endpoint {
         self.connections.remove(ch.0);
     }
 
+    /// Forget a drained connection, remembering its initial CID for a while if it was incoming
+    ///
+    /// Late packets with the connection's other CIDs need no such care: short-header packets are
+    /// answered with a stateless reset and other long-header packets are ignored.
+    fn forget_drained(&mut self, now: Instant, ch: ConnectionHandle) {
+        if self.connections[ch].side().is_server() {
+            self.purge_drained(now);
+            let expiry = now + self.connections[ch].draining_period();
+            let cid = self.connections[ch].init_cid;
+            self.drained_initial_cids.insert(cid, expiry);
+            self.drained_expiry.push_back((expiry, cid));
+        }
+        self.forget(ch);
+    }
+
+    /// Discard drained initial CIDs that are no longer worth remembering
+    fn purge_drained(&mut self, now: Instant) {
+        while let Some(&(expiry, cid)) = self.drained_expiry.front() {
+            if expiry > now {
+                break;
+            }
+            self.drained_expiry.pop_front();
+            if self.drained_initial_cids.get(&cid) == Some(&expiry) {
+                self.drained_initial_cids.remove(&cid);
+            }
+        }
+    }
+
     /// Handle a timer expiring
     pub fn timeout(&mut self, now: Instant, ch: ConnectionHandle, timer: Timer) {
         if self.connections[ch].timeout(now, timer) {
-            self.forget(ch);
+            self.forget_drained(now, ch);
             return;
         }
         self.dirty_timers.insert(ch);
         match timer {
-            Timer::LossDetection | Timer::KeepAlive => {
+            Timer::LossDetection => {
+                self.needs_transmit.insert(ch);
+                // Detecting losses may reduce the congestion window, which is reported as an event
+                self.eventful_conns.insert(ch);
+            }
+            Timer::KeepAlive | Timer::AckDelay => {
                 self.needs_transmit.insert(ch);
             }
-            Timer::Idle => {
+            Timer::Idle | Timer::PathProbe => {
                 self.eventful_conns.insert(ch);
             }
             Timer::PathValidation | Timer::Close | Timer::KeyDiscard => {}
@@ -709,6 +843,9 @@ impl Endpoint {
 
     /// Abandon transmitting data on a stream
     ///
+    /// The receiving half of a bidirectional stream is unaffected, so data from the peer can
+    /// still be read.
+    ///
     /// # Panics
     /// - when applied to a receive stream or an unopened send stream
     pub fn reset(&mut self, ch: ConnectionHandle, stream: StreamId, error_code: u16) {
@@ -716,8 +853,31 @@ impl Endpoint {
         self.needs_transmit.insert(ch);
     }
 
+    /// Permit the peer to send more data after processing data read from `stream`
+    ///
+    /// See `Connection::release_credit`.
+    pub fn release_credit(&mut self, ch: ConnectionHandle, stream: StreamId, bytes: u64) {
+        self.connections[ch].release_credit(stream, bytes);
+        self.needs_transmit.insert(ch);
+    }
+
+    /// See `Connection::grant_credit_now`.
+    pub fn grant_credit_now(&mut self, ch: ConnectionHandle) {
+        self.connections[ch].grant_credit_now();
+        self.needs_transmit.insert(ch);
+    }
+
+    /// Hint the expected total size of an incoming stream so its buffers can be preallocated
+    pub fn reserve_recv(&mut self, ch: ConnectionHandle, stream: StreamId, size: u64) {
+        self.connections[ch].reserve_recv(stream, size);
+    }
+
     /// Instruct the peer to abandon transmitting data on a stream
     ///
+    /// Buffered data is discarded immediately. Data the peer sent before learning of this is
+    /// discarded on arrival, with its flow control credit returned to the connection. The sending
+    /// half of a bidirectional stream is unaffected.
+    ///
     /// # Panics
     /// - when applied to a stream that has not begun receiving data
     pub fn stop_sending(&mut self, ch: ConnectionHandle, stream: StreamId, error_code: u16) {
@@ -728,9 +888,86 @@ impl Endpoint {
     /// Create a new stream
     ///
     /// Returns `None` if the maximum number of streams currently permitted by the remote endpoint
-    /// are already open.
+    /// are already open, in which case the peer is informed that we're blocked and a
+    /// `StreamAvailable` event will be emitted once it raises the limit. The peer is under no
+    /// obligation to ever do so, so callers waiting for that event should bound the wait.
     pub fn open(&mut self, ch: ConnectionHandle, direction: Directionality) -> Option<StreamId> {
-        self.connections[ch].open(direction)
+        let id = self.connections[ch].open(direction);
+        if id.is_none() {
+            self.needs_transmit.insert(ch);
+        }
+        id
+    }
+
+    /// Stop permitting the peer to open new streams on a connection
+    ///
+    /// See `Connection::stop_accepting_streams`.
+    pub fn stop_accepting_streams(&mut self, ch: ConnectionHandle) {
+        self.connections[ch].stop_accepting_streams();
+    }
+
+    /// Change the keep-alive interval of a connection
+    ///
+    /// See `Connection::set_keep_alive`.
+    pub fn set_keep_alive(
+        &mut self,
+        now: Instant,
+        ch: ConnectionHandle,
+        interval: Option<Duration>,
+    ) {
+        self.connections[ch].set_keep_alive(now, interval);
+        self.dirty_timers.insert(ch);
+    }
+
+    /// Fix the length of a connection's closing and draining periods
+    ///
+    /// See `Connection::set_draining_period`.
+    pub fn set_draining_period(&mut self, ch: ConnectionHandle, period: Option<Duration>) {
+        self.connections[ch].set_draining_period(period);
+    }
+
+    /// See `Connection::set_local_ip`
+    pub fn set_local_ip(&mut self, ch: ConnectionHandle, ip: Option<IpAddr>) {
+        self.connections[ch].set_local_ip(ip);
+    }
+
+    /// See `Connection::set_packet_threshold`
+    pub fn set_packet_threshold(&mut self, ch: ConnectionHandle, value: u32) {
+        self.connections[ch].set_packet_threshold(value);
+    }
+
+    /// See `Connection::set_time_threshold`
+    pub fn set_time_threshold(&mut self, ch: ConnectionHandle, value: u16) {
+        self.connections[ch].set_time_threshold(value);
+    }
+
+    /// See `Connection::set_loss_reduction_factor`
+    pub fn set_loss_reduction_factor(&mut self, ch: ConnectionHandle, value: u16) {
+        self.connections[ch].set_loss_reduction_factor(value);
+    }
+
+    /// See `Connection::set_persistent_congestion_threshold`
+    pub fn set_persistent_congestion_threshold(&mut self, ch: ConnectionHandle, value: u32) {
+        self.connections[ch].set_persistent_congestion_threshold(value);
+    }
+
+    /// See `Connection::set_minimum_window`
+    pub fn set_minimum_window(&mut self, ch: ConnectionHandle, value: u64) {
+        self.connections[ch].set_minimum_window(value);
+    }
+
+    /// Change the largest UDP payload sent on a connection
+    ///
+    /// See `Connection::set_mtu`.
+    pub fn set_mtu(&mut self, ch: ConnectionHandle, mtu: u16) {
+        self.connections[ch].set_mtu(mtu);
+    }
+
+    /// Seed a new connection's congestion control from a previous connection to the same peer
+    ///
+    /// See `Connection::warm_up`.
+    pub fn warm_up(&mut self, now: Instant, ch: ConnectionHandle, bdp: u64) {
+        self.connections[ch].warm_up(now, bdp);
     }
 
     /// Ping the remote endpoint
@@ -741,13 +978,106 @@ impl Endpoint {
         self.needs_transmit.insert(ch);
     }
 
+    /// Check whether the peer can be reached at `remote`, without migrating to it
+    ///
+    /// See `Connection::probe_path`.
+    pub fn probe_path(&mut self, now: Instant, ch: ConnectionHandle, remote: SocketAddr) {
+        self.connections[ch].probe_path(now, remote);
+        self.needs_transmit.insert(ch);
+        self.dirty_timers.insert(ch);
+        // The probe may fail immediately
+        self.eventful_conns.insert(ch);
+    }
+
+    /// Remove an idle connection from this endpoint, capturing its state for another process
+    ///
+    /// On success the connection is forgotten without notifying the peer, so that its keys and
+    /// packet numbers are only ever used by the importing process, and no further events are
+    /// reported for it. See `Connection::export_state`.
+    pub fn export_state(&mut self, ch: ConnectionHandle) -> Result<ConnectionState, ExportError> {
+        let state = self.connections[ch].export_state()?;
+        self.forget(ch);
+        Ok(state)
+    }
+
+    /// Resume a connection exported by another endpoint
+    ///
+    /// This endpoint must be a server sharing the exporting endpoint's `EndpointConfig`, so that
+    /// connection IDs are routed here and stateless resets remain valid.
+    pub fn import_state(
+        &mut self,
+        now: Instant,
+        state: ConnectionState,
+        transport_config: Arc<TransportConfig>,
+    ) -> Result<ConnectionHandle, ImportError> {
+        transport_config.validate(&self.log)?;
+        let local_cid_len = self.config.local_cid_len;
+        let conflict = state.loc_cids.iter().any(|&(_, ref cid)| {
+            cid.len() != local_cid_len || (cid.len() > 0 && self.connection_ids.contains_key(cid))
+        });
+        if conflict || self.connection_remotes.contains_key(&state.remote) {
+            return Err(ImportError::ConnectionIdConflict);
+        }
+        let crypto = Crypto::from_suite(
+            Side::Server,
+            state.suite,
+            state.local_secret.clone(),
+            state.remote_secret.clone(),
+        )
+        .ok_or(ImportError::Malformed)?;
+
+        let loc_cid = state.loc_cids[0].1;
+        let params = TransportParameters {
+            stateless_reset_token: Some(reset_token_for(&self.config.reset_key, &loc_cid)),
+            ..TransportParameters::new(&transport_config)
+        };
+        let tls = self
+            .server_tls
+            .as_ref()
+            .ok_or(ImportError::NotServer)?
+            .start_session(&params);
+        let loc_cids = state.loc_cids.iter().map(|&(_, cid)| cid).collect::<Vec<_>>();
+        let remote = state.remote;
+        let id = self.connections.insert(Connection::import(
+            self.log.new(o!("connection" => loc_cid)),
+            Arc::clone(&self.config),
+            transport_config,
+            state,
+            crypto,
+            tls,
+            now,
+        ));
+        let ch = ConnectionHandle(id);
+
+        if local_cid_len > 0 {
+            for cid in loc_cids {
+                self.connection_ids.insert(cid, ch);
+            }
+        }
+        self.connection_remotes.insert(remote, ch);
+        self.dirty_timers.insert(ch);
+        Ok(ch)
+    }
+
+    /// Number of connections currently held by the endpoint, in any state
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Refuse all future incoming connection attempts
+    ///
+    /// Existing connections are unaffected. Useful when shutting down a server.
+    pub fn reject_new_connections(&mut self) {
+        self.reject_new_connections = true;
+    }
+
     /// Close a connection immediately
     ///
     /// This does not ensure delivery of outstanding data. It is the application's responsibility
     /// to call this only when all important communications have been completed.
     pub fn close(&mut self, now: Instant, ch: ConnectionHandle, error_code: u16, reason: Bytes) {
         if self.connections[ch].is_drained() {
-            self.forget(ch);
+            self.forget_drained(now, ch);
             return;
         }
         self.connections[ch].close(now, error_code, reason);
@@ -790,6 +1120,7 @@ impl Endpoint {
 /// link with a 100ms round trip time, with remote endpoints opening at most 320 new streams per
 /// second. Applications which do not require remotely-initiated streams should set the stream
 /// windows to zero.
+#[derive(Clone)]
 pub struct TransportConfig {
     /// Maximum number of bidirectional streams that may be initiated by the peer but not yet
     /// accepted locally
@@ -837,22 +1168,63 @@ pub struct TransportConfig {
     /// should take care to set this low enough to guarantee memory exhaustion does not occur if
     /// every connection uses the entire window.
     pub send_window: u64,
+    /// Amount of unacknowledged stream data below which `Event::SendBufferLow` is emitted
+    ///
+    /// The event fires each time the amount of stream data written but not yet acknowledged by the
+    /// peer falls below this, having previously reached it. Lets applications relaying data from
+    /// elsewhere, such as proxies, stop reading their source while the network catches up rather
+    /// than buffering without bound. Should be smaller than `send_window`. 0 to disable, the
+    /// default.
+    pub send_low_water_mark: u64,
 
     /// Maximum number of tail loss probes before an RTO fires.
     pub max_tlps: u32,
+    /// Maximum number of times unacknowledged handshake packets are retransmitted before the
+    /// connection fails with `ConnectionError::TimedOut`
+    ///
+    /// Allows connection attempts over dead paths to fail well before `idle_timeout` elapses. 0 for
+    /// no limit, which is the default.
+    pub max_crypto_retransmits: u32,
+    /// Maximum number of bytes of handshake data that may be retransmitted before the connection
+    /// fails with `ConnectionError::TimedOut`
+    ///
+    /// Bounds the traffic a handshake with a large certificate chain can generate on a lossy path,
+    /// where each retransmission resends everything unacknowledged. 0 for no limit, the default.
+    pub max_crypto_retransmit_bytes: u64,
     /// Maximum reordering in packet number space before FACK style loss detection considers a
     /// packet lost.
     pub packet_threshold: u32,
     /// Maximum reordering in time space before time based loss detection considers a packet lost.
     /// 0.16 format, added to 1
     pub time_threshold: u16,
-    /// The length of the peer’s delayed ack timer (μs).
-    pub delayed_ack_timeout: u64,
+    /// Maximum amount of time in milliseconds by which we'll delay acknowledging packets
+    ///
+    /// Advertised to the peer. Packets that arrive out of order, and every second ack-eliciting
+    /// packet, are acknowledged immediately regardless. 0 to always acknowledge immediately. Must
+    /// be less than 2^14. Defaults to 25.
+    pub max_ack_delay: u64,
+    /// Exponent used to scale the delay reported in our acknowledgements
+    ///
+    /// Advertised to the peer. Larger values allow longer delays to be expressed compactly, at
+    /// the cost of precision. Must be at most 20.
+    pub ack_delay_exponent: u8,
     /// The RTT used before an RTT sample is taken (μs)
     pub initial_rtt: u64,
+    /// How long to wait for a new path to be validated before reverting to the previous one (μs)
+    ///
+    /// Also bounds how long `Connection::probe_path` waits for a response. 0 to derive it from
+    /// the probe timeout as `3 * max(PTO, 2 * initial_rtt)`, the default. Worth tuning on paths
+    /// whose latency varies widely, where the derived value may abandon a slow path too early or
+    /// cling to a dead one for too long.
+    pub path_validation_timeout: u64,
 
     /// The max packet size that was used for calculating default and minimum congestion windows.
     pub max_datagram_size: u64,
+    /// Largest UDP payload to send, absent a smaller limit from the peer
+    ///
+    /// Useful where the path MTU is known in advance, e.g. within a tunnel. May be changed for an
+    /// established connection with `Connection::set_mtu`. Must be at least 1232, the default.
+    pub initial_mtu: u16,
     /// Default limit on the amount of outstanding data in bytes.
     ///
     /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14600))`
@@ -865,6 +1237,11 @@ pub struct TransportConfig {
     pub loss_reduction_factor: u16,
     /// Number of consecutive PTOs after which network is considered to be experiencing persistent congestion.
     pub persistent_congestion_threshold: u32,
+    /// Constructs the congestion controller for each connection
+    ///
+    /// The window-related settings above are consulted by the default, `congestion::NewReno`, and
+    /// may be ignored by alternative controllers. If `None`, `NewReno` is used.
+    pub congestion_controller: Option<Arc<ControllerFactory>>,
     /// Number of seconds of inactivity before sending a keep-alive packet
     ///
     /// Keep-alive packets prevent an inactive but otherwise healthy connection from timing out.
@@ -873,6 +1250,66 @@ pub struct TransportConfig {
     /// enabled for the connection to be preserved. Must be set lower than the idle_timeout of both
     /// peers to be effective.
     pub keep_alive_interval: u32,
+    /// Whether a keep-alive is satisfied by queued ack-eliciting data rather than a PING
+    ///
+    /// When set, an explicit PING is only sent if nothing else ack-eliciting is waiting to be
+    /// transmitted when the keep-alive interval elapses. Defaults to `false`.
+    pub opportunistic_keep_alive: bool,
+    /// Whether to emit `Event::Idle` each time a connection runs out of work
+    ///
+    /// Useful for connection pools deciding which connections to evict. Defaults to `false`.
+    pub idle_events: bool,
+    /// Length of the closing and draining periods following a connection's closure, in multiples
+    /// of the probe timeout
+    ///
+    /// Connection state is retained for this long so that late packets from the peer are
+    /// recognized rather than treated as new connection attempts or answered with stateless
+    /// resets. Lowering this reduces memory held for recently closed connections, at the risk of
+    /// mishandling such packets. Defaults to 3, as recommended by the specification.
+    pub draining_ptos: u32,
+    /// ECN codepoint to mark outgoing packets with while ECN is in use
+    ///
+    /// Must be `ECT0` (the default) or `ECT1`.
+    pub ecn_codepoint: EcnCodepoint,
+    /// Whether flow control credit is released only by explicit calls to
+    /// `Connection::release_credit`, rather than as soon as data is read
+    ///
+    /// Allows memory-bounded applications to keep the peer from sending more data until what was
+    /// read has actually been processed. Defaults to `false`.
+    pub manual_credit: bool,
+    /// Whether to tolerate new CRYPTO data at an encryption level the handshake has already left
+    ///
+    /// By default such data is a protocol violation. Some implementations may nonetheless send
+    /// handshake data at the old level around key transitions; enabling this buffers up to 4KiB of
+    /// it per level and otherwise ignores it, rather than closing the connection. Only enable it
+    /// when interoperating with a peer known to need it. Defaults to `false`.
+    pub lenient_crypto_levels: bool,
+    /// Maximum bytes of CRYPTO and control frames that may be queued for transmission or awaiting
+    /// acknowledgement before the connection is closed
+    ///
+    /// Bounds the memory a misbehaving peer can make a connection commit to, e.g. by provoking a
+    /// large number of RST_STREAM frames or withholding acknowledgements. Exceeding it while
+    /// processing the peer's packets closes the connection with `PROTOCOL_VIOLATION`; exceeding it
+    /// through frames queued by the application, e.g. by resetting many streams, closes it with
+    /// `INTERNAL_ERROR`. Control frames are counted at an estimated fixed cost each. Stream data is bounded separately by
+    /// `send_window`. Must comfortably exceed the size of the TLS handshake flight if set. 0 for
+    /// no limit, the default.
+    pub max_retransmit_buffer: u64,
+    /// Maximum number of bytes of received stream data to buffer across all streams of a
+    /// connection before the application reads it
+    ///
+    /// Connection-level flow control credit is withheld beyond this, so the peer is blocked until
+    /// the application catches up. Useful for limiting the memory a peer can pin by opening many
+    /// streams and sending on them slowly, independent of how generous `receive_window` is for
+    /// throughput. 0 for no limit beyond `receive_window`, the default.
+    pub max_unread_data: u64,
+    /// Number of packets preceding the most recently received one that are tracked to detect
+    /// duplicates, rounded up to a multiple of 64
+    ///
+    /// Packets older than this can't be distinguished from replays and are dropped, so larger
+    /// values tolerate more reordering. Costs one bit of memory per packet in each of a
+    /// connection's three packet number spaces.
+    pub dedup_window: u64,
 }
 
 impl Default for TransportConfig {
@@ -891,14 +1328,20 @@ impl Default for TransportConfig {
             stream_receive_window: STREAM_RWND,
             receive_window: 8 * STREAM_RWND,
             send_window: 8 * STREAM_RWND,
+            send_low_water_mark: 0,
 
             max_tlps: 2,
+            max_crypto_retransmits: 0,
+            max_crypto_retransmit_bytes: 0,
             packet_threshold: 3,
             time_threshold: 0x2000, // 1/8
-            delayed_ack_timeout: 25 * 1000,
+            max_ack_delay: 25,
+            ack_delay_exponent: 3,
             initial_rtt: EXPECTED_RTT as u64 * 1000,
+            path_validation_timeout: 0,
 
             max_datagram_size: MAX_DATAGRAM_SIZE,
+            initial_mtu: MIN_MTU,
             initial_window: cmp::min(
                 10 * MAX_DATAGRAM_SIZE,
                 cmp::max(2 * MAX_DATAGRAM_SIZE, 14600),
@@ -906,12 +1349,44 @@ impl Default for TransportConfig {
             minimum_window: 2 * MAX_DATAGRAM_SIZE,
             loss_reduction_factor: 0x8000, // 1/2
             persistent_congestion_threshold: 2,
+            congestion_controller: None,
             keep_alive_interval: 0,
+            opportunistic_keep_alive: false,
+            idle_events: false,
+            draining_ptos: 3,
+            ecn_codepoint: EcnCodepoint::ECT0,
+            manual_credit: false,
+            lenient_crypto_levels: false,
+            max_retransmit_buffer: 0,
+            max_unread_data: 0,
+            dedup_window: dedup::DEFAULT_WINDOW,
         }
     }
 }
 
 impl TransportConfig {
+    /// Settings for paths with very long round trips, such as geostationary satellite links
+    ///
+    /// Assumes a 1 second RTT until one is measured, so that neither handshake retransmissions nor
+    /// the client's anti-deadlock probes are sent before the peer could possibly have answered.
+    /// Loss detection tolerates more reordering, and flow control windows and the idle timeout are
+    /// scaled up to suit the larger bandwidth-delay product. Other settings are the defaults.
+    pub fn long_delay() -> Self {
+        const RTT: u64 = 1000; // ms
+        let default = Self::default();
+        let scale = RTT * 1000 / default.initial_rtt;
+        TransportConfig {
+            idle_timeout: 30,
+            stream_receive_window: scale * default.stream_receive_window,
+            receive_window: scale * default.receive_window,
+            send_window: scale * default.send_window,
+            packet_threshold: 6,
+            time_threshold: 0x4000, // 1/4
+            initial_rtt: RTT * 1000,
+            ..default
+        }
+    }
+
     fn validate(&self, log: &Logger) -> Result<(), ConfigError> {
         if let Some((name, _)) = [
             ("stream_window_bidi", self.stream_window_bidi),
@@ -925,6 +1400,25 @@ impl TransportConfig {
         {
             return Err(ConfigError::VarIntBounds(name));
         }
+        if self.stream_window_bidi > MAX_STREAM_COUNT
+            || self.stream_window_uni > MAX_STREAM_COUNT
+        {
+            return Err(ConfigError::IllegalValue(
+                "stream windows must be at most 2^60",
+            ));
+        }
+        if self.max_ack_delay >= 1 << 14 {
+            return Err(ConfigError::IllegalValue("max_ack_delay must be less than 2^14"));
+        }
+        if self.ack_delay_exponent > 20 {
+            return Err(ConfigError::IllegalValue("ack_delay_exponent must be at most 20"));
+        }
+        if self.initial_mtu < MIN_MTU {
+            return Err(ConfigError::IllegalValue("initial_mtu must be at least 1232"));
+        }
+        if self.ecn_codepoint == EcnCodepoint::CE {
+            return Err(ConfigError::IllegalValue("ecn_codepoint must be ECT0 or ECT1"));
+        }
         if self.keep_alive_interval as u64 >= self.idle_timeout {
             warn!(
                 log,
@@ -946,6 +1440,22 @@ pub struct EndpointConfig {
     /// responsible for making sure that the pool is large enough to cover the intended usage.
     pub local_cid_len: usize,
 
+    /// Number of connection IDs each connection keeps issued to its peer
+    ///
+    /// Includes the one chosen during the handshake; the rest are issued once the handshake
+    /// completes, and each one the peer retires is replaced, keeping spares available for the peer
+    /// to migrate with. Draft 18 §5.1.1 recommends at least eight, the default. Must be at least 1,
+    /// and is ignored when `local_cid_len` is 0.
+    pub local_cid_count: usize,
+
+    /// Customizes the local connection IDs chosen by the endpoint
+    ///
+    /// Called with each new CID, `local_cid_len` bytes long and already filled with random data,
+    /// which it may modify in place, e.g. to embed routing information for a load balancer. Enough
+    /// of the CID must remain random for CIDs to be unique and unpredictable. Stateless reset
+    /// tokens are derived from the final CID. If `None`, CIDs are entirely random.
+    pub cid_generator: Option<Arc<CidGenerator>>,
+
     /// Private key used to send authenticated connection resets to peers who were communicating
     /// with a previous instance of this endpoint.
     ///
@@ -959,6 +1469,8 @@ impl Default for EndpointConfig {
         rand::thread_rng().fill_bytes(&mut reset_value);
         Self {
             local_cid_len: 8,
+            local_cid_count: 8,
+            cid_generator: None,
             reset_key: SigningKey::new(&digest::SHA512_256, &reset_value),
         }
     }
@@ -973,10 +1485,16 @@ impl EndpointConfig {
                 "local_cid_len must be 0 or in [4, 18]",
             ));
         }
+        if self.local_cid_count == 0 {
+            return Err(ConfigError::IllegalValue("local_cid_count must be at least 1"));
+        }
         Ok(())
     }
 }
 
+/// A callback used to customize locally chosen connection IDs
+pub type CidGenerator = dyn Fn(&mut [u8]) + Send + Sync;
+
 /// Parameters governing incoming connections.
 pub struct ServerConfig {
     /// Transport configuration to use for incoming connections
@@ -1000,6 +1518,51 @@ pub struct ServerConfig {
     ///
     /// Accepting a connection removes it from the buffer, so this does not need to be large.
     pub accept_buffer: u32,
+
+    /// Maximum number of connections the endpoint may hold at once
+    ///
+    /// Connection attempts beyond this are refused with a `SERVER_BUSY` error before any state is
+    /// allocated for them. Counts every connection, including those still handshaking or draining;
+    /// see `Endpoint::connection_count`. 0 for no limit, the default.
+    pub max_connections: u32,
+
+    /// Whether 0-RTT data from resuming clients may be accepted
+    ///
+    /// When `false`, early data is always rejected, whatever `tls_config` allows, and clients must
+    /// resend it once the handshake completes. This gives up the latency benefit of 0-RTT in
+    /// exchange for immunity to replay attacks, for applications whose requests aren't idempotent.
+    /// Clients learn of the rejection through `Event::ZeroRttRejected`. Defaults to `true`.
+    pub accept_0rtt: bool,
+    /// Whether to issue a TLS session ticket at the end of each handshake
+    ///
+    /// Clients need a ticket to resume a session later, and hence to send 0-RTT data, which the
+    /// ticket only permits if `tls_config.max_early_data_size` is nonzero. At most one ticket is
+    /// issued per connection. Disabling this prevents resumption entirely. Defaults to `true`.
+    pub session_tickets: bool,
+
+    /// Decides how to treat each new connection attempt before any state is allocated for it
+    ///
+    /// Called with the client's address and the destination connection ID of its first Initial
+    /// packet. Useful for enforcing allow/deny lists or rate-limiting by source address. If `None`,
+    /// every attempt is accepted.
+    pub incoming_filter: Option<Arc<IncomingFilter>>,
+}
+
+/// A callback used to screen incoming connection attempts
+pub type IncomingFilter = dyn Fn(&SocketAddr, &ConnectionId) -> IncomingAction + Send + Sync;
+
+/// How an endpoint should respond to a new connection attempt
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IncomingAction {
+    /// Proceed with the handshake
+    Accept,
+    /// Require the client to prove it can receive packets at its address, as if
+    /// `ServerConfig::use_stateless_retry` were set
+    Retry,
+    /// Close the connection immediately with a `SERVER_BUSY` error
+    Refuse,
+    /// Drop the packet without responding
+    Ignore,
 }
 
 impl Default for ServerConfig {
@@ -1018,6 +1581,10 @@ impl Default for ServerConfig {
             retry_token_lifetime: 15_000_000,
 
             accept_buffer: 1024,
+            max_connections: 0,
+            accept_0rtt: true,
+            session_tickets: true,
+            incoming_filter: None,
         }
     }
 }
@@ -1041,20 +1608,77 @@ pub enum Event {
     Handshaking,
     /// A connection was successfully established.
     Connected,
+    /// The server accepted the 0-RTT data sent on this connection
+    ///
+    /// Emitted to clients immediately before `Connected`. Applications that must not send
+    /// non-idempotent requests as early data can hold them until this or `ZeroRttRejected`.
+    ZeroRttAccepted,
+    /// The server rejected the 0-RTT data sent on this connection
+    ///
+    /// Emitted to clients immediately before `Connected`. Streams opened before then were reset,
+    /// and writing to them fails with `WriteError::ZeroRttRejected`; their data must be resent on
+    /// new streams if still wanted.
+    ZeroRttRejected,
     /// A connection was lost.
     ///
     /// Emitted at the end of the lifetime of a connection, even if it was closed locally.
     ConnectionLost { reason: ConnectionError },
-    /// One or more new streams has been opened and is readable
-    StreamOpened,
+    /// The peer opened a new stream
+    ///
+    /// Emitted once per stream, in order, including streams opened implicitly by the peer using a
+    /// higher-numbered stream of the same directionality.
+    StreamOpened { stream: StreamId },
     /// An existing stream has data or errors waiting to be read
     StreamReadable { stream: StreamId },
     /// A formerly write-blocked stream might now accept a write
     StreamWritable { stream: StreamId },
     /// All data sent on `stream` has been received by the peer
     StreamFinished { stream: StreamId },
+    /// The peer abandoned transmitting data on `stream`
+    ///
+    /// Reads from the stream will fail with `ReadError::Reset`.
+    StreamReset { stream: StreamId, error_code: u16 },
     /// At least one new stream of a certain directionality may be opened
     StreamAvailable { directionality: Directionality },
+    /// The congestion window collapsed to its minimum due to severe loss
+    ///
+    /// Advisory only; useful for applications that adapt their sending rate to path quality.
+    CongestionWindowReduced { old: u64, new: u64 },
+    /// Unacknowledged stream data fell below `TransportConfig::send_low_water_mark`
+    ///
+    /// Connection-level counterpart of `StreamWritable`, tied to acknowledgement by the peer
+    /// rather than to buffer space.
+    SendBufferLow,
+    /// An established connection has nothing in flight or waiting to be sent
+    ///
+    /// Only emitted if `TransportConfig::idle_events` is set, and not again until the connection
+    /// has sent something that needs acknowledging. Unrelated to the idle timeout, which still
+    /// applies. Data waiting to be read by the application is not considered.
+    Idle,
+    /// A path probe started by `Connection::probe_path` finished
+    PathProbed {
+        remote: SocketAddr,
+        /// Whether the peer answered from `remote` before the probe timed out
+        validated: bool,
+    },
+    /// The peer began sending from a new address, which is now being validated
+    ///
+    /// Only emitted by servers.
+    Migrated {
+        remote: SocketAddr,
+        kind: MigrationKind,
+    },
+}
+
+/// How a peer came to change its address
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MigrationKind {
+    /// The peer's address changed underneath it, e.g. due to a NAT rebinding
+    ///
+    /// Detected by the peer continuing to use the same connection ID from the new address.
+    Rebinding,
+    /// The peer deliberately moved to a new path, switching to a fresh connection ID
+    Active,
 }
 
 impl From<ConnectionError> for Event {
@@ -1072,11 +1696,15 @@ pub enum Timer {
     KeyDiscard = 3,
     PathValidation = 4,
     KeepAlive = 5,
+    /// When the ack delay timer expires, delayed acknowledgements must be sent.
+    AckDelay = 6,
+    /// When the path probe timer expires, a probe started by `Connection::probe_path` has failed.
+    PathProbe = 7,
 }
 
 impl Timer {
     /// Number of types of timers that a connection may start
-    pub const COUNT: usize = 6;
+    pub const COUNT: usize = 8;
     pub(crate) const VALUES: [Timer; Self::COUNT] = [
         Timer::LossDetection,
         Timer::Idle,
@@ -1084,6 +1712,8 @@ impl Timer {
         Timer::KeyDiscard,
         Timer::PathValidation,
         Timer::KeepAlive,
+        Timer::AckDelay,
+        Timer::PathProbe,
     ];
 }
 
@@ -1101,6 +1731,36 @@ impl slog::Value for Timer {
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct ConnectionHandle(pub usize);
 
+/// Round-robin queue of connections that might have packets to send
+#[derive(Default)]
+struct TransmitQueue {
+    pending: FnvHashSet<ConnectionHandle>,
+    /// May contain stale entries for connections no longer in `pending`, which are skipped
+    order: VecDeque<ConnectionHandle>,
+}
+
+impl TransmitQueue {
+    fn insert(&mut self, ch: ConnectionHandle) {
+        if self.pending.insert(ch) {
+            self.order.push_back(ch);
+        }
+    }
+
+    fn remove(&mut self, ch: &ConnectionHandle) {
+        self.pending.remove(ch);
+    }
+
+    /// Take the connection that has been waiting longest
+    fn pop(&mut self) -> Option<ConnectionHandle> {
+        while let Some(ch) = self.order.pop_front() {
+            if self.pending.remove(&ch) {
+                return Some(ch);
+            }
+        }
+        None
+    }
+}
+
 impl From<ConnectionHandle> for usize {
     fn from(x: ConnectionHandle) -> usize {
         x.0
@@ -1152,3 +1812,27 @@ impl From<ConfigError> for ConnectError {
         ConnectError::Config(x)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transmit_queue_round_robin() {
+        let mut queue = TransmitQueue::default();
+        let (a, b, c) = (ConnectionHandle(0), ConnectionHandle(1), ConnectionHandle(2));
+        queue.insert(a);
+        queue.insert(b);
+        queue.insert(a);
+        queue.insert(c);
+        queue.remove(&b);
+
+        assert_eq!(queue.pop(), Some(a));
+        queue.insert(a);
+        assert_eq!(queue.pop(), Some(c));
+        queue.insert(b);
+        assert_eq!(queue.pop(), Some(a));
+        assert_eq!(queue.pop(), Some(b));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -164,7 +164,10 @@ pub enum Frame {
     ConnectionClose(ConnectionClose),
     ApplicationClose(ApplicationClose),
     Invalid {
-        ty: Type,
+        /// Type of the malformed frame, if it could be decoded
+        ty: Option<Type>,
+        /// Offset of the start of the malformed frame within the payload
+        offset: usize,
         reason: &'static str,
     },
 }
@@ -216,7 +219,8 @@ impl Frame {
             NewConnectionId { .. } => Type::NEW_CONNECTION_ID,
             Crypto(_) => Type::CRYPTO,
             NewToken { .. } => Type::NEW_TOKEN,
-            Invalid { ty, .. } => ty,
+            // A frame whose type is truncated is indistinguishable from trailing garbage
+            Invalid { ty, .. } => ty.unwrap_or(Type::PADDING),
         }
     }
 }
@@ -322,11 +326,41 @@ impl<'a> IntoIterator for &'a Ack {
 }
 
 impl Ack {
-    pub fn encode<W: BufMut>(delay: u64, ranges: &RangeSet, ecn: Option<&EcnCounts>, buf: &mut W) {
-        let mut rest = ranges.iter().rev();
-        let first = rest.next().unwrap();
+    /// Encode an ACK frame covering as many of the most recent `ranges` as fit in `max_len` bytes
+    ///
+    /// Older ranges are omitted when space is tight, but the largest range is always encoded.
+    /// Returns the number of ranges written.
+    pub fn encode<W: BufMut>(
+        delay: u64,
+        ranges: &RangeSet,
+        ecn: Option<&EcnCounts>,
+        max_len: usize,
+        buf: &mut W,
+    ) -> usize {
+        let first = ranges.iter().next_back().unwrap();
         let largest = first.end - 1;
         let first_size = first.end - first.start;
+
+        // The range count is sized as if every range were sent, which can only overestimate
+        let mut len = 1
+            + varint::size(largest).unwrap()
+            + varint::size(delay).unwrap()
+            + varint::size(ranges.len() as u64 - 1).unwrap()
+            + varint::size(first_size - 1).unwrap()
+            + ecn.map_or(0, |x| x.size());
+        let mut count = 1;
+        let mut prev = first.start;
+        for block in ranges.iter().rev().skip(1) {
+            let block_len = varint::size(prev - block.end - 1).unwrap()
+                + varint::size(block.end - block.start - 1).unwrap();
+            if len + block_len > max_len {
+                break;
+            }
+            len += block_len;
+            count += 1;
+            prev = block.start;
+        }
+
         buf.write(if ecn.is_some() {
             Type::ACK_ECN
         } else {
@@ -334,10 +368,10 @@ impl Ack {
         });
         buf.write_var(largest);
         buf.write_var(delay);
-        buf.write_var(ranges.len() as u64 - 1);
+        buf.write_var(count as u64 - 1);
         buf.write_var(first_size - 1);
         let mut prev = first.start;
-        for block in rest {
+        for block in ranges.iter().rev().skip(1).take(count - 1) {
             let size = block.end - block.start;
             buf.write_var(prev - block.end - 1);
             buf.write_var(size - 1);
@@ -346,6 +380,7 @@ impl Ack {
         if let Some(x) = ecn {
             x.encode(buf)
         }
+        count
     }
 
     pub fn iter(&self) -> AckIter<'_> {
@@ -383,6 +418,13 @@ impl EcnCounts {
         ce: 0,
     };
 
+    /// Number of bytes needed to encode these counts
+    pub fn size(&self) -> usize {
+        varint::size(self.ect0).unwrap()
+            + varint::size(self.ect1).unwrap()
+            + varint::size(self.ce).unwrap()
+    }
+
     pub fn encode<W: BufMut>(&self, out: &mut W) {
         out.write_var(self.ect0);
         out.write_var(self.ect1);
@@ -491,6 +533,7 @@ impl Iter {
     }
 
     fn try_next(&mut self) -> Result<Frame, IterErr> {
+        self.last_ty = None;
         let ty = self.bytes.get::<Type>()?;
         self.last_ty = Some(ty);
         Ok(match ty {
@@ -635,13 +678,15 @@ impl Iterator for Iter {
         if !self.bytes.has_remaining() {
             return None;
         }
+        let offset = self.bytes.position() as usize;
         match self.try_next() {
             Ok(x) => Some(x),
             Err(e) => {
                 // Corrupt frame, skip it and everything that follows
                 self.bytes = io::Cursor::new(Bytes::new());
                 Some(Frame::Invalid {
-                    ty: self.last_ty.unwrap(),
+                    ty: self.last_ty,
+                    offset,
                     reason: e.reason(),
                 })
             }
@@ -747,7 +792,7 @@ mod test {
             ect1: 24,
             ce: 12,
         };
-        Ack::encode(42, &ranges, Some(&ECN), &mut buf);
+        Ack::encode(42, &ranges, Some(&ECN), usize::max_value(), &mut buf);
         let frames = Iter::new(Bytes::from(buf)).collect::<Vec<_>>();
         assert_eq!(frames.len(), 1);
         match frames[0] {
@@ -760,4 +805,49 @@ mod test {
             ref x => panic!("incorrect frame {:?}", x),
         }
     }
+
+    #[test]
+    fn ack_truncation() {
+        let mut ranges = RangeSet::new();
+        for packet in 0..100 {
+            ranges.insert_one(packet * 2);
+        }
+        let mut buf = Vec::new();
+        const MAX_LEN: usize = 32;
+        let count = Ack::encode(0, &ranges, None, MAX_LEN, &mut buf);
+        assert!(buf.len() <= MAX_LEN);
+        assert!(count > 1 && count < 100);
+        let frames = Iter::new(Bytes::from(buf)).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 1);
+        match frames[0] {
+            Frame::Ack(ref ack) => {
+                let mut packets = ack.iter().flat_map(|x| x).collect::<Vec<_>>();
+                packets.sort_unstable();
+                // The most recent packets are retained
+                let expected = (100 - count as u64..100).map(|x| x * 2).collect::<Vec<_>>();
+                assert_eq!(packets, expected);
+            }
+            ref x => panic!("incorrect frame {:?}", x),
+        }
+    }
+
+    #[test]
+    fn invalid_offset() {
+        let mut buf = Vec::new();
+        buf.write(Type::PING);
+        buf.write(Type::MAX_DATA);
+        buf.write_var(42);
+        buf.write(Type::MAX_STREAM_DATA);
+        buf.write_var(0); // stream ID, missing offset
+        let frames = Iter::new(Bytes::from(buf)).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        match frames[2] {
+            Frame::Invalid { ty, offset, reason } => {
+                assert_eq!(ty, Some(Type::MAX_STREAM_DATA));
+                assert_eq!(offset, 3);
+                assert_eq!(reason, "unexpected end");
+            }
+            ref x => panic!("incorrect frame {:?}", x),
+        }
+    }
 }
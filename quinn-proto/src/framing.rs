@@ -0,0 +1,240 @@
+//! Length-prefixed message framing over streams
+//!
+//! Protocols layered on QUIC frequently open a unidirectional stream identified by a type prefix
+//! and exchange discrete messages over it, as HTTP/3 does for its control stream. A
+//! `MessageWriter` encodes the type prefix followed by each message as a variable-length integer
+//! length and the message bytes; a `MessageReader` decodes the same format.
+
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use err_derive::Error;
+
+use crate::coding::BufMutExt;
+use crate::endpoint::{ConnectionHandle, Endpoint};
+use crate::stream::{ReadError, WriteError};
+use crate::{varint, Directionality, StreamId};
+
+/// Largest message a `MessageReader` accepts by default
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// Sends length-prefixed messages on an outgoing stream
+#[derive(Debug)]
+pub struct MessageWriter {
+    stream: StreamId,
+    pending: Vec<u8>,
+}
+
+impl MessageWriter {
+    /// Open a new unidirectional stream identified by `stream_type`
+    ///
+    /// Returns `None` if the peer's stream limit has been reached.
+    pub fn open(endpoint: &mut Endpoint, ch: ConnectionHandle, stream_type: u64) -> Option<Self> {
+        let stream = endpoint.open(ch, Directionality::Uni)?;
+        Some(Self::new(stream, stream_type))
+    }
+
+    /// Frame messages on an already-open `stream`, identifying it with `stream_type`
+    pub fn new(stream: StreamId, stream_type: u64) -> Self {
+        let mut pending = Vec::new();
+        pending.write_var(stream_type);
+        Self { stream, pending }
+    }
+
+    /// The stream messages are written to
+    pub fn id(&self) -> StreamId {
+        self.stream
+    }
+
+    /// Queue `message` for transmission
+    ///
+    /// Nothing is written to the stream until `flush` is called.
+    pub fn send(&mut self, message: &[u8]) {
+        self.pending.write_var(message.len() as u64);
+        self.pending.extend_from_slice(message);
+    }
+
+    /// Write as much queued data to the stream as flow control permits
+    ///
+    /// Returns `true` once all queued messages have been written, or `false` if some remain and
+    /// `flush` should be called again when the stream becomes writable.
+    pub fn flush(
+        &mut self,
+        endpoint: &mut Endpoint,
+        ch: ConnectionHandle,
+    ) -> Result<bool, WriteError> {
+        while !self.pending.is_empty() {
+            match endpoint.write(ch, self.stream, &self.pending) {
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(WriteError::Blocked) => {
+                    return Ok(false);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Receives length-prefixed messages from an incoming stream
+#[derive(Debug)]
+pub struct MessageReader {
+    stream: StreamId,
+    stream_type: Option<u64>,
+    buffer: BytesMut,
+    max_message_size: u64,
+}
+
+impl MessageReader {
+    /// Decode messages sent on `stream` by a peer's `MessageWriter`
+    pub fn new(stream: StreamId) -> Self {
+        Self {
+            stream,
+            stream_type: None,
+            buffer: BytesMut::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Reject messages longer than `size` bytes, rather than buffering them
+    pub fn max_message_size(&mut self, size: u64) -> &mut Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// The stream messages are read from
+    pub fn id(&self) -> StreamId {
+        self.stream
+    }
+
+    /// The type prefix the peer identified the stream with, once it has been received
+    pub fn stream_type(&self) -> Option<u64> {
+        self.stream_type
+    }
+
+    /// Get the next complete message, reading from the stream as necessary
+    ///
+    /// Returns `Ok(None)` if no complete message is available yet. `FramingError::Read` with
+    /// `ReadError::Finished` indicates the stream ended cleanly between messages.
+    pub fn poll(
+        &mut self,
+        endpoint: &mut Endpoint,
+        ch: ConnectionHandle,
+    ) -> Result<Option<Bytes>, FramingError> {
+        loop {
+            if let Some(message) = self.decode()? {
+                return Ok(Some(message));
+            }
+            let mut buf = [0; 4096];
+            match endpoint.read(ch, self.stream, &mut buf) {
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&buf[..n]);
+                }
+                Err(ReadError::Blocked) => {
+                    return Ok(None);
+                }
+                Err(ReadError::Finished) if !self.buffer.is_empty() => {
+                    return Err(FramingError::Truncated);
+                }
+                Err(e) => {
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    /// Extract a complete message from the buffer, if there is one
+    fn decode(&mut self) -> Result<Option<Bytes>, FramingError> {
+        if self.stream_type.is_none() {
+            match read_var(&self.buffer) {
+                Some((ty, len)) => {
+                    self.stream_type = Some(ty);
+                    self.buffer.advance(len);
+                }
+                None => return Ok(None),
+            }
+        }
+        let (size, len) = match read_var(&self.buffer) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        if size > self.max_message_size {
+            return Err(FramingError::TooLarge);
+        }
+        if ((self.buffer.len() - len) as u64) < size {
+            self.buffer.reserve(len + size as usize - self.buffer.len());
+            return Ok(None);
+        }
+        self.buffer.advance(len);
+        Ok(Some(self.buffer.split_to(size as usize).freeze()))
+    }
+}
+
+/// Decode a variable-length integer from the start of `buf`, returning it and its encoded size
+fn read_var(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut cursor = io::Cursor::new(buf);
+    let x = varint::read(&mut cursor)?;
+    Some((x, cursor.position() as usize))
+}
+
+/// Errors arising while receiving framed messages
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum FramingError {
+    /// The underlying stream could not be read
+    #[error(display = "{}", _0)]
+    Read(ReadError),
+    /// The peer sent a message larger than the configured maximum
+    #[error(display = "message too large")]
+    TooLarge,
+    /// The stream ended partway through a message
+    #[error(display = "stream ended mid-message")]
+    Truncated,
+}
+
+impl From<ReadError> for FramingError {
+    fn from(x: ReadError) -> Self {
+        FramingError::Read(x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_partial() {
+        let mut writer = MessageWriter::new(StreamId(2), 0x42);
+        writer.send(b"hello");
+        writer.send(&[0xab; 100]);
+        let encoded = writer.pending.clone();
+
+        let mut reader = MessageReader::new(StreamId(2));
+        // Feed the encoding one byte at a time to exercise every partial state
+        let mut messages = Vec::new();
+        for &byte in encoded.iter() {
+            reader.buffer.extend_from_slice(&[byte]);
+            while let Some(message) = reader.decode().unwrap() {
+                messages.push(message);
+            }
+        }
+        assert_eq!(reader.stream_type(), Some(0x42));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(&messages[0][..], b"hello");
+        assert_eq!(&messages[1][..], &[0xab; 100][..]);
+        assert!(reader.buffer.is_empty());
+    }
+
+    #[test]
+    fn decode_too_large() {
+        let mut writer = MessageWriter::new(StreamId(2), 0);
+        writer.send(&[0; 100]);
+        let mut reader = MessageReader::new(StreamId(2));
+        reader.max_message_size(99);
+        reader.buffer.extend_from_slice(&writer.pending);
+        assert_eq!(reader.decode(), Err(FramingError::TooLarge));
+    }
+}
@@ -0,0 +1,249 @@
+//! Transferring idle connections between processes
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use bytes::{Buf, BufMut};
+use err_derive::Error;
+
+use crate::coding::{self, BufExt, BufMutExt};
+use crate::endpoint::ConfigError;
+use crate::packet::ConnectionId;
+use crate::transport_parameters::TransportParameters;
+use crate::{MAX_CID_SIZE, MIN_CID_SIZE};
+
+/// Revision of the encoding produced by `ConnectionState::encode`
+const FORMAT_VERSION: u8 = 1;
+
+/// Essential state of an idle server-side connection, for resuming it in another process
+///
+/// Produced by `Endpoint::export_state` and consumed by `Endpoint::import_state`, e.g. to hand
+/// connections off to a freshly started server during a zero-downtime upgrade. Covers connection
+/// IDs, 1-RTT packet protection secrets, packet numbers, flow control, and stream limits. Stream
+/// data and TLS session state are not included, so ALPN and SNI information is unavailable on the
+/// resumed connection; applications that need it must carry it separately.
+///
+/// # Security
+///
+/// This contains the connection's packet protection secrets. Anyone who obtains it can decrypt
+/// every packet sent on the connection before or after the handoff and forge packets from either
+/// peer. Transfer it only over a channel at least as trustworthy as the memory of the processes
+/// involved, such as a Unix socket between processes of the same user, never persist it, and
+/// discard it once imported. A state must be imported at most once: importing it twice reuses
+/// packet numbers, and hence AEAD nonces, under the same keys.
+pub struct ConnectionState {
+    pub(crate) remote: SocketAddr,
+    pub(crate) loc_cids: Vec<(u64, ConnectionId)>,
+    pub(crate) cids_issued: u64,
+    pub(crate) rem_cid: ConnectionId,
+    pub(crate) rem_cid_seq: u64,
+    /// TLS 1.3 cipher suite identifier
+    pub(crate) suite: u16,
+    pub(crate) local_secret: Vec<u8>,
+    pub(crate) remote_secret: Vec<u8>,
+    pub(crate) next_packet_number: u64,
+    pub(crate) largest_acked_packet: u64,
+    pub(crate) rx_packet: u64,
+    /// Transport parameters supplied by the peer
+    pub(crate) params: TransportParameters,
+    pub(crate) max_data: u64,
+    pub(crate) data_sent: u64,
+    pub(crate) data_recvd: u64,
+    pub(crate) local_max_data: u64,
+    pub(crate) next_uni: u64,
+    pub(crate) next_bi: u64,
+    pub(crate) max_uni: u64,
+    pub(crate) max_bi: u64,
+    pub(crate) max_remote_uni: u64,
+    pub(crate) max_remote_bi: u64,
+    pub(crate) next_remote_uni: u64,
+    pub(crate) next_remote_bi: u64,
+    pub(crate) idle_timeout: u64,
+    pub(crate) mtu: u16,
+}
+
+impl ConnectionState {
+    /// The address of the peer
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// Serialize for transfer to another process
+    pub fn encode<W: BufMut>(&self, buf: &mut W) {
+        buf.write(FORMAT_VERSION);
+        match self.remote.ip() {
+            IpAddr::V4(x) => {
+                buf.write::<u8>(4);
+                buf.write(x);
+            }
+            IpAddr::V6(x) => {
+                buf.write::<u8>(6);
+                buf.write(x);
+            }
+        }
+        buf.write(self.remote.port());
+        buf.write(self.loc_cids.len() as u8);
+        for &(sequence, ref cid) in &self.loc_cids {
+            buf.write_var(sequence);
+            write_cid(buf, cid);
+        }
+        buf.write_var(self.cids_issued);
+        write_cid(buf, &self.rem_cid);
+        buf.write_var(self.rem_cid_seq);
+        buf.write(self.suite);
+        for secret in &[&self.local_secret, &self.remote_secret] {
+            buf.write(secret.len() as u8);
+            buf.put_slice(secret);
+        }
+        for &x in &[
+            self.next_packet_number,
+            self.largest_acked_packet,
+            self.rx_packet,
+        ] {
+            buf.write_var(x);
+        }
+        self.params.write_raw(buf);
+        for &x in &[
+            self.max_data,
+            self.data_sent,
+            self.data_recvd,
+            self.local_max_data,
+            self.next_uni,
+            self.next_bi,
+            self.max_uni,
+            self.max_bi,
+            self.max_remote_uni,
+            self.max_remote_bi,
+            self.next_remote_uni,
+            self.next_remote_bi,
+            self.idle_timeout,
+        ] {
+            buf.write_var(x);
+        }
+        buf.write(self.mtu);
+    }
+
+    /// Deserialize state produced by `encode`
+    pub fn decode<R: Buf>(buf: &mut R) -> Result<Self, ImportError> {
+        if buf.get::<u8>()? != FORMAT_VERSION {
+            return Err(ImportError::Malformed);
+        }
+        let ip: IpAddr = match buf.get::<u8>()? {
+            4 => buf.get::<Ipv4Addr>()?.into(),
+            6 => buf.get::<Ipv6Addr>()?.into(),
+            _ => return Err(ImportError::Malformed),
+        };
+        let remote = SocketAddr::new(ip, buf.get()?);
+        let count = buf.get::<u8>()?;
+        if count == 0 {
+            return Err(ImportError::Malformed);
+        }
+        let mut loc_cids = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let sequence = buf.get_var()?;
+            loc_cids.push((sequence, read_cid(buf)?));
+        }
+        let cids_issued = buf.get_var()?;
+        let rem_cid = read_cid(buf)?;
+        let rem_cid_seq = buf.get_var()?;
+        let suite = buf.get()?;
+        let local_secret = read_secret(buf)?;
+        let remote_secret = read_secret(buf)?;
+        let next_packet_number = buf.get_var()?;
+        let largest_acked_packet = buf.get_var()?;
+        let rx_packet = buf.get_var()?;
+        let params = TransportParameters::read_raw(buf)?;
+        Ok(Self {
+            remote,
+            loc_cids,
+            cids_issued,
+            rem_cid,
+            rem_cid_seq,
+            suite,
+            local_secret,
+            remote_secret,
+            next_packet_number,
+            largest_acked_packet,
+            rx_packet,
+            params,
+            max_data: buf.get_var()?,
+            data_sent: buf.get_var()?,
+            data_recvd: buf.get_var()?,
+            local_max_data: buf.get_var()?,
+            next_uni: buf.get_var()?,
+            next_bi: buf.get_var()?,
+            max_uni: buf.get_var()?,
+            max_bi: buf.get_var()?,
+            max_remote_uni: buf.get_var()?,
+            max_remote_bi: buf.get_var()?,
+            next_remote_uni: buf.get_var()?,
+            next_remote_bi: buf.get_var()?,
+            idle_timeout: buf.get_var()?,
+            mtu: buf.get()?,
+        })
+    }
+}
+
+fn write_cid<W: BufMut>(buf: &mut W, cid: &ConnectionId) {
+    buf.write(cid.len() as u8);
+    buf.put_slice(cid);
+}
+
+fn read_cid<R: Buf>(buf: &mut R) -> Result<ConnectionId, ImportError> {
+    let len = buf.get::<u8>()? as usize;
+    if (len != 0 && (len < MIN_CID_SIZE || len > MAX_CID_SIZE)) || buf.remaining() < len {
+        return Err(ImportError::Malformed);
+    }
+    let mut bytes = [0; MAX_CID_SIZE];
+    buf.copy_to_slice(&mut bytes[..len]);
+    Ok(ConnectionId::new(&bytes[..len]))
+}
+
+fn read_secret<R: Buf>(buf: &mut R) -> Result<Vec<u8>, ImportError> {
+    let len = buf.get::<u8>()? as usize;
+    if len == 0 || buf.remaining() < len {
+        return Err(ImportError::Malformed);
+    }
+    let mut secret = vec![0; len];
+    buf.copy_to_slice(&mut secret);
+    Ok(secret)
+}
+
+/// Reasons why a connection could not be exported
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum ExportError {
+    /// Only established server-side connections that have not updated their keys are supported
+    #[error(display = "connection cannot be exported")]
+    Unsupported,
+    /// The connection has open streams or data in flight
+    #[error(display = "connection is not idle")]
+    Busy,
+}
+
+/// Reasons why a connection could not be imported
+#[derive(Debug, Error)]
+pub enum ImportError {
+    /// The encoded state was truncated or invalid
+    #[error(display = "malformed connection state")]
+    Malformed,
+    /// The endpoint has no server configuration
+    #[error(display = "endpoint is not a server")]
+    NotServer,
+    /// The state's connection IDs don't suit this endpoint's configuration or are already in use
+    #[error(display = "connection IDs unusable by this endpoint")]
+    ConnectionIdConflict,
+    /// The transport configuration was invalid
+    #[error(display = "transport configuration error: {}", _0)]
+    Config(ConfigError),
+}
+
+impl From<coding::UnexpectedEnd> for ImportError {
+    fn from(_: coding::UnexpectedEnd) -> Self {
+        ImportError::Malformed
+    }
+}
+
+impl From<ConfigError> for ImportError {
+    fn from(x: ConfigError) -> Self {
+        ImportError::Config(x)
+    }
+}
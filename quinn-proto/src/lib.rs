@@ -11,11 +11,12 @@ extern crate lazy_static;
 extern crate slog;
 
 use std::fmt;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops;
 use std::time::Duration;
 
 pub mod coding;
+pub mod congestion;
 mod dedup;
 mod range_set;
 #[cfg(test)]
@@ -24,7 +25,7 @@ mod transport_parameters;
 pub mod varint;
 
 mod connection;
-pub use crate::connection::{ConnectionError, TimerSetting, TimerUpdate};
+pub use crate::connection::{ConnectionError, EcnState, RttInfo, TimerSetting, TimerUpdate};
 
 mod crypto;
 pub use crate::crypto::{ClientConfig, TokenKey};
@@ -35,12 +36,15 @@ pub use crate::frame::{ApplicationClose, ConnectionClose};
 
 mod endpoint;
 pub use crate::endpoint::{
-    ConfigError, ConnectError, ConnectionHandle, Endpoint, EndpointConfig, Event, ServerConfig,
-    Timer, TransportConfig,
+    CidGenerator, ConfigError, ConnectError, ConnectionHandle, Endpoint, EndpointConfig, Event,
+    IncomingAction, IncomingFilter, MigrationKind, ServerConfig, Timer, TransportConfig,
 };
 
+mod handoff;
+pub use crate::handoff::{ConnectionState, ExportError, ImportError};
+
 mod packet;
-pub use crate::packet::{ConnectionId, EcnCodepoint};
+pub use crate::packet::{ConnectionId, EcnCodepoint, SpaceId};
 
 mod stream;
 pub use crate::stream::{ReadError, WriteError};
@@ -48,6 +52,12 @@ pub use crate::stream::{ReadError, WriteError};
 mod transport_error;
 pub use crate::transport_error::{Code as TransportErrorCode, Error as TransportError};
 
+#[cfg(feature = "framing")]
+pub mod framing;
+
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+
 /// The QUIC protocol version implemented
 pub const VERSION: u32 = 0xff00_0012;
 
@@ -203,6 +213,10 @@ impl coding::Codec for StreamId {
 #[derive(Debug)]
 pub struct Transmit {
     pub destination: SocketAddr,
+    /// Local address to send the packet from, or `None` to let the operating system choose
+    ///
+    /// Set for connections pinned to a local address with `Connection::set_local_ip`.
+    pub source: Option<IpAddr>,
     /// Explicit congestion notification bits to set on the packet
     pub ecn: Option<EcnCodepoint>,
     pub packet: Box<[u8]>,
@@ -218,3 +232,8 @@ const MIN_CID_SIZE: usize = 4;
 const MIN_INITIAL_SIZE: usize = 1200;
 const MIN_MTU: u16 = 1232;
 const TIMER_GRANULARITY: Duration = Duration::from_millis(1);
+/// Maximum number of streams of each directionality that may ever be opened on a connection
+///
+/// Stream IDs are 62-bit integers with the two low bits reserved for the initiator and
+/// directionality.
+const MAX_STREAM_COUNT: u64 = 1 << 60;
@@ -32,10 +32,11 @@ impl PartialDecode {
     ) -> Result<(Self, Option<BytesMut>), PacketDecodeError> {
         let mut buf = io::Cursor::new(bytes);
         let plain_header = PlainHeader::decode(&mut buf, local_cid_len)?;
-        let dgram_len = buf.get_ref().len();
+        let dgram_len = buf.get_ref().len() as u64;
+        // Compared as u64 so that an oversized length field can't be truncated on 32-bit targets
         let packet_len = plain_header
             .payload_len()
-            .map(|len| (buf.position() + len) as usize)
+            .map(|len| buf.position() + len)
             .unwrap_or(dgram_len);
         match dgram_len.cmp(&packet_len) {
             Ordering::Equal => Ok((Self { plain_header, buf }, None)),
@@ -43,7 +44,7 @@ impl PartialDecode {
                 "packet too short to contain payload length",
             )),
             Ordering::Greater => {
-                let rest = Some(buf.get_mut().split_off(packet_len));
+                let rest = Some(buf.get_mut().split_off(packet_len as usize));
                 Ok((Self { plain_header, buf }, rest))
             }
         }
@@ -843,10 +844,14 @@ impl slog::Value for ConnectionId {
 pub fn set_payload_length(packet: &mut [u8], header_len: usize, pn_len: usize, tag_len: usize) {
     let len = packet.len() - header_len + pn_len + tag_len;
     assert!(len < 2usize.pow(14)); // Fits in reserved space
-    BigEndian::write_u16(
-        &mut packet[header_len - pn_len - 2..],
-        len as u16 | 0b01 << 14,
-    );
+    write_payload_length(packet, header_len, pn_len, len as u16);
+}
+
+/// Write an arbitrary value into the reserved two-byte length field of a long header
+///
+/// Separate from `set_payload_length` so tests can construct packets with inconsistent lengths.
+fn write_payload_length(packet: &mut [u8], header_len: usize, pn_len: usize, len: u16) {
+    BigEndian::write_u16(&mut packet[header_len - pn_len - 2..], len | 0b01 << 14);
 }
 
 pub const LONG_HEADER_FORM: u8 = 0x80;
@@ -947,6 +952,61 @@ mod tests {
         }
     }
 
+    /// Encode an Initial packet with `payload_len` bytes of payload and a length field of `len`
+    fn initial_with_length(payload_len: usize, len: u16) -> (Vec<u8>, RingHeaderCrypto) {
+        let dcid = ConnectionId::new(&hex!("06b858ec6f80452b"));
+        let mut buf = Vec::new();
+        let header = Header::Initial {
+            number: PacketNumber::U8(0),
+            src_cid: ConnectionId::new(&[]),
+            dst_cid: dcid,
+            token: Bytes::new(),
+        };
+        header.encode(&mut buf);
+        let header_len = buf.len();
+        buf.resize(header_len + payload_len, 0);
+        write_payload_length(&mut buf, header_len, 1, len);
+        let crypto = Crypto::new_initial(&dcid, Side::Server);
+        (buf, crypto.header_crypto())
+    }
+
+    #[test]
+    fn length_exceeds_datagram() {
+        for &len in &[34, 100, 0x3fff] {
+            let (buf, _) = initial_with_length(32, len);
+            assert_eq!(
+                PartialDecode::new(buf.into(), 0).err(),
+                Some(PacketDecodeError::InvalidHeader(
+                    "packet too short to contain payload length"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn length_too_short_for_sample() {
+        for &len in &[0, 1, 4, 19] {
+            let (buf, header_crypto) = initial_with_length(32, len);
+            let (decode, rest) = PartialDecode::new(buf.into(), 0).unwrap();
+            assert_eq!(rest.map(|x| x.len()), Some(33 - len as usize));
+            assert_eq!(
+                decode.finish(Some(&header_crypto)).err(),
+                Some(PacketDecodeError::InvalidHeader(
+                    "packet too short to extract header protection sample"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_header() {
+        let (buf, _) = initial_with_length(32, 32);
+        // Cut the datagram off partway through the length field itself
+        for end in 1..buf.len() - 32 {
+            assert!(PartialDecode::new(buf[..end].into(), 0).is_err());
+        }
+    }
+
     #[test]
     fn header_encoding() {
         let dcid = ConnectionId::new(&hex!("06b858ec6f80452b"));
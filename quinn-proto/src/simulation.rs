@@ -0,0 +1,243 @@
+//! Deterministic in-memory network for exercising endpoints without sockets
+//!
+//! A `Network` owns any number of `Endpoint`s, each bound to a simulated address, and carries the
+//! datagrams they transmit to one another with configurable latency, jitter, and loss. Time is
+//! simulated too, advancing directly to the next timer expiry or delivery, so runs are fast and,
+//! for a given seed, reproducible.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::endpoint::{ConnectionHandle, Endpoint, Timer};
+use crate::{EcnCodepoint, TimerSetting, Transmit};
+
+/// A set of endpoints exchanging datagrams over a simulated network
+pub struct Network {
+    /// One-way delivery delay applied to every datagram
+    pub latency: Duration,
+    /// Upper bound on a random extra delay applied to each datagram, which may reorder them
+    pub jitter: Duration,
+    /// Probability, between 0 and 1, that any given datagram is lost
+    pub loss: f64,
+    time: Instant,
+    rng: StdRng,
+    nodes: Vec<Node>,
+    /// Number of datagrams sent so far, used to deliver simultaneous arrivals in send order
+    sent: u64,
+}
+
+struct Node {
+    endpoint: Endpoint,
+    addr: SocketAddr,
+    timers: BTreeMap<(ConnectionHandle, Timer), Instant>,
+    inbound: BTreeMap<(Instant, u64), Datagram>,
+}
+
+struct Datagram {
+    source: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    packet: Box<[u8]>,
+}
+
+impl Network {
+    /// Construct an empty, lossless network with no latency
+    ///
+    /// `seed` determines the outcome of all random loss and jitter.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            latency: Duration::new(0, 0),
+            jitter: Duration::new(0, 0),
+            loss: 0.0,
+            time: Instant::now(),
+            rng: StdRng::seed_from_u64(seed),
+            nodes: Vec::new(),
+            sent: 0,
+        }
+    }
+
+    /// Attach `endpoint` to the network at `addr`, returning its index
+    pub fn add(&mut self, endpoint: Endpoint, addr: SocketAddr) -> usize {
+        self.nodes.push(Node {
+            endpoint,
+            addr,
+            timers: BTreeMap::new(),
+            inbound: BTreeMap::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// The current simulated time
+    pub fn time(&self) -> Instant {
+        self.time
+    }
+
+    /// The address of the endpoint at `index`
+    pub fn addr(&self, index: usize) -> SocketAddr {
+        self.nodes[index].addr
+    }
+
+    pub fn endpoint(&self, index: usize) -> &Endpoint {
+        &self.nodes[index].endpoint
+    }
+
+    pub fn endpoint_mut(&mut self, index: usize) -> &mut Endpoint {
+        &mut self.nodes[index].endpoint
+    }
+
+    /// Process everything due at the current time, then advance to the next event
+    ///
+    /// Returns `false` once the only pending events are idle and keep-alive timers, which may
+    /// recur indefinitely.
+    pub fn step(&mut self) -> bool {
+        self.poll();
+        match self.next_event(false) {
+            Some(time) => {
+                self.time = cmp::max(self.time, time);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Step until only idle and keep-alive timers remain
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Step until `deadline`, honoring idle and keep-alive timers as well
+    pub fn run_until(&mut self, deadline: Instant) {
+        loop {
+            self.poll();
+            match self.next_event(true) {
+                Some(time) if time <= deadline => {
+                    self.time = cmp::max(self.time, time);
+                }
+                _ => {
+                    self.time = cmp::max(self.time, deadline);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Handle timers, deliveries, and transmits at the current time until nothing more is due
+    fn poll(&mut self) {
+        loop {
+            let mut progress = false;
+            for index in 0..self.nodes.len() {
+                progress |= self.poll_node(index);
+            }
+            if !progress {
+                return;
+            }
+        }
+    }
+
+    fn poll_node(&mut self, index: usize) -> bool {
+        let now = self.time;
+        let node = &mut self.nodes[index];
+        let mut progress = false;
+
+        let expired = node
+            .timers
+            .iter()
+            .filter(|&(_, &time)| time <= now)
+            .map(|(&key, _)| key)
+            .collect::<Vec<_>>();
+        for (ch, timer) in expired {
+            node.timers.remove(&(ch, timer));
+            // Timers may outlive a connection the endpoint has since forgotten
+            if node.endpoint.connections.contains(ch.0) {
+                node.endpoint.timeout(now, ch, timer);
+                progress = true;
+            }
+        }
+
+        while let Some(&key) = node.inbound.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            let datagram = node.inbound.remove(&key).unwrap();
+            node.endpoint.handle(
+                now,
+                datagram.source,
+                datagram.ecn,
+                Vec::from(datagram.packet).into(),
+            );
+            progress = true;
+        }
+
+        let mut transmits = Vec::new();
+        while let Some(x) = node.endpoint.poll_transmit(now) {
+            transmits.push(x);
+        }
+        while let Some((ch, x)) = node.endpoint.poll_timers() {
+            match x.update {
+                TimerSetting::Stop => {
+                    node.timers.remove(&(ch, x.timer));
+                }
+                TimerSetting::Start(time) => {
+                    node.timers.insert((ch, x.timer), time);
+                }
+            }
+        }
+
+        let source = node.addr;
+        progress |= !transmits.is_empty();
+        for x in transmits {
+            self.send(source, x);
+        }
+        progress
+    }
+
+    fn send(&mut self, source: SocketAddr, transmit: Transmit) {
+        self.sent += 1;
+        if self.loss > 0.0 && self.rng.gen::<f64>() < self.loss {
+            return;
+        }
+        let jitter = self.jitter.as_secs() * 1_000_000_000 + u64::from(self.jitter.subsec_nanos());
+        let delay = if jitter == 0 {
+            self.latency
+        } else {
+            self.latency + Duration::from_nanos(self.rng.gen_range(0, jitter + 1))
+        };
+        let key = (self.time + delay, self.sent);
+        if let Some(node) = self
+            .nodes
+            .iter_mut()
+            .find(|node| node.addr == transmit.destination)
+        {
+            node.inbound.insert(
+                key,
+                Datagram {
+                    source,
+                    ecn: transmit.ecn,
+                    packet: transmit.packet,
+                },
+            );
+        }
+    }
+
+    /// Time of the earliest pending delivery or timer
+    fn next_event(&self, include_idle: bool) -> Option<Instant> {
+        self.nodes
+            .iter()
+            .flat_map(|node| {
+                let timers = node
+                    .timers
+                    .iter()
+                    .filter(move |&(&(_, timer), _)| {
+                        include_idle || (timer != Timer::Idle && timer != Timer::KeepAlive)
+                    })
+                    .map(|(_, &time)| time);
+                let delivery = node.inbound.keys().next().map(|&(time, _)| time);
+                timers.chain(delivery)
+            })
+            .min()
+    }
+}
@@ -76,6 +76,23 @@ impl Streams {
     pub fn get_send_mut(&mut self, id: StreamId) -> Option<&mut Send> {
         self.streams.get_mut(&id)?.send_mut()
     }
+
+    /// Number of `dir` streams opened by either peer that are neither closed nor reset
+    pub fn open_count(&self, side: Side, dir: Directionality) -> u64 {
+        let next_remote = match dir {
+            Directionality::Bi => self.next_remote_bi,
+            Directionality::Uni => self.next_remote_uni,
+        };
+        self.streams
+            .iter()
+            .filter(|&(id, stream)| {
+                id.directionality() == dir
+                    && (id.initiator() == side || id.index() < next_remote)
+                    && !stream.is_closed()
+                    && !stream.is_reset()
+            })
+            .count() as u64
+    }
 }
 
 #[derive(Debug)]
@@ -126,6 +143,15 @@ impl Stream {
     pub fn is_closed(&self) -> bool {
         self.send().map_or(true, |x| x.is_closed()) && self.recv().map_or(true, |x| x.is_closed())
     }
+
+    /// Whether either direction has been abandoned by a reset
+    pub fn is_reset(&self) -> bool {
+        self.send().map_or(false, |x| x.state.was_reset())
+            || self.recv().map_or(false, |x| match x.state {
+                RecvState::ResetRecvd { .. } => true,
+                _ => false,
+            })
+    }
 }
 
 impl From<Send> for Stream {
@@ -199,6 +225,11 @@ pub enum WriteError {
     /// The peer is no longer accepting data on this stream.
     #[error(display = "stopped by peer: error {}", error_code)]
     Stopped { error_code: u16 },
+    /// The stream was opened in 0-RTT and discarded when the server rejected 0-RTT data.
+    ///
+    /// The stream must be reopened and any data written to it sent again.
+    #[error(display = "0-RTT rejected")]
+    ZeroRttRejected,
 }
 
 #[derive(Debug)]
@@ -213,6 +244,14 @@ pub struct Recv {
     /// Number of bytes read by the application. Equal to assembler.offset when `unordered` is
     /// false.
     pub bytes_read: u64,
+    /// Number of bytes whose flow control credit has been returned to the peer. At most
+    /// `bytes_read`, and equal to it unless credit is released manually.
+    pub released: u64,
+    /// Value of `released` last sent to the peer in a MAX_STREAM_DATA frame, which the peer's
+    /// flow control limit is based on
+    pub released_sent: u64,
+    /// Whether the application has asked the peer to stop sending, so incoming data is discarded
+    pub stopped: bool,
 }
 
 impl Recv {
@@ -224,6 +263,9 @@ impl Recv {
             unordered: false,
             assembler: Assembler::new(),
             bytes_read: 0,
+            released: 0,
+            released_sent: 0,
+            stopped: false,
         }
     }
 
@@ -300,6 +342,12 @@ impl Recv {
     }
 
     pub fn buffer(&mut self, data: Bytes, offset: u64) {
+        if self.stopped {
+            // Nobody will read this, so treat it as consumed on arrival
+            self.bytes_read = self.limit();
+            self.released = self.bytes_read;
+            return;
+        }
         // TODO: Dedup
         if data.is_empty() {
             return;
@@ -321,9 +369,25 @@ impl Recv {
         }
     }
 
-    pub fn reset(&mut self, error_code: u16, final_offset: u64) {
+    /// Discard buffered data and any that arrives later
+    ///
+    /// Returns the number of bytes of flow control credit released by treating everything
+    /// received so far as read and released.
+    pub fn stop(&mut self) -> u64 {
+        self.stopped = true;
+        self.buffered.clear();
+        self.assembler.clear();
+        let limit = self.limit();
+        let freed = limit - self.released;
+        self.bytes_read = limit;
+        self.released = limit;
+        freed
+    }
+
+    /// Returns the number of unread bytes discarded
+    pub fn reset(&mut self, error_code: u16, final_offset: u64) -> u64 {
         if self.is_closed() {
-            return;
+            return 0;
         }
         self.state = RecvState::ResetRecvd {
             size: final_offset,
@@ -335,6 +399,9 @@ impl Recv {
         // reset streams.
         self.buffered.clear();
         self.assembler.clear();
+        let discarded = final_offset - self.bytes_read;
+        self.bytes_read = final_offset;
+        discarded
     }
 }
 
@@ -478,6 +545,13 @@ impl Assembler {
         }
     }
 
+    /// Ensure capacity for at least `len` buffered bytes without reallocating
+    pub fn reserve(&mut self, len: usize) {
+        self.data.reserve(len.saturating_sub(self.data.len()));
+        let bitmap_len = len / 8 + 2;
+        self.written.reserve(bitmap_len.saturating_sub(self.written.len()));
+    }
+
     /// Current position in the stream
     pub fn offset(&self) -> u64 {
         self.offset
@@ -494,6 +568,35 @@ impl Assembler {
 mod test {
     use super::*;
 
+    #[test]
+    fn recv_stop() {
+        let mut x = Recv::new();
+        x.recvd.insert(0..3);
+        x.buffer(Bytes::from_static(b"123"), 0);
+        x.recvd.insert(5..8);
+        x.buffer(Bytes::from_static(b"678"), 5);
+        assert_eq!(x.stop(), 8);
+        assert!(x.buffered.is_empty());
+        assert!(x.is_blocked());
+
+        x.recvd.insert(8..10);
+        x.buffer(Bytes::from_static(b"9A"), 8);
+        assert!(x.buffered.is_empty());
+        assert_eq!(x.bytes_read, 10);
+        assert_eq!(x.released, 10);
+    }
+
+    #[test]
+    fn assemble_reserved() {
+        let mut x = Assembler::new();
+        x.reserve(1024);
+        assert!(x.data.capacity() >= 1024);
+        x.insert(3, &b"456"[..]);
+        x.insert(0, &b"123"[..]);
+        assert!(x.data.capacity() >= 1024);
+        assert_matches!(x.next(), Some(ref y) if &y[..] == b"123456");
+    }
+
     #[test]
     fn assemble_ordered() {
         let mut x = Assembler::new();
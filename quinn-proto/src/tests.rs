@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 use std::io::{self, Write};
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::ops::RangeFrom;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
@@ -141,6 +141,25 @@ impl Pair {
         }
     }
 
+    /// Replace the server with a fresh endpoint, as if its process had restarted
+    ///
+    /// Connections are lost, but anything shared through `server_config`, like the TLS session
+    /// cache, carries over.
+    fn restart_server(
+        &mut self,
+        endpoint_config: Arc<EndpointConfig>,
+        server_config: ServerConfig,
+    ) {
+        self.server.endpoint = Endpoint::new(
+            self.log.new(o!("side" => "Server")),
+            endpoint_config,
+            Some(Arc::new(server_config)),
+        )
+        .unwrap();
+        self.server.conn = None;
+        self.server.timers = [None; Timer::COUNT];
+    }
+
     /// Returns whether the connection is not idle
     fn step(&mut self) -> bool {
         self.drive_client();
@@ -455,6 +474,14 @@ fn lifecycle() {
     assert_matches!(pair.client.poll(), None);
     assert!(pair.client.connection(client_ch).using_ecn());
     assert!(pair.server.connection(server_ch).using_ecn());
+    assert_eq!(
+        pair.client.connection(client_ch).ecn_state(),
+        EcnState::Validated
+    );
+    assert_eq!(
+        pair.server.connection(server_ch).ecn_state(),
+        EcnState::Validated
+    );
 
     const REASON: &[u8] = b"whee";
     info!(pair.log, "closing");
@@ -468,6 +495,21 @@ fn lifecycle() {
     assert_matches!(pair.client.poll(), None);
 }
 
+#[test]
+fn negotiated_crypto() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let client = pair.client.connection(client_ch);
+    let server = pair.server.connection(server_ch);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    assert_eq!(server.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    assert!(client.negotiated_cipher_suite().is_some());
+    assert_eq!(
+        client.negotiated_cipher_suite(),
+        server.negotiated_cipher_suite()
+    );
+}
+
 #[test]
 fn stateless_retry() {
     let mut pair = Pair::new(
@@ -552,7 +594,7 @@ fn finish_stream() {
 
     assert_matches!(pair.client.poll(), Some((conn, Event::StreamFinished { stream })) if conn == client_ch && stream == s);
     assert_matches!(pair.client.poll(), None);
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(pair.server.poll(), None);
     assert_matches!(pair.server.read_unordered(server_ch, s), Ok((ref data, 0)) if data == MSG);
@@ -562,6 +604,89 @@ fn finish_stream() {
     );
 }
 
+#[test]
+fn lost_stream_data() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    pair.drive_client();
+    info!(pair.log, "dropping first write");
+    pair.server.inbound.clear();
+    pair.client.write(client_ch, s, b"world").unwrap();
+    pair.drive();
+
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 1);
+    assert!(
+        pair.server.connection(server_ch).packets_received()
+            < pair.client.connection(client_ch).packets_sent()
+    );
+    assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
+    let mut buf = [0; 10];
+    let mut read = 0;
+    while read < buf.len() {
+        read += pair.server.read(server_ch, s, &mut buf[read..]).unwrap();
+    }
+    assert_eq!(&buf, b"helloworld");
+}
+
+#[test]
+fn open_stream_count() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    let client_count = |pair: &Pair, dir| pair.client.connection(client_ch).open_stream_count(dir);
+    let server_count = |pair: &Pair, dir| pair.server.connection(server_ch).open_stream_count(dir);
+    assert_eq!(client_count(&pair, Directionality::Uni), 1);
+    assert_eq!(client_count(&pair, Directionality::Bi), 0);
+    assert_eq!(server_count(&pair, Directionality::Uni), 0);
+
+    const MSG: &[u8] = b"hello";
+    pair.client.write(client_ch, s, MSG).unwrap();
+    pair.drive();
+    assert_eq!(server_count(&pair, Directionality::Uni), 1);
+    assert_eq!(server_count(&pair, Directionality::Bi), 0);
+
+    pair.client.finish(client_ch, s);
+    pair.drive();
+    assert_eq!(client_count(&pair, Directionality::Uni), 0);
+    assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
+    assert_matches!(pair.server.read_unordered(server_ch, s), Ok((ref data, 0)) if data == MSG);
+    assert_matches!(
+        pair.server.read_unordered(server_ch, s),
+        Err(ReadError::Finished)
+    );
+    assert_eq!(server_count(&pair, Directionality::Uni), 0);
+}
+
+#[test]
+fn read_while_draining() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client.write(client_ch, s, MSG).unwrap();
+    pair.client.finish(client_ch, s);
+    pair.drive();
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
+
+    pair.client.close(pair.time, client_ch, 0, [][..].into());
+    // Deliver the close without letting the draining period elapse
+    pair.drive_client();
+    pair.drive_server();
+    assert!(pair.server.connection(server_ch).is_draining());
+
+    assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
+    assert_matches!(pair.server.read_unordered(server_ch, s), Ok((ref data, 0)) if data == MSG);
+    assert_matches!(
+        pair.server.read_unordered(server_ch, s),
+        Err(ReadError::Finished)
+    );
+}
+
 #[test]
 fn reset_stream() {
     let mut pair = Pair::default();
@@ -572,13 +697,22 @@ fn reset_stream() {
     const MSG: &[u8] = b"hello";
     pair.client.write(client_ch, s, MSG).unwrap();
     pair.drive();
+    assert_eq!(
+        pair.server.connection(server_ch).recv_offset(s),
+        Some(MSG.len() as u64)
+    );
 
     info!(pair.log, "resetting stream");
     const ERROR: u16 = 42;
     pair.client.reset(client_ch, s, ERROR);
     pair.drive();
 
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
+    assert_matches!(
+        pair.server.poll(),
+        Some((conn, Event::StreamReset { stream, error_code: ERROR }))
+            if conn == server_ch && stream == s
+    );
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(
         pair.server.read_unordered(server_ch, s),
@@ -602,7 +736,7 @@ fn stop_stream() {
     pair.server.stop_sending(server_ch, s, ERROR);
     pair.drive();
 
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(
         pair.server.read_unordered(server_ch, s),
@@ -615,6 +749,88 @@ fn stop_stream() {
     );
 }
 
+#[test]
+fn stop_sending_discards_data() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    pair.drive();
+
+    pair.server.stop_sending(server_ch, s, 42);
+    // Buffered data is freed immediately, not when the peer's reset arrives
+    let mut buf = [0; 5];
+    assert_eq!(
+        pair.server.read(server_ch, s, &mut buf),
+        Err(ReadError::Blocked)
+    );
+    pair.drive();
+    assert_eq!(
+        pair.server.read(server_ch, s, &mut buf),
+        Err(ReadError::Reset { error_code: 42 })
+    );
+}
+
+#[test]
+fn reset_send_still_receiving() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client.open(client_ch, Directionality::Bi).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    pair.drive();
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
+    assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
+
+    pair.client.reset(client_ch, s, 42);
+    pair.server.write(server_ch, s, b"world").unwrap();
+    pair.server.finish(server_ch, s);
+    pair.drive();
+
+    assert_matches!(
+        pair.server.read_unordered(server_ch, s),
+        Err(ReadError::Reset { error_code: 42 })
+    );
+    // Our own reset doesn't prevent us from receiving the peer's data
+    let mut buf = [0; 5];
+    assert_eq!(pair.client.read(client_ch, s, &mut buf), Ok(5));
+    assert_eq!(&buf, b"world");
+    assert_eq!(
+        pair.client.read(client_ch, s, &mut buf),
+        Err(ReadError::Finished)
+    );
+    assert!(!pair.client.connection(client_ch).is_closed());
+}
+
+#[test]
+fn stop_sending_then_data_arrives() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    pair.drive();
+
+    pair.server.stop_sending(server_ch, s, 42);
+    // Data already in flight when the STOP_SENDING is sent
+    pair.client.write(client_ch, s, &[0xAB; 1000]).unwrap();
+    pair.drive_client();
+    pair.drive();
+
+    let mut buf = [0; 5];
+    assert_eq!(
+        pair.server.read(server_ch, s, &mut buf),
+        Err(ReadError::Reset { error_code: 42 })
+    );
+    assert_matches!(
+        pair.client.write(client_ch, s, b"foo"),
+        Err(WriteError::Stopped { error_code: 42 })
+    );
+    assert!(!pair.server.connection(server_ch).is_closed());
+    assert!(!pair.client.connection(client_ch).is_closed());
+}
+
 #[test]
 fn reject_self_signed_cert() {
     let mut client_config = ClientConfig::new();
@@ -634,8 +850,12 @@ fn reject_self_signed_cert() {
         .unwrap();
     pair.drive();
     assert_matches!(pair.client.poll(),
-                    Some((conn, Event::ConnectionLost { reason: ConnectionError::TransportError(ref error)}))
-                    if conn == client_ch && error.code == TransportErrorCode::crypto(AlertDescription::BadCertificate.get_u8()));
+                    Some((conn, Event::ConnectionLost { reason: reason @ ConnectionError::TransportError(_) }))
+                    if conn == client_ch && reason.tls_alert() == Some(AlertDescription::BadCertificate.get_u8()));
+    assert_matches!(pair.server.poll(), Some((_, Event::Handshaking)));
+    assert_matches!(pair.server.poll(),
+                    Some((_, Event::ConnectionLost { reason: reason @ ConnectionError::ConnectionClosed { .. } }))
+                    if reason.tls_alert() == Some(AlertDescription::BadCertificate.get_u8()));
 }
 
 #[test]
@@ -675,6 +895,184 @@ fn high_latency_handshake() {
     assert!(pair.server.connection(server_ch).using_ecn());
 }
 
+#[test]
+fn low_latency_handshake_sends_no_probes() {
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(1);
+    let (client_ch, server_ch) = pair.connect();
+    pair.drive();
+    let sent = pair.client.connection(client_ch).packets_sent();
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 0);
+    assert_eq!(pair.server.connection(server_ch).lost_packets(), 0);
+    assert_eq!(pair.server.connection(server_ch).packets_received(), sent);
+}
+
+#[test]
+fn long_delay_handshake_sends_no_probes() {
+    let config = Arc::new(TransportConfig::long_delay());
+    let server = ServerConfig {
+        transport_config: config.clone(),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    pair.latency = Duration::from_millis(350);
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, config, client_config(), "localhost")
+        .unwrap();
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert_matches!(pair.client.poll(), Some((_, Event::Connected { .. })));
+    let sent = pair.client.connection(client_ch).packets_sent();
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 0);
+    assert_eq!(pair.server.connection(server_ch).lost_packets(), 0);
+    assert_eq!(pair.server.connection(server_ch).packets_received(), sent);
+}
+
+#[test]
+fn spin_rtt() {
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, server_ch) = pair.connect();
+    for _ in 0..4 {
+        pair.client.ping(client_ch);
+        pair.drive();
+    }
+    // Edges can't arrive more often than once per round trip
+    let rtt = pair.client.connection(client_ch).spin_rtt().unwrap();
+    assert!(rtt >= 2 * pair.latency);
+    assert!(pair.server.connection(server_ch).spin_rtt().is_some());
+}
+
+#[test]
+fn rtt_info() {
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect();
+    let info = pair.client.connection(client_ch).rtt_info().unwrap();
+    assert_eq!(Some(info.smoothed), pair.client.connection(client_ch).rtt());
+    assert!(info.min >= 2 * pair.latency);
+    assert!(info.min <= info.smoothed);
+}
+
+#[test]
+fn app_limited_congestion() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let window = pair.client.connection(client_ch).congestion_state();
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    // A trickle of data never fills the window, so says nothing about whether it could be larger
+    for _ in 0..100 {
+        pair.client.write(client_ch, s, &[42; 100]).unwrap();
+        pair.drive();
+    }
+    assert_eq!(pair.client.connection(client_ch).congestion_state(), window);
+}
+
+#[test]
+fn congestion_warmup() {
+    let mut pair = Pair::default();
+    let initial_window = TransportConfig::default().initial_window;
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    // A stale or tiny estimate never shrinks the window
+    pair.client.warm_up(pair.time, client_ch, initial_window);
+    assert_eq!(pair.client.connection(client_ch).congestion_state(), initial_window);
+    pair.client.warm_up(pair.time, client_ch, 8 * initial_window);
+    assert_eq!(pair.client.connection(client_ch).congestion_state(), 4 * initial_window);
+    pair.drive();
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 0);
+    assert!(pair.client.connection(client_ch).congestion_state() >= 4 * initial_window);
+}
+
+#[test]
+fn custom_congestion_controller() {
+    struct FixedWindow(u64);
+
+    impl congestion::CongestionController for FixedWindow {
+        fn on_ack(&mut self, _: &congestion::Context, _: Instant, _: u64, _: bool) {}
+        fn on_congestion_event(&mut self, _: &congestion::Context, _: Instant) {}
+        fn on_persistent_congestion(&mut self, _: &congestion::Context) {}
+        fn on_path_change(&mut self, _: &congestion::Context) {}
+        fn window(&self) -> u64 {
+            self.0
+        }
+    }
+
+    const WINDOW: u64 = 4000;
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            congestion_controller: Some(Arc::new(|_: &TransportConfig| {
+                Box::new(FixedWindow(WINDOW)) as Box<dyn congestion::CongestionController>
+            })),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+    assert_eq!(pair.server.connection(server_ch).congestion_state(), WINDOW);
+
+    let s = pair.server.open(server_ch, Directionality::Uni).unwrap();
+    let msg = vec![0xab; 32 * 1024];
+    assert_eq!(pair.server.write(server_ch, s, &msg).unwrap(), msg.len());
+    pair.server.finish(server_ch, s);
+    pair.drive_server();
+    assert!(pair.server.connection(server_ch).bytes_in_flight() <= WINDOW);
+
+    // The fixed window still lets everything through, a window at a time
+    pair.drive();
+    assert_eq!(pair.server.connection(server_ch).bytes_in_flight(), 0);
+    assert_eq!(pair.server.connection(server_ch).congestion_state(), WINDOW);
+    assert_eq!(pair.server.connection(server_ch).lost_packets(), 0);
+}
+
+#[test]
+fn first_flight_data() {
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(100);
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    let connected_at = loop {
+        pair.drive_client();
+        if let Some((ch, Event::Connected)) = pair.client.poll() {
+            assert_eq!(ch, client_ch);
+            break pair.time;
+        }
+        pair.drive_server();
+        pair.time = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()).unwrap();
+    };
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    pair.drive_client();
+
+    // The data should arrive together with the client's handshake completion, without waiting on
+    // any further round trip
+    pair.time = connected_at + pair.latency;
+    pair.drive_server();
+    let server_ch = pair.server.assert_accept();
+    assert_matches!(pair.server.poll(), Some((ch, Event::Connected)) if ch == server_ch);
+    assert_matches!(pair.server.poll(), Some((ch, Event::StreamOpened { .. })) if ch == server_ch);
+    let mut buf = [0; 5];
+    assert_eq!(pair.server.read(server_ch, s, &mut buf), Ok(5));
+    assert_eq!(&buf, b"hello");
+}
+
 #[test]
 fn zero_rtt() {
     let mut pair = Pair::default();
@@ -705,11 +1103,18 @@ fn zero_rtt() {
         .connect(pair.server.addr, Default::default(), config, "localhost")
         .unwrap();
     assert!(pair.client.connection(client_ch).has_0rtt());
+    assert!(pair.client.connection(client_ch).can_send_0rtt());
     let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
     const MSG: &[u8] = b"Hello, 0-RTT!";
     pair.client.write(client_ch, s, MSG).unwrap();
     pair.drive();
     assert!(pair.client.connection(client_ch).accepted_0rtt());
+    assert!(!pair.client.connection(client_ch).can_send_0rtt());
+    assert_matches!(
+        pair.client.connections[client_ch].poll(),
+        Some(Event::ZeroRttAccepted)
+    );
+    assert_matches!(pair.client.connections[client_ch].poll(), Some(Event::Connected));
     let server_ch = pair.server.assert_accept();
     assert_matches!(pair.server.read_unordered(server_ch, s), Ok((ref data, 0)) if data == MSG);
     assert_eq!(pair.client.connection(client_ch).lost_packets(), 0);
@@ -754,9 +1159,18 @@ fn zero_rtt_rejection() {
     pair.client.write(client_conn, s, MSG).unwrap();
     pair.drive();
     assert!(!pair.client.connection(client_conn).accepted_0rtt());
+    assert_matches!(
+        pair.client.connections[client_conn].poll(),
+        Some(Event::ZeroRttRejected)
+    );
+    assert_matches!(pair.client.connections[client_conn].poll(), Some(Event::Connected));
     let server_conn = pair.server.assert_accept();
     assert_matches!(pair.server.poll(), Some((_, Event::Connected)));
     assert_matches!(pair.server.poll(), None);
+    assert_eq!(
+        pair.client.write(client_conn, s, MSG),
+        Err(WriteError::ZeroRttRejected)
+    );
     let s2 = pair.client.open(client_conn, Directionality::Uni).unwrap();
     assert_eq!(s, s2);
     assert_eq!(
@@ -767,37 +1181,329 @@ fn zero_rtt_rejection() {
 }
 
 #[test]
-fn close_during_handshake() {
+fn zero_rtt_rejection_releases_send_window() {
     let mut pair = Pair::default();
-    let c = pair
+    let mut config = client_config();
+    let transport = Arc::new(TransportConfig {
+        send_window: 1024,
+        ..TransportConfig::default()
+    });
+
+    // Establish normal connection
+    let client_conn = pair
         .client
         .connect(
             pair.server.addr,
-            Default::default(),
-            client_config(),
+            transport.clone(),
+            config.clone(),
             "localhost",
         )
         .unwrap();
-    pair.client.close(pair.time, c, 0, Bytes::new());
-    // This never actually sends the client's Initial; we may want to behave better here.
-}
-
-#[test]
-fn stream_id_backpressure() {
-    let server = ServerConfig {
-        transport_config: Arc::new(TransportConfig {
-            stream_window_uni: 1,
-            ..TransportConfig::default()
-        }),
-        ..server_config()
-    };
-    let mut pair = Pair::new(Default::default(), server);
-    let (client_ch, server_ch) = pair.connect();
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client.close(pair.time, client_conn, 0, [][..].into());
+    pair.drive();
+
+    // Changing protocols invalidates 0-RTT
+    Arc::get_mut(&mut config)
+        .unwrap()
+        .set_protocols(&["foo".into()]);
+    info!(pair.log, "resuming session");
+    let client_conn = pair
+        .client
+        .connect(pair.server.addr, transport, config, "localhost")
+        .unwrap();
+    assert!(pair.client.connection(client_conn).has_0rtt());
+    let s = pair.client.open(client_conn, Directionality::Uni).unwrap();
+    const MSG: &[u8] = &[0xab; 1024];
+    assert_eq!(pair.client.write(client_conn, s, MSG), Ok(MSG.len()));
+    pair.drive();
+    assert!(!pair.client.connection(client_conn).accepted_0rtt());
+
+    // The discarded 0-RTT data must not count against the send window
+    let s = pair.client.open(client_conn, Directionality::Uni).unwrap();
+    assert_eq!(pair.client.write(client_conn, s, MSG), Ok(MSG.len()));
+}
+
+#[test]
+fn no_session_tickets() {
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            session_tickets: false,
+            ..server_config()
+        },
+    );
+    let config = client_config();
+
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            config.clone(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client.close(pair.time, client_ch, 0, [][..].into());
+    pair.drive();
+
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    info!(pair.log, "attempting to resume session");
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, Default::default(), config, "localhost")
+        .unwrap();
+    // Without a ticket from the first connection, there's nothing to resume
+    assert!(!pair.client.connection(client_ch).has_0rtt());
+    pair.drive();
+    pair.server.assert_accept();
+    assert!(!pair.client.connection(client_ch).accepted_0rtt());
+}
+
+#[test]
+fn zero_rtt_disabled() {
+    let server_config = server_config();
+    let tls_config = server_config.tls_config.clone();
+    let mut pair = Pair::new(Default::default(), server_config);
+    let config = client_config();
+
+    // Establish normal connection, obtaining a ticket that permits 0-RTT
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            config.clone(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client.close(pair.time, client_ch, 0, [][..].into());
+    pair.drive();
+
+    // Restart the server with 0-RTT disabled, preserving the TLS session cache
+    pair.restart_server(
+        Default::default(),
+        ServerConfig {
+            tls_config,
+            accept_0rtt: false,
+            ..Default::default()
+        },
+    );
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    info!(pair.log, "resuming session");
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, Default::default(), config, "localhost")
+        .unwrap();
+    assert!(pair.client.connection(client_ch).has_0rtt());
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    const MSG: &[u8] = b"Hello, 0-RTT!";
+    pair.client.write(client_ch, s, MSG).unwrap();
+    pair.drive();
+    assert!(!pair.client.connection(client_ch).accepted_0rtt());
+    assert_matches!(pair.client.poll(), Some((_, Event::ZeroRttRejected)));
+    assert_matches!(pair.client.poll(), Some((_, Event::Connected)));
+    assert_eq!(
+        pair.client.write(client_ch, s, MSG),
+        Err(WriteError::ZeroRttRejected)
+    );
+}
+
+#[test]
+fn zero_rtt_reduced_limits() {
+    let server_config = server_config();
+    let tls_config = server_config.tls_config.clone();
+    let mut pair = Pair::new(Default::default(), server_config);
+    let config = client_config();
+
+    // Establish normal connection, caching the server's transport parameters
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            config.clone(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client.close(pair.time, client_ch, 0, [][..].into());
+    pair.drive();
+
+    // Restart the server with a smaller connection-level flow control window, preserving the TLS
+    // session cache so 0-RTT is still accepted
+    pair.restart_server(
+        Default::default(),
+        ServerConfig {
+            transport_config: Arc::new(TransportConfig {
+                receive_window: TransportConfig::default().receive_window / 2,
+                ..TransportConfig::default()
+            }),
+            tls_config,
+            ..Default::default()
+        },
+    );
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    info!(pair.log, "resuming session");
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, Default::default(), config, "localhost")
+        .unwrap();
+    assert!(pair.client.connection(client_ch).has_0rtt());
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"Hello, 0-RTT!").unwrap();
+    pair.drive();
+    assert_matches!(pair.client.poll(),
+                    Some((conn, Event::ConnectionLost { reason: ConnectionError::TransportError(ref error)}))
+                    if conn == client_ch && error.code == TransportErrorCode::PROTOCOL_VIOLATION);
+}
+
+#[test]
+fn zero_rtt_stale_limits() {
+    let server_config = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            receive_window: 1000,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let tls_config = server_config.tls_config.clone();
+    let mut pair = Pair::new(Default::default(), server_config);
+    let config = client_config();
+
+    // Establish normal connection, caching the server's transport parameters
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            config.clone(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client.close(pair.time, client_ch, 0, [][..].into());
+    pair.drive();
+
+    // Restart the server with a larger window than the client remembers
+    pair.restart_server(
+        Default::default(),
+        ServerConfig {
+            transport_config: Arc::new(TransportConfig {
+                receive_window: 3000,
+                ..TransportConfig::default()
+            }),
+            tls_config,
+            ..Default::default()
+        },
+    );
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    info!(pair.log, "resuming session");
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, Default::default(), config, "localhost")
+        .unwrap();
+    assert!(pair.client.connection(client_ch).has_0rtt());
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    const MSG: &[u8] = &[0xab; 5000];
+    // 0-RTT data is limited by the remembered window
+    assert_eq!(pair.client.write(client_ch, s, MSG), Ok(1000));
+    pair.drive();
+    assert!(pair.client.connection(client_ch).accepted_0rtt());
+    assert!(!pair.client.connection(client_ch).is_closed());
+
+    // Data sent in 0-RTT still counts against the window the server actually advertised
+    assert_eq!(pair.client.write(client_ch, s, &MSG[1000..]), Ok(2000));
+    pair.drive();
+    assert!(!pair.client.connection(client_ch).is_closed());
+    assert!(!pair.server.connection(pair.server.conn.unwrap()).is_closed());
+}
+
+#[test]
+fn close_during_handshake() {
+    let mut pair = Pair::default();
+    let c = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.client.close(pair.time, c, 0, Bytes::new());
+    // This never actually sends the client's Initial; we may want to behave better here.
+}
+
+#[test]
+fn application_close_during_handshake() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive_client();
+    pair.drive_server();
+    let server_ch = pair.server.conn.unwrap();
+    assert_matches!(pair.server.poll(), Some((ch, Event::Handshaking)) if ch == server_ch);
+
+    // The client only has Initial keys, so its close must be a transport-level frame
+    pair.client.close(pair.time, client_ch, 42, Bytes::from_static(b"secret"));
+    pair.drive();
+    assert_matches!(
+        pair.server.poll(),
+        Some((ch, Event::ConnectionLost {
+            reason: ConnectionError::ConnectionClosed { ref reason }
+        })) if ch == server_ch
+            && reason.error_code == TransportErrorCode::APPLICATION_ERROR
+            && reason.reason.is_empty()
+    );
+}
+
+#[test]
+fn stream_id_backpressure() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            stream_window_uni: 1,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    let uni = Directionality::Uni;
+    assert_eq!(pair.client.connection(client_ch).remaining_local_streams(uni), 1);
+    assert_eq!(pair.server.connection(server_ch).remote_stream_limit(uni), 1);
 
     let s = pair
         .client
         .open(client_ch, Directionality::Uni)
         .expect("couldn't open first stream");
+    assert_eq!(pair.client.connection(client_ch).remaining_local_streams(uni), 0);
     assert_eq!(
         pair.client.open(client_ch, Directionality::Uni),
         None,
@@ -808,7 +1514,7 @@ fn stream_id_backpressure() {
     pair.drive();
     assert_matches!(pair.client.poll(), Some((conn, Event::StreamFinished { stream })) if conn == client_ch && stream == s);
     assert_matches!(pair.client.poll(), None);
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(
         pair.server.read_unordered(server_ch, s),
@@ -818,6 +1524,10 @@ fn stream_id_backpressure() {
     pair.drive();
     assert_matches!(pair.client.poll(), Some((conn, Event::StreamAvailable { directionality: Directionality::Uni })) if conn == client_ch);
     assert_matches!(pair.client.poll(), None);
+    assert_eq!(pair.client.connection(client_ch).remaining_local_streams(uni), 1);
+    let server_conn = pair.server.connection(server_ch);
+    assert_eq!(server_conn.remote_stream_limit(uni), 2);
+    assert_eq!(server_conn.remaining_remote_streams(uni), 1);
 
     // Try opening the second stream again, now that we've made room
     let s = pair
@@ -827,7 +1537,7 @@ fn stream_id_backpressure() {
     pair.client.finish(client_ch, s);
     pair.drive();
     // Make sure the server actually processes data on the newly-available stream
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(pair.server.poll(), None);
     assert_matches!(
@@ -836,6 +1546,36 @@ fn stream_id_backpressure() {
     );
 }
 
+#[test]
+fn stop_accepting_streams() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            stream_window_uni: 1,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    pair.server.stop_accepting_streams(server_ch);
+
+    // Streams already permitted can still be opened
+    let s = pair
+        .client
+        .open(client_ch, Directionality::Uni)
+        .expect("couldn't open first stream");
+    pair.client.finish(client_ch, s);
+    pair.drive();
+    assert_matches!(pair.client.poll(), Some((conn, Event::StreamFinished { stream })) if conn == client_ch && stream == s);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
+    assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
+
+    // But accepting them no longer makes room for more
+    pair.drive();
+    assert_matches!(pair.client.poll(), None);
+    assert_eq!(pair.client.open(client_ch, Directionality::Uni), None);
+}
+
 #[test]
 fn key_update() {
     let mut pair = Pair::default();
@@ -849,7 +1589,7 @@ fn key_update() {
     pair.client.write(client_ch, s, MSG1).unwrap();
     pair.drive();
 
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(pair.server.poll(), None);
     assert_matches!(
@@ -895,7 +1635,7 @@ fn key_update_reordered() {
     pair.client.finish_delay();
     pair.drive();
 
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_ch);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_ch);
     assert_matches!(pair.server.accept_stream(server_ch), Some(stream) if stream == s);
     assert_matches!(pair.server.poll(), None);
     assert_matches!(
@@ -911,19 +1651,45 @@ fn key_update_reordered() {
 }
 
 #[test]
-fn initial_retransmit() {
+fn max_reordering() {
     let mut pair = Pair::default();
-    let client_ch = pair
-        .client
-        .connect(
-            pair.server.addr,
-            Default::default(),
-            client_config(),
-            "localhost",
-        )
-        .unwrap();
-    pair.client.drive(&pair.log, pair.time, pair.server.addr);
-    pair.client.outbound.clear(); // Drop initial
+    let (client_ch, server_ch) = pair.connect();
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    assert_eq!(pair.client.connection(client_ch).max_reordering(), 0);
+
+    pair.client.write(client_ch, s, b"first").unwrap();
+    pair.client.drive(&pair.log, pair.time, pair.server.addr);
+    pair.client.delay_outbound();
+    for _ in 0..4 {
+        pair.client.write(client_ch, s, b"later").unwrap();
+        pair.drive_client();
+    }
+    // The acknowledgement of the later packets causes the first to be deemed lost
+    pair.drive_server();
+    pair.drive_client();
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 1);
+
+    pair.client.finish_delay();
+    pair.drive();
+    assert_eq!(pair.client.connection(client_ch).max_reordering(), 4);
+    assert_eq!(pair.client.connection(client_ch).reordered_packets_acked(), 1);
+    assert_eq!(pair.server.connection(server_ch).reordered_packets_received(), 1);
+}
+
+#[test]
+fn initial_retransmit() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.client.drive(&pair.log, pair.time, pair.server.addr);
+    pair.client.outbound.clear(); // Drop initial
     pair.drive();
     assert_matches!(pair.client.poll(), Some((conn, Event::Connected { .. })) if conn == client_ch);
 }
@@ -947,6 +1713,37 @@ fn instant_close() {
     assert_matches!(pair.server.poll(), None);
 }
 
+#[test]
+fn late_initial_after_drain() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive_client();
+    let initial = pair.server.inbound.front().unwrap().2.clone();
+    pair.drive();
+    pair.server.assert_accept();
+    assert_matches!(pair.client.poll(), Some((_, Event::Connected { .. })));
+    assert_matches!(pair.server.poll(), Some((_, Event::Connected { .. })));
+
+    pair.client.close(pair.time, client_ch, 0, Bytes::new());
+    pair.drive();
+    assert_matches!(pair.server.poll(), Some((_, Event::ConnectionLost { .. })));
+    assert_eq!(pair.server.connection_count(), 0);
+
+    info!(pair.log, "redelivering initial");
+    pair.server.inbound.push_back((pair.time, None, initial));
+    pair.drive();
+    assert_matches!(pair.server.poll(), None);
+    assert_eq!(pair.server.connection_count(), 0);
+}
+
 #[test]
 fn instant_close_2() {
     let mut pair = Pair::default();
@@ -971,6 +1768,73 @@ fn instant_close_2() {
     }})) if reason.is_empty());
 }
 
+#[test]
+fn simultaneous_close_drains_early() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let start = pair.time;
+    let period = pair.client.connection(client_ch).draining_period();
+    pair.client.close(start, client_ch, 42, Bytes::new());
+    pair.server.close(start, server_ch, 42, Bytes::new());
+    pair.drive_client();
+    pair.drive_server();
+    pair.drive_client();
+    // Each side saw the other's close, so neither needs to linger for the full period
+    let client_close = pair.client.timers[Timer::Close as usize].unwrap();
+    let server_close = pair.server.timers[Timer::Close as usize].unwrap();
+    assert!(client_close < start + period);
+    assert!(server_close < start + period);
+    pair.drive();
+    assert_eq!(pair.client.connection_count(), 0);
+    assert_eq!(pair.server.connection_count(), 0);
+}
+
+#[test]
+fn unresponsive_peer_times_out() {
+    const IDLE_TIMEOUT: u64 = 10;
+    let transport = Arc::new(TransportConfig {
+        idle_timeout: IDLE_TIMEOUT,
+        keep_alive_interval: 1,
+        ..TransportConfig::default()
+    });
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, transport, client_config(), "localhost")
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+    assert_matches!(pair.client.poll(), Some((ch, Event::Connected)) if ch == client_ch);
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    let start = pair.time;
+    let sent = pair.client.connection(client_ch).packets_sent();
+    while !pair.client.connection(client_ch).is_closed() {
+        if !pair.step() {
+            if let Some(t) = pair.client.next_wakeup() {
+                pair.time = t;
+            }
+        }
+        // Simulate a peer that has vanished entirely
+        pair.client.inbound.clear();
+        pair.server.inbound.clear();
+    }
+
+    // Probes and keep-alives went unanswered without postponing the timeout
+    assert!(pair.client.connection(client_ch).packets_sent() > sent + 2);
+    assert!(pair.time - start <= Duration::from_secs(IDLE_TIMEOUT));
+    assert_matches!(
+        pair.client.poll(),
+        Some((
+            _,
+            Event::ConnectionLost {
+                reason: ConnectionError::TimedOut,
+            },
+        ))
+    );
+}
+
 #[test]
 fn idle_timeout() {
     const IDLE_TIMEOUT: u64 = 10;
@@ -1018,6 +1882,32 @@ fn idle_timeout() {
     );
 }
 
+#[test]
+fn idle_timeout_remaining() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let now = pair.time;
+    let conn = pair.client.connection(client_ch);
+    let remaining = conn.idle_timeout_remaining(now).unwrap();
+    let timeout = Duration::from_secs(TransportConfig::default().idle_timeout);
+    assert!(remaining > Duration::new(0, 0) && remaining <= timeout);
+    let later = now + Duration::from_secs(1);
+    assert_eq!(
+        conn.idle_timeout_remaining(later),
+        Some(remaining - Duration::from_secs(1))
+    );
+    assert_eq!(
+        conn.idle_timeout_remaining(now + timeout),
+        Some(Duration::new(0, 0))
+    );
+
+    pair.client.close(now, client_ch, 0, Bytes::new());
+    assert_eq!(
+        pair.client.connection(client_ch).idle_timeout_remaining(now),
+        None
+    );
+}
+
 #[test]
 fn server_busy() {
     let mut pair = Pair::new(
@@ -1055,6 +1945,147 @@ fn server_busy() {
     assert_matches!(pair.server.poll(), None);
 }
 
+#[test]
+fn reject_new_connections() {
+    let mut pair = Pair::default();
+    pair.server.reject_new_connections();
+    pair.client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((
+            _,
+            Event::ConnectionLost {
+                reason:
+                    ConnectionError::ConnectionClosed {
+                        reason:
+                            frame::ConnectionClose {
+                                error_code: TransportErrorCode::SERVER_BUSY,
+                                ..
+                            },
+                    },
+            },
+        ))
+    );
+    assert_matches!(pair.server.poll(), None);
+}
+
+#[test]
+fn max_connections() {
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            max_connections: 1,
+            ..server_config()
+        },
+    );
+    pair.connect();
+    assert_eq!(pair.server.connection_count(), 1);
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((
+            ch,
+            Event::ConnectionLost {
+                reason:
+                    ConnectionError::ConnectionClosed {
+                        reason:
+                            frame::ConnectionClose {
+                                error_code: TransportErrorCode::SERVER_BUSY,
+                                ..
+                            },
+                    },
+            },
+        )) if ch == client_ch
+    );
+    assert_matches!(pair.server.poll(), None);
+    assert_eq!(pair.server.connection_count(), 1);
+}
+
+#[test]
+fn incoming_filter_refuse() {
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            incoming_filter: Some(Arc::new(|_: &SocketAddr, _: &ConnectionId| {
+                IncomingAction::Refuse
+            })),
+            ..server_config()
+        },
+    );
+    pair.client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((
+            _,
+            Event::ConnectionLost {
+                reason:
+                    ConnectionError::ConnectionClosed {
+                        reason:
+                            frame::ConnectionClose {
+                                error_code: TransportErrorCode::SERVER_BUSY,
+                                ..
+                            },
+                    },
+            },
+        ))
+    );
+    assert_matches!(pair.server.poll(), None);
+}
+
+#[test]
+fn incoming_filter_ignore() {
+    let client_port = Arc::new(Mutex::new(None));
+    let seen = client_port.clone();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            incoming_filter: Some(Arc::new(move |remote: &SocketAddr, _: &ConnectionId| {
+                *seen.lock().unwrap() = Some(remote.port());
+                IncomingAction::Ignore
+            })),
+            ..server_config()
+        },
+    );
+    pair.client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive_client();
+    pair.drive_server();
+    assert_eq!(*client_port.lock().unwrap(), Some(pair.client.addr.port()));
+    assert!(pair.client.inbound.is_empty());
+    assert_matches!(pair.server.poll(), None);
+}
+
 #[test]
 fn server_hs_retransmit() {
     let mut pair = Pair::default();
@@ -1121,20 +2152,775 @@ fn decode_coalesced() {
 }
 
 #[test]
-fn migration() {
+fn ecn_ect1() {
+    let transport = Arc::new(TransportConfig {
+        ecn_codepoint: EcnCodepoint::ECT1,
+        ..TransportConfig::default()
+    });
+    let server = ServerConfig {
+        transport_config: transport.clone(),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let client_ch = pair
+        .client
+        .connect(pair.server.addr, transport, client_config(), "localhost")
+        .unwrap();
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert_matches!(pair.client.poll(), Some((conn, Event::Connected { .. })) if conn == client_ch);
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, b"hello").unwrap();
+    pair.drive_client();
+    assert!(pair
+        .server
+        .inbound
+        .iter()
+        .all(|&(_, ecn, _)| ecn == Some(EcnCodepoint::ECT1)));
+    pair.drive();
+    // Feedback for ECT(1)-marked packets must pass validation
+    assert!(pair.client.connection(client_ch).using_ecn());
+    assert!(pair.server.connection(server_ch).using_ecn());
+}
+
+#[test]
+fn delayed_ack() {
     let mut pair = Pair::default();
     let (client_ch, server_ch) = pair.connect();
-    pair.client.addr = SocketAddr::new(
-        Ipv4Addr::new(127, 0, 0, 1).into(),
-        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    let acks_before = pair.server.connection(server_ch).ack_only_packets();
+
+    pair.client.ping(client_ch);
+    pair.drive_client();
+    pair.drive_server();
+    assert!(
+        pair.client.inbound.is_empty(),
+        "a lone ack-eliciting packet should be acknowledged after a delay"
     );
+
     pair.client.ping(client_ch);
-    pair.drive();
-    assert_matches!(pair.client.poll(), None);
-    assert_eq!(pair.server.connection(server_ch).remote(), pair.client.addr);
-}
+    pair.drive_client();
+    pair.drive_server();
+    assert!(
+        !pair.client.inbound.is_empty(),
+        "every second ack-eliciting packet should be acknowledged immediately"
+    );
 
-fn test_flow_control(config: TransportConfig, window_size: usize) {
+    pair.drive();
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 0);
+    assert_eq!(
+        pair.server.connection(server_ch).ack_only_packets() - acks_before,
+        1,
+        "both pings should be covered by a single acknowledgement"
+    );
+}
+
+#[test]
+fn ack_delay_exponent() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            ack_delay_exponent: 10,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    pair.latency = Duration::from_millis(10);
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Arc::new(TransportConfig {
+                ack_delay_exponent: 5,
+                ..TransportConfig::default()
+            }),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+
+    // Lone pings are acknowledged only after the peer's full ack delay, which must be accounted for
+    // to recover the true round trip time
+    let plausible = |rtt: Option<Duration>| {
+        let rtt = rtt.unwrap();
+        rtt >= Duration::from_millis(20) && rtt < Duration::from_millis(21)
+    };
+    pair.client.ping(client_ch);
+    pair.drive();
+    let rtt = pair.client.connection(client_ch).latest_rtt(SpaceId::Data);
+    assert!(plausible(rtt), "client RTT {:?}", rtt);
+    pair.server.ping(server_ch);
+    pair.drive();
+    let rtt = pair.server.connection(server_ch).latest_rtt(SpaceId::Data);
+    assert!(plausible(rtt), "server RTT {:?}", rtt);
+}
+
+#[test]
+fn sent_packets_tracked() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let baseline = pair
+        .client
+        .connection(client_ch)
+        .sent_packets_tracked(SpaceId::Data);
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, &[0xab; 4000]).unwrap();
+    pair.client.drive(&pair.log, pair.time, pair.server.addr);
+    let sent = pair.client.outbound.len();
+    assert!(sent > 1);
+    let client = pair.client.connection(client_ch);
+    assert_eq!(client.sent_packets_tracked(SpaceId::Data), baseline + sent);
+
+    pair.drive();
+    let client = pair.client.connection(client_ch);
+    assert_eq!(client.bytes_in_flight(), 0);
+    assert!(client.sent_packets_tracked(SpaceId::Data) < baseline + sent);
+}
+
+#[test]
+fn per_space_rtt() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    assert!(pair
+        .client
+        .connection(client_ch)
+        .latest_rtt(SpaceId::Initial)
+        .is_some());
+
+    pair.client.ping(client_ch);
+    pair.drive();
+    let client = pair.client.connection(client_ch);
+    assert!(client.latest_rtt(SpaceId::Data).is_some());
+    assert!(client.rtt().is_some());
+}
+
+#[test]
+fn congestion_window_collapse() {
+    let defaults = TransportConfig::default();
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            minimum_window: defaults.initial_window / 2,
+            loss_reduction_factor: 0,
+            ..defaults
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+
+    let s = pair.server.open(server_ch, Directionality::Uni).unwrap();
+    pair.server.write(server_ch, s, b"hello").unwrap();
+    pair.drive_server();
+    pair.client.inbound.clear();
+    pair.server.write(server_ch, s, b"world").unwrap();
+    pair.drive();
+
+    assert_matches!(
+        pair.server.poll(),
+        Some((conn, Event::CongestionWindowReduced { old, new }))
+            if conn == server_ch && old > new && new == defaults.initial_window / 2
+    );
+}
+
+#[test]
+fn pending_bytes() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    assert_eq!(pair.client.connection(client_ch).pending_bytes(), 0);
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    const MSG: &[u8] = &[0xab; 4000];
+    assert_eq!(pair.client.write(client_ch, s, MSG), Ok(MSG.len()));
+    // Queued but not yet sent
+    assert_eq!(pair.client.connection(client_ch).bytes_in_flight(), 0);
+    assert_eq!(
+        pair.client.connection(client_ch).pending_bytes(),
+        MSG.len() as u64
+    );
+    pair.drive();
+    assert_eq!(pair.client.connection(client_ch).pending_bytes(), 0);
+}
+
+#[test]
+fn stream_fairness() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let s1 = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    let s2 = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    const MSG: &[u8] = &[0xab; 4000];
+    assert_eq!(pair.client.write(client_ch, s1, MSG), Ok(MSG.len()));
+    assert_eq!(pair.client.write(client_ch, s2, MSG), Ok(MSG.len()));
+    pair.drive_client();
+
+    // Deliver only the first packet, which should carry data from both streams
+    pair.server.inbound.truncate(1);
+    pair.time = pair.server.inbound.front().unwrap().0;
+    pair.drive_server();
+    assert_matches!(pair.server.read_unordered(server_ch, s1), Ok(_));
+    assert_matches!(pair.server.read_unordered(server_ch, s2), Ok(_));
+}
+
+#[test]
+fn stream_fairness_many_writes() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let s1 = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    let s2 = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    // Queueing data in many small writes mustn't earn a stream more than its share
+    const CHUNK: &[u8] = &[0xab; 200];
+    for _ in 0..5 {
+        assert_eq!(pair.client.write(client_ch, s1, CHUNK), Ok(CHUNK.len()));
+    }
+    const MSG: &[u8] = &[0xcd; 1000];
+    assert_eq!(pair.client.write(client_ch, s2, MSG), Ok(MSG.len()));
+    pair.drive_client();
+
+    pair.server.inbound.truncate(1);
+    pair.time = pair.server.inbound.front().unwrap().0;
+    pair.drive_server();
+    fn received(pair: &mut Pair, ch: ConnectionHandle, stream: StreamId) -> (u64, u64) {
+        let (mut total, mut end) = (0, 0);
+        while let Ok((data, offset)) = pair.server.read_unordered(ch, stream) {
+            total += data.len() as u64;
+            end = cmp::max(end, offset + data.len() as u64);
+        }
+        (total, end)
+    }
+    // The first stream's turn ends after one quantum, wherever its writes begin and end, and
+    // resumes in offset order once the second stream has had its turn.
+    let (total1, end1) = received(&mut pair, server_ch, s1);
+    let (total2, _) = received(&mut pair, server_ch, s2);
+    assert_eq!(total2, 512);
+    assert!(total1 > 512 && total1 < 1000);
+    assert_eq!(total1, end1);
+}
+
+#[test]
+fn initial_mtu() {
+    const MTU: u16 = 1400;
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport_config: Arc::new(TransportConfig {
+                initial_mtu: MTU,
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (_, server_ch) = pair.connect();
+    let s = pair.server.open(server_ch, Directionality::Uni).unwrap();
+    const MSG: &[u8] = &[0xab; 10000];
+    assert_eq!(pair.server.write(server_ch, s, MSG), Ok(MSG.len()));
+    pair.drive_server();
+    let largest = pair.client.inbound.iter().map(|x| x.2.len()).max().unwrap();
+    assert!(largest > MIN_MTU as usize && largest <= MTU as usize);
+}
+
+#[test]
+fn ack_for_unsent_packet() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let mut ranges = range_set::RangeSet::new();
+    ranges.insert_one(1_000_000);
+    let mut buf = Vec::new();
+    frame::Ack::encode(0, &ranges, None, 64, &mut buf);
+    let now = pair.time;
+    assert_matches!(
+        pair.client.connections[client_ch].inject_payload(now, buf.into()),
+        Err(TransportError {
+            code: TransportErrorCode::PROTOCOL_VIOLATION,
+            ..
+        })
+    );
+}
+
+#[test]
+fn retransmit_buffer_limit() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Arc::new(TransportConfig {
+                max_retransmit_buffer: 512,
+                ..TransportConfig::default()
+            }),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+
+    // Each STOP_SENDING makes the client queue a RST_STREAM
+    let mut buf = Vec::new();
+    for _ in 0..20 {
+        let s = pair.client.open(client_ch, Directionality::Bi).unwrap();
+        coding::BufMutExt::write(&mut buf, frame::Type::STOP_SENDING);
+        coding::BufMutExt::write(&mut buf, s);
+        coding::BufMutExt::write::<u16>(&mut buf, 0);
+    }
+    let now = pair.time;
+    assert_matches!(
+        pair.client.connections[client_ch].inject_payload(now, buf.into()),
+        Err(TransportError {
+            code: TransportErrorCode::PROTOCOL_VIOLATION,
+            ..
+        })
+    );
+}
+
+#[test]
+fn retransmit_buffer_limit_local() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Arc::new(TransportConfig {
+                max_retransmit_buffer: 512,
+                ..TransportConfig::default()
+            }),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    pair.server.assert_accept();
+    while pair.client.poll().is_some() {}
+
+    // Frames queued by the application count too, but aren't the peer's fault
+    for _ in 0..20 {
+        let s = pair.client.open(client_ch, Directionality::Bi).unwrap();
+        pair.client.reset(client_ch, s, 0);
+    }
+    pair.drive_client();
+    assert_matches!(
+        pair.client.poll(),
+        Some((ch, Event::ConnectionLost {
+            reason: ConnectionError::TransportError(ref error)
+        })) if ch == client_ch && error.code == TransportErrorCode::INTERNAL_ERROR
+    );
+}
+
+#[test]
+fn fixed_draining_period() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let period = Duration::from_millis(1234);
+    pair.client.set_draining_period(client_ch, Some(period));
+    let now = pair.time;
+    pair.client.close(now, client_ch, 0, Bytes::new());
+    pair.drive_client();
+    assert_eq!(
+        pair.client.timers[Timer::Close as usize],
+        Some(now + period)
+    );
+}
+
+#[test]
+fn fixed_draining_period_simultaneous_close() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let period = Duration::from_millis(1234);
+    pair.client.set_draining_period(client_ch, Some(period));
+    pair.server.set_draining_period(server_ch, Some(period));
+    let start = pair.time;
+    pair.client.close(start, client_ch, 42, Bytes::new());
+    pair.server.close(start, server_ch, 42, Bytes::new());
+    pair.drive_client();
+    pair.drive_server();
+    pair.drive_client();
+    // Seeing the peer's close doesn't cut a fixed period short
+    assert_eq!(
+        pair.client.timers[Timer::Close as usize],
+        Some(start + period)
+    );
+    assert_eq!(
+        pair.server.timers[Timer::Close as usize],
+        Some(start + period)
+    );
+}
+
+/// Deliver STREAM frames for the client's first unidirectional stream directly to the server
+fn inject_stream_frames(frames: &[(u64, usize, bool)]) -> Result<(), TransportError> {
+    let mut pair = Pair::default();
+    let (_, server_ch) = pair.connect();
+    let id = StreamId::new(Side::Client, Directionality::Uni, 0);
+    let now = pair.time;
+    for &(offset, len, fin) in frames {
+        let mut buf = Vec::new();
+        frame::Stream {
+            id,
+            offset,
+            fin,
+            data: vec![0xab; len].into(),
+        }
+        .encode(true, &mut buf);
+        pair.server.connections[server_ch].inject_payload(now, buf.into())?;
+    }
+    Ok(())
+}
+
+#[test]
+fn stream_final_offset() {
+    assert_matches!(inject_stream_frames(&[(0, 5, true), (0, 5, true)]), Ok(()));
+    // Extending the stream after FIN
+    assert_matches!(
+        inject_stream_frames(&[(0, 5, true), (5, 1, false)]),
+        Err(TransportError {
+            code: TransportErrorCode::FINAL_OFFSET_ERROR,
+            ..
+        })
+    );
+    // Extending the stream after all data was received
+    assert_matches!(
+        inject_stream_frames(&[(0, 5, true), (0, 6, false)]),
+        Err(TransportError {
+            code: TransportErrorCode::FINAL_OFFSET_ERROR,
+            ..
+        })
+    );
+    // Conflicting FIN offsets
+    assert_matches!(
+        inject_stream_frames(&[(0, 3, false), (3, 2, true), (0, 3, true)]),
+        Err(TransportError {
+            code: TransportErrorCode::FINAL_OFFSET_ERROR,
+            ..
+        })
+    );
+    // FIN below data already received
+    assert_matches!(
+        inject_stream_frames(&[(0, 10, false), (0, 5, true)]),
+        Err(TransportError {
+            code: TransportErrorCode::FINAL_OFFSET_ERROR,
+            ..
+        })
+    );
+}
+
+#[test]
+fn stream_flow_control_advertised_limit() {
+    let mut pair = Pair::default();
+    let (_, server_ch) = pair.connect();
+    let id = StreamId::new(Side::Client, Directionality::Uni, 0);
+    let window = TransportConfig::default().stream_receive_window;
+    let stream_frame = |offset: u64, len: usize| {
+        let mut buf = Vec::new();
+        frame::Stream {
+            id,
+            offset,
+            fin: false,
+            data: vec![0xab; len].into(),
+        }
+        .encode(true, &mut buf);
+        Bytes::from(buf)
+    };
+
+    let now = pair.time;
+    let conn = &mut pair.server.connections[server_ch];
+    assert_matches!(conn.inject_payload(now, stream_frame(0, window as usize)), Ok(()));
+    while conn.read_unordered(id).is_ok() {}
+    // Reading freed up credit, but the peer hasn't been told yet
+    assert_matches!(
+        conn.inject_payload(now, stream_frame(window, 1)),
+        Err(TransportError {
+            code: TransportErrorCode::FLOW_CONTROL_ERROR,
+            ..
+        })
+    );
+
+    pair.drive_server();
+    let conn = &mut pair.server.connections[server_ch];
+    assert_matches!(conn.inject_payload(now, stream_frame(window, 1)), Ok(()));
+}
+
+#[test]
+fn connection_flow_control() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            stream_receive_window: 12,
+            receive_window: 20,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+    let stream_frame = |index: u64, len: usize| {
+        let mut buf = Vec::new();
+        frame::Stream {
+            id: StreamId::new(Side::Client, Directionality::Uni, index),
+            offset: 0,
+            fin: false,
+            data: vec![0xab; len].into(),
+        }
+        .encode(true, &mut buf);
+        Bytes::from(buf)
+    };
+
+    let now = pair.time;
+    let conn = &mut pair.server.connections[server_ch];
+    assert_matches!(conn.inject_payload(now, stream_frame(0, 12)), Ok(()));
+    assert_matches!(conn.inject_payload(now, stream_frame(1, 8)), Ok(()));
+    for index in 0..2 {
+        let id = StreamId::new(Side::Client, Directionality::Uni, index);
+        while conn.read_unordered(id).is_ok() {}
+    }
+    // Reading freed up credit, but the peer hasn't been told yet
+    assert_matches!(
+        conn.inject_payload(now, stream_frame(2, 1)),
+        Err(TransportError {
+            code: TransportErrorCode::FLOW_CONTROL_ERROR,
+            frame: Some(ty),
+            ..
+        }) if ty.to_string() == "STREAM"
+    );
+
+    pair.drive_server();
+    let conn = &mut pair.server.connections[server_ch];
+    assert_matches!(conn.inject_payload(now, stream_frame(2, 1)), Ok(()));
+}
+
+#[test]
+fn migration() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let old_cid = pair.server.connection(server_ch).rem_cid();
+    let available = pair.server.connection(server_ch).rem_cids_available();
+    assert!(available > 0);
+    pair.client.addr = SocketAddr::new(
+        Ipv4Addr::new(127, 0, 0, 1).into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client.ping(client_ch);
+    pair.drive();
+    assert_matches!(pair.client.poll(), None);
+    assert_eq!(pair.server.connection(server_ch).remote(), pair.client.addr);
+
+    // The server switched to a fresh CID and retired the old one, which the client replaced
+    assert_ne!(pair.server.connection(server_ch).rem_cid(), old_cid);
+    let client_conn = pair.client.connection(client_ch);
+    assert!(client_conn.loc_cids().all(|&x| x != old_cid));
+    assert_eq!(client_conn.loc_cids().count(), 8);
+    assert_eq!(
+        pair.server.connection(server_ch).rem_cids_available(),
+        available
+    );
+}
+
+#[test]
+fn path_validation_timeout() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            path_validation_timeout: 5 * 1000 * 1000,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    assert!(!pair.server.connection(server_ch).is_path_validating());
+    assert!(pair.server.connection(server_ch).is_path_validated());
+    pair.client.addr = SocketAddr::new(
+        Ipv4Addr::new(127, 0, 0, 1).into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client.ping(client_ch);
+    pair.drive_client();
+    pair.drive_server();
+    assert!(pair.server.connection(server_ch).is_path_validating());
+    assert!(!pair.server.connection(server_ch).is_path_validated());
+    assert_eq!(
+        pair.server.timers[Timer::PathValidation as usize],
+        Some(pair.time + Duration::from_secs(5))
+    );
+    pair.drive();
+    assert!(!pair.server.connection(server_ch).is_path_validating());
+    assert!(pair.server.connection(server_ch).is_path_validated());
+    assert_eq!(pair.server.timers[Timer::PathValidation as usize], None);
+}
+
+#[test]
+fn pinned_local_ip() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    let ip = IpAddr::from(Ipv6Addr::LOCALHOST);
+    pair.client.set_local_ip(client_ch, Some(ip));
+    pair.client.drive(&pair.log, pair.time, pair.server.addr);
+    assert!(!pair.client.outbound.is_empty());
+    assert!(pair.client.outbound.iter().all(|x| x.source == Some(ip)));
+    pair.drive();
+    pair.server.assert_accept();
+    assert_eq!(pair.client.connection(client_ch).local_ip(), Some(ip));
+}
+
+#[test]
+fn probe_path() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let server_addr = pair.server.addr;
+
+    // Nothing answers at an unused address
+    let unused = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let now = pair.time;
+    pair.client.probe_path(now, client_ch, unused);
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((ch, Event::PathProbed { remote, validated: false }))
+            if ch == client_ch && remote == unused
+    );
+
+    // The server can also be reached at a new address
+    let new_addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.server.addr = new_addr;
+    let now = pair.time;
+    pair.client.probe_path(now, client_ch, new_addr);
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((ch, Event::PathProbed { remote, validated: true }))
+            if ch == client_ch && remote == new_addr
+    );
+    assert_eq!(pair.client.connection(client_ch).remote(), server_addr);
+}
+
+#[test]
+fn probe_path_unavailable() {
+    let mut pair = Pair::default();
+    let client_ch = pair
+        .client
+        .connect(
+            pair.server.addr,
+            Default::default(),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    let server_addr = pair.server.addr;
+
+    // Before the handshake completes
+    let now = pair.time;
+    pair.client.probe_path(now, client_ch, server_addr);
+    assert_matches!(
+        pair.client.poll(),
+        Some((ch, Event::PathProbed { remote, validated: false }))
+            if ch == client_ch && remote == server_addr
+    );
+
+    pair.drive();
+    pair.server.assert_accept();
+    while pair.client.poll().is_some() {}
+
+    // After the connection is closed
+    let now = pair.time;
+    pair.client.close(now, client_ch, 0, Bytes::new());
+    pair.client.probe_path(now, client_ch, server_addr);
+    assert_matches!(
+        pair.client.poll(),
+        Some((ch, Event::PathProbed { remote, validated: false }))
+            if ch == client_ch && remote == server_addr
+    );
+    assert_eq!(pair.client.timers[Timer::PathProbe as usize], None);
+}
+
+#[test]
+fn local_cid_count() {
+    let endpoint_config = Arc::new(EndpointConfig {
+        local_cid_count: 3,
+        ..Default::default()
+    });
+    let mut pair = Pair::new(endpoint_config, server_config());
+    let (client_ch, server_ch) = pair.connect();
+    assert_eq!(pair.server.connection(server_ch).loc_cids().count(), 3);
+    assert_eq!(pair.client.connection(client_ch).rem_cids_available(), 2);
+
+    // Retiring a CID prompts a replacement, never more
+    pair.client.connections[client_ch].switch_rem_cid();
+    pair.client.ping(client_ch);
+    pair.drive();
+    assert_eq!(pair.server.connection(server_ch).loc_cids().count(), 3);
+    assert_eq!(pair.client.connection(client_ch).rem_cids_available(), 2);
+}
+
+#[test]
+fn cid_generator() {
+    let endpoint_config = Arc::new(EndpointConfig {
+        cid_generator: Some(Arc::new(|cid: &mut [u8]| cid[0] = 0xab)),
+        ..Default::default()
+    });
+    let mut pair = Pair::new(endpoint_config.clone(), server_config());
+    let (client_ch, server_ch) = pair.connect();
+    let client_cids = pair.client.connection(client_ch).loc_cids();
+    let server_cids = pair.server.connection(server_ch).loc_cids();
+    assert!(client_cids.chain(server_cids).all(|cid| cid[0] == 0xab));
+
+    // Stateless reset tokens are derived from the customized CIDs
+    pair.server.endpoint = Endpoint::new(
+        pair.log.new(o!("side" => "Server")),
+        endpoint_config,
+        Some(Arc::new(server_config())),
+    )
+    .unwrap();
+    pair.client
+        .close(pair.time, client_ch, 42, (&[0xab; 128][..]).into());
+    pair.drive();
+    assert_matches!(pair.client.poll(), Some((conn, Event::ConnectionLost { reason: ConnectionError::Reset })) if conn == client_ch);
+}
+
+#[test]
+fn migration_kind() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    // Same CID from a new address
+    pair.client.addr = SocketAddr::new(
+        Ipv4Addr::new(127, 0, 0, 1).into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client.ping(client_ch);
+    pair.drive();
+    assert_matches!(
+        pair.server.poll(),
+        Some((ch, Event::Migrated { remote, kind: MigrationKind::Rebinding }))
+        if ch == server_ch && remote == pair.client.addr
+    );
+    assert_matches!(pair.server.poll(), None);
+
+    // Fresh CID from a new address
+    pair.client.addr = SocketAddr::new(
+        Ipv4Addr::new(127, 0, 0, 1).into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client.connections[client_ch].switch_rem_cid();
+    pair.client.ping(client_ch);
+    pair.drive();
+    assert_matches!(
+        pair.server.poll(),
+        Some((ch, Event::Migrated { remote, kind: MigrationKind::Active }))
+        if ch == server_ch && remote == pair.client.addr
+    );
+}
+
+fn test_flow_control(config: TransportConfig, window_size: usize) {
     let mut pair = Pair::new(
         Default::default(),
         ServerConfig {
@@ -1220,16 +3006,91 @@ fn stream_flow_control() {
 }
 
 #[test]
-fn conn_flow_control() {
+fn conn_flow_control() {
+    test_flow_control(
+        TransportConfig {
+            receive_window: 2000,
+            ..TransportConfig::default()
+        },
+        2000,
+    );
+}
+
+#[test]
+fn unread_data_flow_control() {
     test_flow_control(
         TransportConfig {
-            receive_window: 2000,
+            max_unread_data: 2000,
             ..TransportConfig::default()
         },
         2000,
     );
 }
 
+#[test]
+fn manual_credit() {
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport_config: Arc::new(TransportConfig {
+                stream_receive_window: 2000,
+                manual_credit: true,
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (client_conn, server_conn) = pair.connect();
+    let msg = [0xAB; 2010];
+    let mut buf = [0; 4096];
+
+    let s = pair.client.open(client_conn, Directionality::Uni).unwrap();
+    assert_eq!(pair.client.write(client_conn, s, &msg), Ok(2000));
+    pair.drive();
+    assert_eq!(pair.server.read(server_conn, s, &mut buf), Ok(2000));
+    pair.drive();
+    // Reading alone doesn't permit the peer to send more
+    assert_eq!(
+        pair.client.write(client_conn, s, &msg[2000..]),
+        Err(WriteError::Blocked)
+    );
+
+    pair.server.release_credit(server_conn, s, 1000);
+    pair.drive();
+    assert_eq!(pair.client.write(client_conn, s, &msg[..1500]), Ok(1000));
+}
+
+#[test]
+fn grant_credit_now() {
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport_config: Arc::new(TransportConfig {
+                stream_receive_window: 2000,
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (client_conn, server_conn) = pair.connect();
+    let msg = [0xAB; 2010];
+    let mut buf = [0; 4096];
+
+    let s = pair.client.open(client_conn, Directionality::Uni).unwrap();
+    assert_eq!(pair.client.write(client_conn, s, &msg), Ok(2000));
+    pair.drive();
+    assert_eq!(pair.server.read(server_conn, s, &mut buf), Ok(2000));
+    // Lose the packet carrying the new credit
+    pair.server.drive(&pair.log, pair.time, pair.client.addr);
+    pair.server.outbound.clear();
+
+    pair.server.grant_credit_now(server_conn);
+    pair.drive_server();
+    pair.drive_client();
+    // Credit arrived without waiting for loss detection
+    assert_eq!(pair.client.write(client_conn, s, &msg[2000..]), Ok(10));
+}
+
 #[test]
 fn stop_opens_bidi() {
     let mut pair = Pair::default();
@@ -1239,7 +3100,7 @@ fn stop_opens_bidi() {
     pair.client.stop_sending(server_conn, s, ERROR);
     pair.drive();
 
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_conn);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_conn);
     assert_matches!(pair.server.accept_stream(server_conn), Some(stream) if stream == s);
     assert_matches!(
         pair.server.read_unordered(server_conn, s),
@@ -1259,12 +3120,114 @@ fn implicit_open() {
     let s2 = pair.client.open(client_conn, Directionality::Uni).unwrap();
     pair.client.write(client_conn, s2, b"hello").unwrap();
     pair.drive();
-    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened)) if conn == server_conn);
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_conn);
     assert_eq!(pair.server.accept_stream(server_conn), Some(s1));
     assert_eq!(pair.server.accept_stream(server_conn), Some(s2));
     assert_eq!(pair.server.accept_stream(server_conn), None);
 }
 
+#[test]
+fn implicitly_opened_streams() {
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let streams = (0..3)
+        .map(|_| pair.client.open(client_ch, Directionality::Uni).unwrap())
+        .collect::<Vec<_>>();
+    pair.client.write(client_ch, streams[2], b"hello").unwrap();
+    pair.drive();
+
+    for &s in &streams {
+        assert_matches!(
+            pair.server.poll(),
+            Some((conn, Event::StreamOpened { stream })) if conn == server_ch && stream == s
+        );
+    }
+    assert_matches!(pair.server.poll(), None);
+}
+
+#[test]
+fn export_import_state() {
+    const MSG: &[u8] = b"hello";
+    let endpoint_config = Arc::new(EndpointConfig::default());
+    let server_config = server_config();
+    let tls_config = server_config.tls_config.clone();
+    let mut pair = Pair::new(endpoint_config.clone(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+    pair.drive();
+
+    let mut buf = Vec::new();
+    pair.server.export_state(server_ch).unwrap().encode(&mut buf);
+    let state = ConnectionState::decode(&mut io::Cursor::new(&buf)).unwrap();
+
+    // Hand the connection off to a fresh endpoint, as a restarted server process would
+    pair.restart_server(
+        endpoint_config,
+        ServerConfig {
+            tls_config,
+            ..Default::default()
+        },
+    );
+    let server_ch = pair
+        .server
+        .import_state(pair.time, state, Default::default())
+        .unwrap();
+    pair.server.conn = Some(server_ch);
+
+    let s = pair.client.open(client_ch, Directionality::Uni).unwrap();
+    pair.client.write(client_ch, s, MSG).unwrap();
+    pair.client.finish(client_ch, s);
+    pair.drive();
+    assert_matches!(
+        pair.server.poll(),
+        Some((conn, Event::StreamOpened { stream })) if conn == server_ch && stream == s
+    );
+    assert_matches!(pair.server.read_unordered(server_ch, s), Ok((ref data, 0)) if data == MSG);
+    assert_eq!(pair.client.connection(client_ch).lost_packets(), 0);
+}
+
+#[test]
+fn poll_events() {
+    let mut pair = Pair::default();
+    let (client_conn, server_conn) = pair.connect();
+    let s = pair.client.open(client_conn, Directionality::Uni).unwrap();
+    pair.client.write(client_conn, s, b"hello").unwrap();
+    pair.drive();
+    pair.client.close(pair.time, client_conn, 42, Bytes::new());
+    pair.drive();
+
+    let mut events = Vec::new();
+    pair.server.poll_events(&mut events);
+    assert_eq!(events.len(), 2);
+    assert_matches!(events[0], (conn, Event::StreamOpened { .. }) if conn == server_conn);
+    assert_matches!(events[1], (conn, Event::ConnectionLost { .. }) if conn == server_conn);
+    assert_matches!(pair.server.poll(), None);
+}
+
+#[cfg(feature = "framing")]
+#[test]
+fn framed_messages() {
+    use crate::framing::{MessageReader, MessageWriter};
+
+    let mut pair = Pair::default();
+    let (client_conn, server_conn) = pair.connect();
+    let mut writer = MessageWriter::open(&mut pair.client, client_conn, 0x42).unwrap();
+    writer.send(b"hello");
+    writer.send(&[0xab; 2000]);
+    assert_eq!(writer.flush(&mut pair.client, client_conn), Ok(true));
+    pair.drive();
+
+    assert_matches!(pair.server.poll(), Some((conn, Event::StreamOpened { .. })) if conn == server_conn);
+    let stream = pair.server.accept_stream(server_conn).unwrap();
+    assert_eq!(stream, writer.id());
+    let mut reader = MessageReader::new(stream);
+    let message = reader.poll(&mut pair.server, server_conn).unwrap();
+    assert_eq!(reader.stream_type(), Some(0x42));
+    assert_eq!(message.as_ref().map(|x| &x[..]), Some(&b"hello"[..]));
+    let message = reader.poll(&mut pair.server, server_conn).unwrap();
+    assert_eq!(message.map(|x| x.len()), Some(2000));
+    assert_eq!(reader.poll(&mut pair.server, server_conn), Ok(None));
+}
+
 #[test]
 fn zero_length_cid() {
     let mut pair = Pair::new(
@@ -1275,6 +3238,9 @@ fn zero_length_cid() {
         server_config(),
     );
     let (client_ch, server_ch) = pair.connect();
+    assert_eq!(pair.client.connection(client_ch).loc_cid_len(), 0);
+    assert_eq!(pair.client.connection(client_ch).rem_cid_len(), 0);
+    assert_eq!(pair.server.connection(server_ch).rem_cid_len(), 0);
     // Ensure we can reconnect after a previous connection is cleaned up
     info!(pair.log, "closing");
     pair.client.close(pair.time, client_ch, 42, Bytes::new());
@@ -1309,6 +3275,167 @@ fn keep_alive() {
     }
 }
 
+#[test]
+fn opportunistic_keep_alive() {
+    const IDLE_TIMEOUT: u64 = 10;
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            keep_alive_interval: IDLE_TIMEOUT as u32 / 2,
+            opportunistic_keep_alive: true,
+            idle_timeout: IDLE_TIMEOUT,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    // With nothing else to send, a PING must still go out
+    let end = pair.time + Duration::new(20 * IDLE_TIMEOUT, 0);
+    while pair.time < end {
+        if !pair.step() {
+            if let Some(time) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = time;
+            }
+        }
+        assert!(!pair.client.connection(client_ch).is_closed());
+        assert!(!pair.server.connection(server_ch).is_closed());
+    }
+}
+
+#[test]
+fn disable_keep_alive() {
+    const IDLE_TIMEOUT: u64 = 10;
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            keep_alive_interval: IDLE_TIMEOUT as u32 / 2,
+            idle_timeout: IDLE_TIMEOUT,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+    let now = pair.time;
+    pair.server.set_keep_alive(now, server_ch, None);
+    let end = pair.time + Duration::new(2 * IDLE_TIMEOUT, 0);
+    while pair.time < end && !pair.server.connection(server_ch).is_closed() {
+        if !pair.step() {
+            if let Some(time) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = time;
+            }
+        }
+    }
+    assert_matches!(
+        pair.server.poll(),
+        Some((
+            _,
+            Event::ConnectionLost {
+                reason: ConnectionError::TimedOut,
+            },
+        ))
+    );
+}
+
+#[test]
+fn crypto_retransmit_limit() {
+    let mut pair = Pair::default();
+    let start = pair.time;
+    // Nothing is listening here, so the handshake is never answered
+    let unreachable = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let client_ch = pair
+        .client
+        .connect(
+            unreachable,
+            Arc::new(TransportConfig {
+                max_crypto_retransmits: 3,
+                ..TransportConfig::default()
+            }),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((
+            conn,
+            Event::ConnectionLost {
+                reason: ConnectionError::TimedOut,
+            },
+        )) if conn == client_ch
+    );
+    // The original Initial plus three retransmissions
+    assert_eq!(pair.client.connection(client_ch).packets_sent(), 4);
+    assert!(pair.time - start < Duration::new(TransportConfig::default().idle_timeout, 0));
+}
+
+#[test]
+fn crypto_retransmit_byte_limit() {
+    let mut pair = Pair::default();
+    let unreachable = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let client_ch = pair
+        .client
+        .connect(
+            unreachable,
+            Arc::new(TransportConfig {
+                max_crypto_retransmit_bytes: 1,
+                ..TransportConfig::default()
+            }),
+            client_config(),
+            "localhost",
+        )
+        .unwrap();
+    assert_eq!(pair.client.connection(client_ch).crypto_packets_in_flight(), 0);
+    pair.drive();
+    assert_matches!(
+        pair.client.poll(),
+        Some((
+            conn,
+            Event::ConnectionLost {
+                reason: ConnectionError::TimedOut,
+            },
+        )) if conn == client_ch
+    );
+    // Requeueing the lost ClientHello alone exhausts the budget
+    let client = pair.client.connection(client_ch);
+    assert_eq!(client.packets_sent(), 1);
+    assert!(client.crypto_bytes_retransmitted() > 1);
+}
+
+#[test]
+fn connection_error_retryable() {
+    assert!(ConnectionError::TimedOut.is_retryable());
+    assert!(ConnectionError::Reset.is_retryable());
+    assert!(!ConnectionError::VersionMismatch.is_retryable());
+    assert!(ConnectionError::from(TransportError::SERVER_BUSY("")).is_retryable());
+    assert!(!ConnectionError::from(TransportError::PROTOCOL_VIOLATION("")).is_retryable());
+    let reason = ApplicationClose {
+        error_code: 0,
+        reason: Bytes::new(),
+    };
+    assert!(!ConnectionError::ApplicationClosed { reason }.is_retryable());
+}
+
+#[test]
+fn next_timeout() {
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let expected = pair.client.next_wakeup();
+    assert!(expected.is_some());
+    assert_eq!(pair.client.connection(client_ch).next_timeout(), expected);
+
+    pair.client.ping(client_ch);
+    pair.drive_client();
+    let expected = pair.client.next_wakeup();
+    assert_eq!(pair.client.connection(client_ch).next_timeout(), expected);
+}
+
 fn min_opt<T: Ord>(x: Option<T>, y: Option<T>) -> Option<T> {
     match (x, y) {
         (Some(x), Some(y)) => Some(cmp::min(x, y)),
@@ -1317,3 +3444,117 @@ fn min_opt<T: Ord>(x: Option<T>, y: Option<T>) -> Option<T> {
         _ => None,
     }
 }
+
+#[test]
+fn lossy_transfer() {
+    let log = logger();
+    let mut net = simulation::Network::new(42);
+    net.latency = Duration::from_millis(10);
+    net.jitter = Duration::from_millis(5);
+    net.loss = 0.1;
+    let server = net.add(
+        Endpoint::new(
+            log.new(o!("side" => "Server")),
+            Default::default(),
+            Some(Arc::new(server_config())),
+        )
+        .unwrap(),
+        "[::1]:4433".parse().unwrap(),
+    );
+    let client = net.add(
+        Endpoint::new(log.new(o!("side" => "Client")), Default::default(), None).unwrap(),
+        "[::1]:44433".parse().unwrap(),
+    );
+
+    let server_addr = net.addr(server);
+    let client_ch = net
+        .endpoint_mut(client)
+        .connect(server_addr, Default::default(), client_config(), "localhost")
+        .unwrap();
+    net.run();
+    let server_ch = match net.endpoint_mut(server).poll() {
+        Some((ch, Event::Handshaking)) => ch,
+        x => panic!("unexpected event {:?}", x),
+    };
+    net.endpoint_mut(server).accept();
+    assert_matches!(net.endpoint_mut(client).poll(), Some((ch, Event::Connected)) if ch == client_ch);
+
+    let data = (0..8192).map(|x| x as u8).collect::<Vec<_>>();
+    let s = net
+        .endpoint_mut(client)
+        .open(client_ch, Directionality::Uni)
+        .unwrap();
+    assert_eq!(
+        net.endpoint_mut(client).write(client_ch, s, &data),
+        Ok(data.len())
+    );
+    net.endpoint_mut(client).finish(client_ch, s);
+    net.run();
+
+    assert_eq!(net.endpoint_mut(server).accept_stream(server_ch), Some(s));
+    let mut received = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        match net.endpoint_mut(server).read(server_ch, s, &mut buf) {
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(ReadError::Finished) => break,
+            Err(e) => panic!("unexpected read error: {:?}", e),
+        }
+    }
+    assert_eq!(received, data);
+}
+
+#[test]
+fn send_buffer_low() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            send_low_water_mark: 1000,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+    let s = pair.server.open(server_ch, Directionality::Uni).unwrap();
+
+    // Never reached the mark
+    pair.server.write(server_ch, s, &[0xAB; 500]).unwrap();
+    pair.drive();
+    assert_matches!(pair.server.poll(), None);
+
+    pair.server.write(server_ch, s, &[0xAB; 4000]).unwrap();
+    pair.drive();
+    assert_matches!(pair.server.poll(), Some((ch, Event::SendBufferLow)) if ch == server_ch);
+    assert_matches!(pair.server.poll(), None);
+}
+
+#[test]
+fn idle_event() {
+    let server = ServerConfig {
+        transport_config: Arc::new(TransportConfig {
+            idle_events: true,
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+    let mut idle = false;
+    while let Some((_, event)) = pair.server.poll() {
+        if let Event::Idle = event {
+            idle = true;
+        }
+    }
+    assert!(idle);
+
+    let s = pair.server.open(server_ch, Directionality::Uni).unwrap();
+    pair.server.write(server_ch, s, &[0xAB; 500]).unwrap();
+    pair.drive();
+    assert_matches!(pair.server.poll(), Some((ch, Event::Idle)) if ch == server_ch);
+    assert_matches!(pair.server.poll(), None);
+
+    // Not reported again without new activity
+    pair.client.ping(pair.client.conn.unwrap());
+    pair.drive();
+    assert_matches!(pair.server.poll(), None);
+}
@@ -5,12 +5,18 @@ use slog;
 
 use crate::coding::{self, BufExt, BufMutExt};
 use crate::frame;
+use crate::packet::SpaceId;
 use rustls::internal::msgs::{codec::Codec, enums::AlertDescription};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Error {
     pub code: Code,
+    /// Type of the frame that caused the error, if any
     pub frame: Option<frame::Type>,
+    /// Packet number space of the received packet that caused the error, if any
+    ///
+    /// Not conveyed to the peer.
+    pub space: Option<SpaceId>,
     pub reason: String,
 }
 
@@ -22,6 +28,7 @@ impl Error {
         Self {
             code,
             frame,
+            space: None,
             reason: reason.into(),
         }
     }
@@ -29,6 +36,12 @@ impl Error {
     pub fn crypto(code: u8, reason: String) -> Self {
         Self::new(Code::crypto(code), None, reason)
     }
+
+    /// Attribute the error to a frame of type `ty`, unless it already names one
+    pub(crate) fn in_frame(mut self, ty: Option<frame::Type>) -> Self {
+        self.frame = self.frame.or(ty);
+        self
+    }
 }
 
 impl fmt::Display for Error {
@@ -37,6 +50,9 @@ impl fmt::Display for Error {
         if let Some(frame) = self.frame {
             write!(f, " in {}", frame)?;
         }
+        if let Some(space) = self.space {
+            write!(f, " ({:?} packet)", space)?;
+        }
         if !self.reason.is_empty() {
             write!(f, ": {}", self.reason)?;
         }
@@ -49,6 +65,7 @@ impl From<Code> for Error {
         Self {
             code: x,
             frame: None,
+            space: None,
             reason: "".to_string(),
         }
     }
@@ -72,6 +89,18 @@ impl Code {
     pub fn crypto(code: u8) -> Self {
         Code(0x100 | code as u16)
     }
+
+    /// The TLS alert this code carries, if it reports a failed cryptographic handshake
+    ///
+    /// Distinguishes, for example, an untrusted certificate (`bad_certificate`, 42) from a lack of
+    /// mutually supported protocols (`no_application_protocol`, 120).
+    pub fn tls_alert(self) -> Option<u8> {
+        if self.0 >= 0x100 && self.0 < 0x200 {
+            Some(self.0 as u8)
+        } else {
+            None
+        }
+    }
 }
 
 impl coding::Codec for Code {
@@ -98,6 +127,7 @@ macro_rules! errors {
                 Self {
                     code: Code::$name,
                     frame: None,
+                    space: None,
                     reason: reason.into(),
                 }
             }
@@ -110,6 +140,9 @@ macro_rules! errors {
 
         impl fmt::Debug for Code {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if *self == Code::APPLICATION_ERROR {
+                    return f.write_str("APPLICATION_ERROR or INVALID_MIGRATION");
+                }
                 match self.0 {
                     $($val => f.write_str(stringify!($name)),)*
                     x if x >= 0x100 && x < 0x200 => match AlertDescription::read_bytes(&[self.0 as u8]) {
@@ -123,9 +156,18 @@ macro_rules! errors {
 
         impl fmt::Display for Code {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if *self == Code::APPLICATION_ERROR {
+                    return f.write_str(APPLICATION_ERROR_DESC);
+                }
                 let x = match self.0 {
                     $($val => $desc,)*
-                    _ if self.0 >= 0x100 && self.0 < 0x200 => "the cryptographic handshake failed", // FIXME: Describe specific alert
+                    _ if self.0 >= 0x100 && self.0 < 0x200 => {
+                        f.write_str("the cryptographic handshake failed")?;
+                        return match AlertDescription::read_bytes(&[self.0 as u8]) {
+                            Some(desc) => write!(f, " ({:?})", desc),
+                            None => write!(f, " (alert {})", self.0 as u8),
+                        };
+                    }
                     _ => "unknown error",
                 };
                 f.write_str(x)
@@ -159,3 +201,18 @@ errors! {
     PROTOCOL_VIOLATION(0xA) "detected an error with protocol compliance that was not covered by more specific error codes";
     INVALID_MIGRATION(0xC) "received a PATH_RESPONSE frame that did not correspond to any PATH_CHALLENGE frame that it previously sent";
 }
+
+impl Code {
+    /// The application or application protocol caused the connection to be closed during the
+    /// handshake
+    ///
+    /// Sent in place of an application close before 1-RTT keys are available. Shares its value
+    /// with `INVALID_MIGRATION`, which later drafts retired: a draft-18 peer will interpret it as
+    /// such. Both meanings are named when the code is formatted, since they can't be told apart.
+    pub const APPLICATION_ERROR: Self = Code(0xC);
+}
+
+/// `Display` text for the code shared by `APPLICATION_ERROR` and `INVALID_MIGRATION`
+const APPLICATION_ERROR_DESC: &str = "the application or application protocol caused the \
+connection to be closed during the handshake, or received a PATH_RESPONSE frame that did not \
+correspond to any PATH_CHALLENGE frame that it previously sent";
@@ -6,8 +6,8 @@ use err_derive::Error;
 use crate::coding::{BufExt, BufMutExt, UnexpectedEnd};
 use crate::packet::ConnectionId;
 use crate::{
-    varint, Side, TransportConfig, TransportError, MAX_CID_SIZE, MIN_CID_SIZE, RESET_TOKEN_SIZE,
-    VERSION,
+    varint, Side, TransportConfig, TransportError, MAX_CID_SIZE, MAX_STREAM_COUNT, MIN_CID_SIZE,
+    RESET_TOKEN_SIZE, VERSION,
 };
 
 // Apply a given macro to a list of all the transport parameters having integer types, along with
@@ -74,12 +74,16 @@ impl TransportParameters {
         TransportParameters {
             initial_max_streams_bidi: config.stream_window_bidi,
             initial_max_streams_uni: config.stream_window_uni,
-            initial_max_data: config.receive_window,
+            initial_max_data: match config.max_unread_data {
+                0 => config.receive_window,
+                x => config.receive_window.min(x),
+            },
             initial_max_stream_data_bidi_local: config.stream_receive_window,
             initial_max_stream_data_bidi_remote: config.stream_receive_window,
             initial_max_stream_data_uni: config.stream_receive_window,
             idle_timeout: config.idle_timeout,
-            max_ack_delay: 0, // Unimplemented
+            max_ack_delay: config.max_ack_delay,
+            ack_delay_exponent: config.ack_delay_exponent as u64,
             ..Self::default()
         }
     }
@@ -228,6 +232,30 @@ impl TransportParameters {
         w.put_slice(&buf);
     }
 
+    /// Encode the integer parameters and migration flag, without versions or server-only fields
+    pub(crate) fn write_raw<W: BufMut>(&self, w: &mut W) {
+        macro_rules! write_raw {
+            {$($name:ident ($code:expr) = $default:expr,)*} => {
+                $(w.write_var(self.$name);)*
+            }
+        }
+        apply_params!(write_raw);
+        w.write::<u8>(self.disable_migration as u8);
+    }
+
+    /// Decode parameters written by `write_raw`
+    pub(crate) fn read_raw<R: Buf>(r: &mut R) -> Result<Self, UnexpectedEnd> {
+        let mut params = Self::default();
+        macro_rules! read_raw {
+            {$($name:ident ($code:expr) = $default:expr,)*} => {
+                $(params.$name = r.get_var()?;)*
+            }
+        }
+        apply_params!(read_raw);
+        params.disable_migration = r.get::<u8>()? != 0;
+        Ok(params)
+    }
+
     pub fn read<R: Buf>(side: Side, r: &mut R) -> Result<Self, Error> {
         if side.is_server() {
             if r.remaining() < 26 {
@@ -342,6 +370,8 @@ impl TransportParameters {
 
         if params.ack_delay_exponent > 20
             || params.max_ack_delay >= 1 << 14
+            || params.initial_max_streams_bidi > MAX_STREAM_COUNT
+            || params.initial_max_streams_uni > MAX_STREAM_COUNT
             || (side.is_server()
                 && (params.stateless_reset_token.is_some() || params.preferred_address.is_some()))
         {
@@ -379,4 +409,31 @@ mod test {
             params
         );
     }
+
+    #[test]
+    fn stream_limit_bounds() {
+        let mut buf = Vec::new();
+        let params = TransportParameters {
+            initial_max_streams_bidi: MAX_STREAM_COUNT,
+            idle_timeout: 10,
+            ..TransportParameters::default()
+        };
+        params.write(Side::Server, &mut buf);
+        assert_eq!(
+            TransportParameters::read(Side::Client, &mut buf.into_buf()).unwrap(),
+            params
+        );
+
+        let mut buf = Vec::new();
+        let params = TransportParameters {
+            initial_max_streams_uni: MAX_STREAM_COUNT + 1,
+            idle_timeout: 10,
+            ..TransportParameters::default()
+        };
+        params.write(Side::Server, &mut buf);
+        assert_eq!(
+            TransportParameters::read(Side::Client, &mut buf.into_buf()),
+            Err(Error::IllegalValue)
+        );
+    }
 }
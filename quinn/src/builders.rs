@@ -1,22 +1,27 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::VecDeque;
 use std::io;
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::rc::Rc;
 use std::str;
 use std::sync::Arc;
+use std::time::Duration;
 
 use err_derive::Error;
 use fnv::FnvHashMap;
 use futures::stream::futures_unordered::FuturesUnordered;
 use quinn_proto as quinn;
-use rustls::{KeyLogFile, ProtocolVersion, TLSError};
+use rustls::{
+    KeyLogFile, NoServerSessionStorage, ProtocolVersion, ServerSessionMemoryCache,
+    SupportedCipherSuite, TLSError, Ticketer,
+};
 use slog::Logger;
 
-use quinn_proto::{EndpointConfig, ServerConfig, TransportConfig};
+use quinn_proto::{ConnectionId, EndpointConfig, IncomingAction, ServerConfig, TransportConfig};
 
-use crate::tls::{Certificate, CertificateChain, PrivateKey};
+use crate::tls::{Certificate, CertificateChain, PrivateKey, TicketLifetime};
 use crate::udp::UdpSocket;
 use crate::{Driver, Endpoint, EndpointInner, Incoming};
 
@@ -40,6 +45,10 @@ impl<'a> EndpointBuilder<'a> {
     }
 
     /// Build an endpoint bound to `addr`.
+    ///
+    /// To serve both IPv4 and IPv6 peers from one socket, bind to `[::]` on a platform where
+    /// `IPV6_V6ONLY` is off by default, or pass a socket configured accordingly to `from_socket`.
+    /// IPv4 peers are then reported by their plain IPv4 addresses.
     pub fn bind<T: ToSocketAddrs>(
         self,
         addr: T,
@@ -58,7 +67,6 @@ impl<'a> EndpointBuilder<'a> {
         } else {
             Cow::Owned(tokio_reactor::Handle::default())
         };
-        let addr = socket.local_addr().map_err(EndpointError::Socket)?;
         let socket = UdpSocket::from_std(socket, &reactor).map_err(EndpointError::Socket)?;
         let (send, recv) = futures::sync::mpsc::channel(4);
         let rc = Rc::new(RefCell::new(EndpointInner {
@@ -75,7 +83,7 @@ impl<'a> EndpointBuilder<'a> {
             buffered_incoming: VecDeque::new(),
             incoming: send,
             driver: None,
-            ipv6: addr.is_ipv6(),
+            closed: false,
         }));
         Ok((
             Endpoint {
@@ -200,11 +208,88 @@ impl ServerConfigBuilder {
         self
     }
 
+    /// Set the cipher suites to accept, in order of descending preference.
+    ///
+    /// Only TLS 1.3 suites, such as those in `rustls::ciphersuite`, can be negotiated by QUIC.
+    pub fn cipher_suites(&mut self, suites: &[&'static SupportedCipherSuite]) -> &mut Self {
+        Arc::make_mut(&mut self.config.tls_config).ciphersuites = suites.to_vec();
+        self
+    }
+
     /// Whether to require clients to prove they can receive packets before accepting a connection
     pub fn use_stateless_retry(&mut self, enabled: bool) -> &mut Self {
         self.config.use_stateless_retry = enabled;
         self
     }
+
+    /// Refuse new connections while the endpoint already holds `max` of them
+    ///
+    /// 0 for no limit, the default.
+    pub fn max_connections(&mut self, max: u32) -> &mut Self {
+        self.config.max_connections = max;
+        self
+    }
+
+    /// Whether to accept 0-RTT data from resuming clients
+    ///
+    /// Disabling this rejects all early data, giving up its latency benefit to rule out replay
+    /// attacks. Enabled by default.
+    pub fn accept_0rtt(&mut self, enabled: bool) -> &mut Self {
+        self.config.accept_0rtt = enabled;
+        self
+    }
+
+    /// Set the number of TLS sessions remembered so that clients can resume them
+    ///
+    /// Each remembered session costs a few hundred bytes. Once the cache is full, new sessions
+    /// displace old ones, so clients that return less often than the cache turns over can't resume
+    /// or use 0-RTT. 0 disables the cache. Unused after `ticket_lifetime` is set. Defaults to 256.
+    pub fn session_cache_size(&mut self, size: usize) -> &mut Self {
+        let tls = Arc::make_mut(&mut self.config.tls_config);
+        tls.session_storage = if size == 0 {
+            Arc::new(NoServerSessionStorage {})
+        } else {
+            ServerSessionMemoryCache::new(size)
+        };
+        self
+    }
+
+    /// Issue self-contained session tickets that clients may use to resume for up to `lifetime`
+    ///
+    /// Rather than being remembered by the server, session state is encrypted into the ticket
+    /// under a key that is rotated every few hours, so resumption needs no memory per client but
+    /// never outlives that key. Longer lifetimes let clients resume, and send 0-RTT data, after
+    /// longer absences, but lengthen the period during which a stolen ticket or ticket key
+    /// compromises the secrecy of resumed sessions and 0-RTT data can be replayed. Capped at seven
+    /// days. By default, no such tickets are issued.
+    pub fn ticket_lifetime(&mut self, lifetime: Duration) -> &mut Self {
+        const MAX_LIFETIME: u64 = 7 * 24 * 60 * 60;
+        Arc::make_mut(&mut self.config.tls_config).ticketer = Arc::new(TicketLifetime {
+            inner: Ticketer::new(),
+            lifetime: cmp::min(lifetime.as_secs(), MAX_LIFETIME) as u32,
+        });
+        self
+    }
+
+    /// Whether to issue session tickets, without which clients can't resume sessions or use 0-RTT
+    ///
+    /// Enabled by default.
+    pub fn session_tickets(&mut self, enabled: bool) -> &mut Self {
+        self.config.session_tickets = enabled;
+        self
+    }
+
+    /// Screen incoming connection attempts by the client's address and initial connection ID
+    ///
+    /// Called before any state is allocated for a new connection, so it can be used to cheaply
+    /// enforce allow/deny lists or rate limits.
+    pub fn incoming_filter<F>(&mut self, filter: F) -> &mut Self
+    where
+        F: Fn(&SocketAddr, &ConnectionId) -> IncomingAction + Send + Sync + 'static,
+    {
+        self.config.incoming_filter = Some(Arc::new(filter));
+        self
+    }
 }
 
 impl Default for ServerConfigBuilder {
@@ -277,6 +362,15 @@ impl ClientConfigBuilder {
         self
     }
 
+    /// Set the cipher suites to offer, in order of descending preference.
+    ///
+    /// Only TLS 1.3 suites, such as those in `rustls::ciphersuite`, can be negotiated by QUIC. For
+    /// example, devices without AES hardware may prefer to offer only ChaCha20-Poly1305.
+    pub fn cipher_suites(&mut self, suites: &[&'static SupportedCipherSuite]) -> &mut Self {
+        self.crypto.ciphersuites = suites.to_vec();
+        self
+    }
+
     /// Begin connecting from `endpoint` to `addr`.
     pub fn build(self) -> ClientConfig {
         ClientConfig {
@@ -59,31 +59,34 @@ mod platform;
 pub mod tls;
 mod udp;
 
+use std::any::Any;
 use std::cell::RefCell;
 use std::collections::{hash_map, VecDeque};
-use std::net::{SocketAddr, SocketAddrV6};
-use std::rc::Rc;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::{Rc, Weak};
 use std::str;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{io, mem};
 
 use bytes::Bytes;
 use err_derive::Error;
 use fnv::FnvHashMap;
+use futures::future::Either;
 use futures::stream::FuturesUnordered;
 use futures::task::{self, Task};
 use futures::unsync::oneshot;
 use futures::Stream as FuturesStream;
 use futures::{Async, Future, Poll, Sink};
-use quinn_proto::{self as quinn, ConnectionHandle, Directionality, Side, StreamId, TimerUpdate};
+use quinn_proto::{self as quinn, Directionality, Side, StreamId, TimerUpdate};
+use rustls::{CipherSuite, ProtocolVersion};
 use slog::Logger;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_timer::Delay;
 
 pub use crate::quinn::{
-    ConnectError, ConnectionError, ConnectionId, ServerConfig, TransportConfig, ALPN_QUIC_H3,
-    ALPN_QUIC_HTTP,
+    congestion, ConnectError, ConnectionError, ConnectionHandle, ConnectionId, IncomingAction,
+    ServerConfig, TransportConfig, ALPN_QUIC_H3, ALPN_QUIC_HTTP,
 };
 pub use crate::tls::{Certificate, CertificateChain, PrivateKey};
 
@@ -121,8 +124,7 @@ impl Endpoint {
         &self,
         addr: &SocketAddr,
         server_name: &str,
-    ) -> Result<impl Future<Item = NewClientConnection, Error = ConnectionError>, ConnectError>
-    {
+    ) -> Result<Connecting, ConnectError> {
         self.connect_with(&self.default_client_config, addr, server_name)
     }
 
@@ -135,21 +137,24 @@ impl Endpoint {
         config: &ClientConfig,
         addr: &SocketAddr,
         server_name: &str,
-    ) -> Result<impl Future<Item = NewClientConnection, Error = ConnectionError>, ConnectError>
-    {
-        let (fut, conn) = self.connect_inner(
+    ) -> Result<Connecting, ConnectError> {
+        let (connected, conn) = self.connect_inner(
             addr,
             config.transport.clone(),
             config.tls_config.clone(),
             server_name,
         )?;
-        Ok(fut.map_err(|_| unreachable!()).and_then(move |err| {
-            if let Some(err) = err {
-                Err(err)
-            } else {
-                Ok(NewClientConnection::new(Rc::new(conn)))
-            }
-        }))
+        let zero_rtt = self
+            .inner
+            .borrow()
+            .inner
+            .connection(conn.handle)
+            .has_0rtt();
+        Ok(Connecting {
+            conn: Some(conn),
+            connected,
+            zero_rtt,
+        })
     }
 
     /// Switch to a new UDP socket
@@ -163,14 +168,63 @@ impl Endpoint {
         socket: std::net::UdpSocket,
         reactor: &tokio_reactor::Handle,
     ) -> io::Result<()> {
-        let addr = socket.local_addr()?;
         let socket = UdpSocket::from_std(socket, &reactor)?;
-        let mut inner = self.inner.borrow_mut();
-        inner.socket = socket;
-        inner.ipv6 = addr.is_ipv6();
+        self.inner.borrow_mut().socket = socket;
         Ok(())
     }
 
+    /// Close all connections and stop accepting new ones
+    ///
+    /// Every connection is closed immediately with `error_code` and `reason`, as by
+    /// `Connection::close`, and incoming connection attempts are refused from now on. The `Driver`
+    /// future completes once every connection has been drained.
+    pub fn close(&self, error_code: u16, reason: &[u8]) {
+        let endpoint = &mut *self.inner.borrow_mut();
+        endpoint.closed = true;
+        endpoint.inner.reject_new_connections();
+        let now = Instant::now();
+        for (&ch, pending) in &endpoint.pending {
+            if !pending.drained {
+                endpoint.inner.close(now, ch, error_code, reason.into());
+            }
+        }
+        endpoint.notify();
+    }
+
+    /// Details of every connection on this endpoint that has not yet been drained
+    ///
+    /// Includes incoming connections that have not yet been accepted, which can't be retrieved
+    /// with `connection` until they are.
+    pub fn connections(&self) -> Vec<ConnectionSummary> {
+        let endpoint = self.inner.borrow();
+        endpoint
+            .pending
+            .iter()
+            .filter(|(_, pending)| !pending.drained)
+            .map(|(&handle, _)| {
+                let conn = endpoint.inner.connection(handle);
+                ConnectionSummary {
+                    handle,
+                    remote: conn.remote(),
+                    remote_id: conn.rem_cid(),
+                }
+            })
+            .collect()
+    }
+
+    /// Get a handle to a connection listed by `connections`
+    ///
+    /// Returns `None` if the connection has been drained, has not yet been accepted or finished
+    /// connecting, or if every other handle to it has been dropped.
+    pub fn connection(&self, handle: ConnectionHandle) -> Option<Connection> {
+        self.inner
+            .borrow()
+            .pending
+            .get(&handle)
+            .and_then(|pending| pending.connection.upgrade())
+            .map(Connection)
+    }
+
     /*
     /// Connect to a remote endpoint, with support for transmitting data before the connection is
     /// established
@@ -218,25 +272,14 @@ impl Endpoint {
         transport_config: Arc<TransportConfig>,
         crypto_config: Arc<quinn::ClientConfig>,
         server_name: &str,
-    ) -> Result<
-        (
-            impl Future<Item = Option<ConnectionError>, Error = futures::Canceled>,
-            ConnectionInner,
-        ),
-        ConnectError,
-    > {
+    ) -> Result<(oneshot::Receiver<Option<ConnectionError>>, ConnectionInner), ConnectError> {
         let (send, recv) = oneshot::channel();
         let handle = {
             let mut endpoint = self.inner.borrow_mut();
-            let addr = if endpoint.ipv6 {
-                SocketAddr::V6(ensure_ipv6(*addr))
-            } else {
-                *addr
-            };
             let handle =
                 endpoint
                     .inner
-                    .connect(addr, transport_config, crypto_config, server_name)?;
+                    .connect(*addr, transport_config, crypto_config, server_name)?;
             endpoint.pending.insert(handle, Pending::new(Some(send)));
             endpoint.notify();
             handle
@@ -245,11 +288,59 @@ impl Endpoint {
             endpoint: self.inner.clone(),
             handle,
             side: Side::Client,
+            user_data: RefCell::new(None),
         };
         Ok((recv, conn))
     }
 }
 
+/// Identifying details of a connection, as reported by `Endpoint::connections`
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionSummary {
+    /// Key with which the connection can be retrieved using `Endpoint::connection`
+    pub handle: ConnectionHandle,
+    /// The peer's UDP address
+    pub remote: SocketAddr,
+    /// The connection ID chosen by the peer
+    pub remote_id: ConnectionId,
+}
+
+/// A connection in the process of being established
+///
+/// Resolves once the handshake completes.
+pub struct Connecting {
+    conn: Option<ConnectionInner>,
+    connected: oneshot::Receiver<Option<ConnectionError>>,
+    zero_rtt: bool,
+}
+
+impl Connecting {
+    /// Whether 0-RTT will be attempted on this connection
+    ///
+    /// This is the case when a resumption ticket with compatible transport parameters was
+    /// available for the server. Data sent before the handshake completes may be rejected by the
+    /// server, and may be replayed by an attacker, so should only be sent when this is `true` and
+    /// the application protocol tolerates it.
+    pub fn has_0rtt(&self) -> bool {
+        self.zero_rtt
+    }
+}
+
+impl Future for Connecting {
+    type Item = NewClientConnection;
+    type Error = ConnectionError;
+    fn poll(&mut self) -> Poll<NewClientConnection, ConnectionError> {
+        match self.connected.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(NewClientConnection::new(Rc::new(
+                self.conn.take().expect("polled after completion"),
+            )))),
+            Ok(Async::Ready(Some(e))) => Err(e),
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
 /// A future that drives IO on an endpoint.
 pub struct Driver(Rc<RefCell<EndpointInner>>);
 
@@ -323,13 +414,13 @@ impl Future for Driver {
                             writer.notify();
                         }
                     }
-                    StreamOpened => {
+                    StreamOpened { .. } => {
                         let pending = endpoint.pending.get_mut(&ch).unwrap();
                         if let Some(x) = pending.incoming_streams_reader.take() {
                             x.notify();
                         }
                     }
-                    StreamReadable { stream } => {
+                    StreamReadable { stream } | StreamReset { stream, .. } => {
                         let pending = endpoint.pending.get_mut(&ch).unwrap();
                         if let Some(reader) = pending.blocked_readers.remove(&stream) {
                             reader.notify();
@@ -342,6 +433,10 @@ impl Future for Driver {
                             Directionality::Bi => &mut pending.bi_opening,
                         };
                         while let Some(connection) = queue.pop_front() {
+                            if connection.is_canceled() {
+                                // The application stopped waiting, e.g. due to a timeout
+                                continue;
+                            }
                             if let Some(id) = endpoint.inner.open(ch, directionality) {
                                 let _ = connection.send(Ok(id));
                             } else {
@@ -360,14 +455,19 @@ impl Future for Driver {
                             .unwrap()
                             .send(None);
                     }
+                    CongestionWindowReduced { .. }
+                    | Idle
+                    | Migrated { .. }
+                    | PathProbed { .. }
+                    | SendBufferLow
+                    | ZeroRttAccepted
+                    | ZeroRttRejected => {}
                     Handshaking => {
                         endpoint.pending.insert(ch, Pending::new(None));
                         match endpoint.incoming.poll_ready() {
                             Ok(Async::Ready(())) => {
-                                endpoint
-                                    .incoming
-                                    .start_send(NewConnection::new(self.0.clone(), ch))
-                                    .unwrap();
+                                let conn = NewConnection::new(self.0.clone(), endpoint, ch);
+                                endpoint.incoming.start_send(conn).unwrap();
                                 endpoint.inner.accept();
                             }
                             _ => {
@@ -379,10 +479,8 @@ impl Future for Driver {
             }
             while let Ok(Async::Ready(())) = endpoint.incoming.poll_ready() {
                 if let Some(ch) = endpoint.buffered_incoming.pop_front() {
-                    endpoint
-                        .incoming
-                        .start_send(NewConnection::new(self.0.clone(), ch))
-                        .unwrap();
+                    let conn = NewConnection::new(self.0.clone(), endpoint, ch);
+                    endpoint.incoming.start_send(conn).unwrap();
                     endpoint.inner.accept();
                 } else {
                     break;
@@ -391,7 +489,7 @@ impl Future for Driver {
             let _ = endpoint.incoming.poll_complete();
             let mut blocked = false;
             if let Some(ref x) = endpoint.outgoing {
-                match endpoint.socket.poll_send(&x.destination, x.ecn, &x.packet) {
+                match endpoint.socket.poll_send(&x.destination, x.source, x.ecn, &x.packet) {
                     Ok(Async::Ready(_)) => {
                         endpoint.outgoing = None;
                     }
@@ -409,7 +507,7 @@ impl Future for Driver {
             if !blocked {
                 let mut sent = 0;
                 while let Some(x) = endpoint.inner.poll_transmit(now) {
-                    match endpoint.socket.poll_send(&x.destination, x.ecn, &x.packet) {
+                    match endpoint.socket.poll_send(&x.destination, x.source, x.ecn, &x.packet) {
                         Ok(Async::Ready(_)) => {}
                         Ok(Async::NotReady) => {
                             endpoint.outgoing = Some(x);
@@ -508,6 +606,9 @@ impl Future for Driver {
                 break;
             }
         }
+        if endpoint.closed && endpoint.pending.values().all(|x| x.drained) {
+            return Ok(Async::Ready(()));
+        }
         Ok(Async::NotReady)
     }
 }
@@ -519,6 +620,7 @@ impl Drop for Driver {
             ch.fail(ConnectionError::TransportError(quinn::TransportError {
                 code: quinn::TransportErrorCode::INTERNAL_ERROR,
                 frame: None,
+                space: None,
                 reason: "driver future was dropped".to_string(),
             }));
         }
@@ -536,7 +638,8 @@ struct EndpointInner {
     buffered_incoming: VecDeque<ConnectionHandle>,
     incoming: futures::sync::mpsc::Sender<NewConnection>,
     driver: Option<Task>,
-    ipv6: bool,
+    /// Whether `Endpoint::close` has been called
+    closed: bool,
 }
 
 impl EndpointInner {
@@ -561,6 +664,8 @@ struct Pending {
     closing: Option<oneshot::Sender<()>>,
     dropped: bool,
     drained: bool,
+    /// The application's handle, once one has been created
+    connection: Weak<ConnectionInner>,
 }
 
 impl Pending {
@@ -571,13 +676,14 @@ impl Pending {
             connecting,
             uni_opening: VecDeque::new(),
             bi_opening: VecDeque::new(),
-            cancel_timers: [None, None, None, None, None, None],
+            cancel_timers: [None, None, None, None, None, None, None, None],
             incoming_streams_reader: None,
             finishing: FnvHashMap::default(),
             error: None,
             closing: None,
             dropped: false,
             drained: false,
+            connection: Weak::new(),
         }
     }
 
@@ -619,12 +725,18 @@ pub struct NewConnection {
 }
 
 impl NewConnection {
-    fn new(endpoint: Rc<RefCell<EndpointInner>>, handle: quinn::ConnectionHandle) -> Self {
+    fn new(
+        endpoint: Rc<RefCell<EndpointInner>>,
+        inner: &mut EndpointInner,
+        handle: ConnectionHandle,
+    ) -> Self {
         let conn = Rc::new(ConnectionInner {
             endpoint,
             handle,
             side: Side::Server,
+            user_data: RefCell::new(None),
         });
+        inner.pending.get_mut(&handle).unwrap().connection = Rc::downgrade(&conn);
         NewConnection {
             connection: Connection(conn.clone()),
             incoming: IncomingStreams(conn),
@@ -642,6 +754,9 @@ pub struct NewClientConnection {
 
 impl NewClientConnection {
     fn new(conn: Rc<ConnectionInner>) -> Self {
+        if let Some(pending) = conn.endpoint.borrow_mut().pending.get_mut(&conn.handle) {
+            pending.connection = Rc::downgrade(&conn);
+        }
         Self {
             connection: Connection(conn.clone()),
             incoming: IncomingStreams(conn.clone()),
@@ -659,7 +774,27 @@ impl NewClientConnection {
 pub struct Connection(Rc<ConnectionInner>);
 
 impl Connection {
+    /// Associate application-defined data with this connection
+    ///
+    /// Replaces any data set previously. Shared by every handle to the connection, including those
+    /// obtained later from `Endpoint::connection`.
+    pub fn set_user_data<T: Any>(&self, data: T) {
+        *self.0.user_data.borrow_mut() = Some(Rc::new(data));
+    }
+
+    /// The data most recently associated with this connection by `set_user_data`
+    ///
+    /// `None` if no data has been set, or if it isn't a `T`.
+    pub fn user_data<T: Any>(&self) -> Option<Rc<T>> {
+        let data = self.0.user_data.borrow().clone()?;
+        data.downcast().ok()
+    }
+
     /// Initite a new outgoing unidirectional stream.
+    ///
+    /// If the peer's stream limit has been reached, the returned future waits until the peer
+    /// permits another stream, which it may never do. Use `try_open_uni` to fail immediately, or
+    /// `open_uni_timeout` to give up eventually, instead.
     pub fn open_uni(&self) -> impl Future<Item = SendStream, Error = ConnectionError> {
         let (send, recv) = oneshot::channel();
         {
@@ -669,8 +804,8 @@ impl Connection {
             } else {
                 let pending = endpoint.pending.get_mut(&self.0.handle).unwrap();
                 pending.uni_opening.push_back(send);
-                // We don't notify the driver here because there's no way to ask the peer for more
-                // streams
+                // Let the peer know we're blocked
+                endpoint.notify();
             }
         }
         let conn = self.0.clone();
@@ -680,6 +815,10 @@ impl Connection {
     }
 
     /// Initiate a new outgoing bidirectional stream.
+    ///
+    /// If the peer's stream limit has been reached, the returned future waits until the peer
+    /// permits another stream, which it may never do. Use `try_open_bi` to fail immediately, or
+    /// `open_bi_timeout` to give up eventually, instead.
     pub fn open_bi(&self) -> impl Future<Item = BiStream, Error = ConnectionError> {
         let (send, recv) = oneshot::channel();
         {
@@ -689,8 +828,8 @@ impl Connection {
             } else {
                 let pending = endpoint.pending.get_mut(&self.0.handle).unwrap();
                 pending.bi_opening.push_back(send);
-                // We don't notify the driver here because there's no way to ask the peer for more
-                // streams
+                // Let the peer know we're blocked
+                endpoint.notify();
             }
         }
         let conn = self.0.clone();
@@ -699,6 +838,56 @@ impl Connection {
             .map(move |stream| BiStream::new(conn.clone(), stream))
     }
 
+    /// Initiate a new outgoing unidirectional stream, waiting at most `timeout` for the peer to
+    /// permit it
+    ///
+    /// While waiting, the peer is told that we're blocked on its stream limit. A peer that keeps
+    /// the connection alive but never raises the limit otherwise leaves `open_uni` pending
+    /// forever.
+    pub fn open_uni_timeout(
+        &self,
+        timeout: Duration,
+    ) -> impl Future<Item = SendStream, Error = OpenError> {
+        with_timeout(self.open_uni(), timeout)
+    }
+
+    /// Initiate a new outgoing bidirectional stream, waiting at most `timeout` for the peer to
+    /// permit it
+    ///
+    /// See `open_uni_timeout`.
+    pub fn open_bi_timeout(
+        &self,
+        timeout: Duration,
+    ) -> impl Future<Item = BiStream, Error = OpenError> {
+        with_timeout(self.open_bi(), timeout)
+    }
+
+    /// Initiate a new outgoing unidirectional stream without waiting
+    ///
+    /// Returns `None` if the peer's stream limit has been reached.
+    pub fn try_open_uni(&self) -> Option<SendStream> {
+        self.try_open(Directionality::Uni)
+            .map(|stream| SendStream(BiStream::new(self.0.clone(), stream)))
+    }
+
+    /// Initiate a new outgoing bidirectional stream without waiting
+    ///
+    /// Returns `None` if the peer's stream limit has been reached.
+    pub fn try_open_bi(&self) -> Option<BiStream> {
+        self.try_open(Directionality::Bi)
+            .map(|stream| BiStream::new(self.0.clone(), stream))
+    }
+
+    fn try_open(&self, direction: Directionality) -> Option<StreamId> {
+        let mut endpoint = self.0.endpoint.borrow_mut();
+        let id = endpoint.inner.open(self.0.handle, direction);
+        if id.is_none() {
+            // Let the peer know we're blocked
+            endpoint.notify();
+        }
+        id
+    }
+
     /// Close the connection immediately.
     ///
     /// This does not ensure delivery of outstanding data. It is the application's responsibility
@@ -746,6 +935,18 @@ impl Connection {
             .remote()
     }
 
+    /// Send all further packets from `ip`, or let the operating system choose if `None`
+    ///
+    /// Useful on hosts with multiple interfaces to control which one the connection uses. Only
+    /// supported on Linux; elsewhere, the operating system always chooses.
+    pub fn set_local_ip(&self, ip: Option<IpAddr>) {
+        self.0
+            .endpoint
+            .borrow_mut()
+            .inner
+            .set_local_ip(self.0.handle, ip);
+    }
+
     /// The `ConnectionId`s defined for `conn` locally.
     pub fn local_ids(&self) -> impl Iterator<Item = ConnectionId> {
         self.0
@@ -779,6 +980,62 @@ impl Connection {
             .map(|x| x.into())
     }
 
+    /// The TLS cipher suite negotiated by the handshake
+    pub fn negotiated_cipher_suite(&self) -> Option<CipherSuite> {
+        self.0
+            .endpoint
+            .borrow()
+            .inner
+            .connection(self.0.handle)
+            .negotiated_cipher_suite()
+    }
+
+    /// The TLS protocol version negotiated by the handshake
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.0
+            .endpoint
+            .borrow()
+            .inner
+            .connection(self.0.handle)
+            .protocol_version()
+    }
+
+    /// Stop permitting the peer to open new streams
+    ///
+    /// Streams the peer may already open will continue to be delivered, but no further streams
+    /// will be permitted. Useful for draining a connection before closing it.
+    pub fn stop_accepting_streams(&self) {
+        self.0
+            .endpoint
+            .borrow_mut()
+            .inner
+            .stop_accepting_streams(self.0.handle)
+    }
+
+    /// Change the interval of inactivity after which a keep-alive packet is sent
+    ///
+    /// Overrides the transport configuration for this connection only. `None` disables keep-alive,
+    /// allowing an idle connection to time out naturally.
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        let endpoint = &mut *self.0.endpoint.borrow_mut();
+        endpoint
+            .inner
+            .set_keep_alive(Instant::now(), self.0.handle, interval);
+        endpoint.notify();
+    }
+
+    /// Number of streams of the given directionality currently open on this connection
+    ///
+    /// Counts streams initiated by either peer that have not yet been closed or reset.
+    pub fn open_stream_count(&self, dir: Directionality) -> u64 {
+        self.0
+            .endpoint
+            .borrow()
+            .inner
+            .connection(self.0.handle)
+            .open_stream_count(dir)
+    }
+
     // Update traffic keys spontaneously for testing purposes.
     #[doc(hidden)]
     pub fn force_key_update(&self) {
@@ -794,6 +1051,7 @@ struct ConnectionInner {
     endpoint: Rc<RefCell<EndpointInner>>,
     handle: ConnectionHandle,
     side: Side,
+    user_data: RefCell<Option<Rc<dyn Any>>>,
 }
 
 impl Drop for ConnectionInner {
@@ -898,6 +1156,9 @@ impl Write for BiStream {
             Err(Stopped { error_code }) => {
                 return Err(WriteError::Stopped { error_code });
             }
+            Err(ZeroRttRejected) => {
+                return Err(WriteError::ZeroRttRejected);
+            }
         };
         endpoint.notify();
         Ok(Async::Ready(n))
@@ -1011,6 +1272,10 @@ impl io::Write for BiStream {
                 io::ErrorKind::ConnectionAborted,
                 format!("connection closed: {}", e),
             )),
+            Err(WriteError::ZeroRttRejected) => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "0-RTT rejected",
+            )),
         }
     }
 
@@ -1145,6 +1410,23 @@ pub fn read_to_end<T: Read>(stream: T, size_limit: usize) -> ReadToEnd<T> {
     }
 }
 
+/// Write everything read from `reader` to `stream`, then finish the stream
+///
+/// Resolves to the reader, the stream, and the number of bytes copied once the peer has
+/// acknowledged all data. Reads are suspended while the stream is blocked by flow or congestion
+/// control.
+pub fn copy_to_stream<R: AsyncRead, W: Write>(reader: R, stream: W) -> CopyToStream<R, W> {
+    CopyToStream {
+        reader: Some(reader),
+        stream: Some(stream),
+        buffer: vec![0; 8 * 1024].into(),
+        pos: 0,
+        len: 0,
+        eof: false,
+        copied: 0,
+    }
+}
+
 struct Timer {
     ch: ConnectionHandle,
     ty: quinn::Timer,
@@ -1209,6 +1491,78 @@ impl<T: Read> Future for ReadToEnd<T> {
     }
 }
 
+/// Future produced by `copy_to_stream`
+pub struct CopyToStream<R, W> {
+    reader: Option<R>,
+    stream: Option<W>,
+    buffer: Box<[u8]>,
+    /// Start of the data in `buffer` not yet written to the stream
+    pos: usize,
+    /// End of the data in `buffer`
+    len: usize,
+    eof: bool,
+    copied: u64,
+}
+
+impl<R: AsyncRead, W: Write> Future for CopyToStream<R, W> {
+    type Item = (R, W, u64);
+    type Error = CopyError;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if self.pos == self.len && !self.eof {
+                let reader = self.reader.as_mut().unwrap();
+                match AsyncRead::poll_read(reader, &mut self.buffer) {
+                    Ok(Async::Ready(0)) => {
+                        self.eof = true;
+                    }
+                    Ok(Async::Ready(n)) => {
+                        self.pos = 0;
+                        self.len = n;
+                    }
+                    Ok(Async::NotReady) => {
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => {
+                        return Err(CopyError::Read(e));
+                    }
+                }
+            }
+            let stream = self.stream.as_mut().unwrap();
+            while self.pos < self.len {
+                match Write::poll_write(stream, &self.buffer[self.pos..self.len]) {
+                    Ok(Async::Ready(n)) => {
+                        self.pos += n;
+                        self.copied += n as u64;
+                    }
+                    Ok(Async::NotReady) => {
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => {
+                        return Err(CopyError::Write(e));
+                    }
+                }
+            }
+            if self.eof {
+                match stream.poll_finish() {
+                    Ok(Async::Ready(())) => {
+                        return Ok(Async::Ready((
+                            self.reader.take().unwrap(),
+                            self.stream.take().unwrap(),
+                            self.copied,
+                        )));
+                    }
+                    Ok(Async::NotReady) => {
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => {
+                        return Err(CopyError::Finish(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// Trait of readable streams
 pub trait Read {
     /// Read data contiguously from the stream.
@@ -1246,6 +1600,32 @@ pub trait Read {
     fn stop(&mut self, error_code: u16);
 }
 
+/// Fail with `OpenError::TimedOut` if `open` doesn't complete within `timeout`
+fn with_timeout<F, T>(open: F, timeout: Duration) -> impl Future<Item = T, Error = OpenError>
+where
+    F: Future<Item = T, Error = ConnectionError>,
+{
+    open.map_err(OpenError::ConnectionClosed)
+        .select2(Delay::new(Instant::now() + timeout))
+        .then(|result| match result {
+            Ok(Either::A((stream, _))) => Ok(stream),
+            Ok(Either::B(_)) => Err(OpenError::TimedOut),
+            Err(Either::A((e, _))) => Err(e),
+            Err(Either::B((e, _))) => panic!("unexpected timer error: {}", e),
+        })
+}
+
+/// Errors that arise from opening a stream with a timeout
+#[derive(Debug, Error, Clone)]
+pub enum OpenError {
+    /// The connection was closed.
+    #[error(display = "connection closed: {}", _0)]
+    ConnectionClosed(ConnectionError),
+    /// The peer didn't permit another stream to be opened in time.
+    #[error(display = "timed out waiting for the peer's stream limit to be raised")]
+    TimedOut,
+}
+
 /// Errors that arise from reading from a stream.
 #[derive(Debug, Error, Clone)]
 pub enum ReadError {
@@ -1301,11 +1681,21 @@ pub enum WriteError {
     /// The connection was closed.
     #[error(display = "connection closed: {}", _0)]
     ConnectionClosed(ConnectionError),
+    /// The stream was opened in 0-RTT and discarded when the server rejected 0-RTT data.
+    #[error(display = "0-RTT rejected")]
+    ZeroRttRejected,
 }
 
-fn ensure_ipv6(x: SocketAddr) -> SocketAddrV6 {
-    match x {
-        SocketAddr::V6(x) => x,
-        SocketAddr::V4(x) => SocketAddrV6::new(x.ip().to_ipv6_mapped(), x.port(), 0, 0),
-    }
+/// Errors that arise from `copy_to_stream`
+#[derive(Debug, Error)]
+pub enum CopyError {
+    /// The source could not be read
+    #[error(display = "read failed: {}", _0)]
+    Read(io::Error),
+    /// The stream could not be written
+    #[error(display = "write failed: {}", _0)]
+    Write(WriteError),
+    /// The stream could not be finished
+    #[error(display = "finish failed: {}", _0)]
+    Finish(ConnectionError),
 }
@@ -1,4 +1,7 @@
-use std::{io, net::SocketAddr};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
 
 use mio::net::UdpSocket;
 
@@ -12,6 +15,7 @@ impl super::UdpExt for UdpSocket {
     fn send_ext(
         &self,
         remote: &SocketAddr,
+        _: Option<IpAddr>,
         _: Option<EcnCodepoint>,
         msg: &[u8],
     ) -> io::Result<usize> {
@@ -1,6 +1,9 @@
 //! Uniform interface to send/recv UDP packets with ECN information.
 use quinn_proto::EcnCodepoint;
-use std::{io, net::SocketAddr};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
 
 #[cfg(unix)]
 mod cmsg;
@@ -13,9 +16,11 @@ mod fallback;
 
 pub trait UdpExt {
     fn init_ext(&self) -> io::Result<()>;
+    /// Send `msg` to `remote`, from `source` if specified and supported by the platform
     fn send_ext(
         &self,
         remote: &SocketAddr,
+        source: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         msg: &[u8],
     ) -> io::Result<usize>;
@@ -1,7 +1,7 @@
 use std::os::unix::io::AsRawFd;
 use std::{
     io, mem,
-    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
     ptr,
 };
 
@@ -74,6 +74,7 @@ impl super::UdpExt for UdpSocket {
     fn send_ext(
         &self,
         remote: &SocketAddr,
+        source: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         msg: &[u8],
     ) -> io::Result<usize> {
@@ -111,6 +112,29 @@ impl super::UdpExt for UdpSocket {
         } else {
             encoder.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ecn);
         }
+        #[cfg(target_os = "linux")]
+        match source {
+            Some(IpAddr::V4(ip)) => {
+                let addr = libc::in_addr {
+                    s_addr: u32::from_ne_bytes(ip.octets()),
+                };
+                let pktinfo = libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: addr,
+                    ipi_addr: addr,
+                };
+                encoder.push(libc::IPPROTO_IP, libc::IP_PKTINFO, pktinfo);
+            }
+            Some(IpAddr::V6(ip)) => {
+                let mut pktinfo: libc::in6_pktinfo = unsafe { mem::zeroed() };
+                pktinfo.ipi6_addr.s6_addr = ip.octets();
+                encoder.push(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, pktinfo);
+            }
+            None => {}
+        }
+        // Selecting the source address is only implemented on Linux
+        #[cfg(not(target_os = "linux"))]
+        let _ = source;
         encoder.finish();
         loop {
             let n = unsafe { libc::sendmsg(self.as_raw_fd(), &hdr, 0) };
@@ -176,4 +200,4 @@ impl super::UdpExt for UdpSocket {
     }
 }
 
-const CMSG_LEN: usize = 24;
+const CMSG_LEN: usize = 64;
@@ -73,6 +73,9 @@ fn run_echo(client_addr: SocketAddr, server_addr: SocketAddr) {
                 .map_err(|e| panic!("connection failed: {}", e))
                 .and_then(move |conn| {
                     let conn = conn.connection;
+                    // Replies from an IPv4 server to a dual-stack client must be attributed to the
+                    // same address the client dialed
+                    assert_eq!(conn.remote_address(), server_addr);
                     let stream = conn.open_bi();
                     stream
                         .map_err(|_| ())
@@ -1,8 +1,9 @@
 //! TLS-related helpers
 
 use std::fmt;
+use std::sync::Arc;
 
-use rustls::{self, internal::pemfile};
+use rustls::{self, internal::pemfile, ProducesTickets};
 
 /// A single TLS certificate
 #[derive(Debug, Clone)]
@@ -102,3 +103,25 @@ impl fmt::Display for ParseError {
         f.pad(self.0)
     }
 }
+
+/// Stateless session ticket issuer that advertises a custom ticket lifetime
+pub(crate) struct TicketLifetime {
+    pub(crate) inner: Arc<dyn ProducesTickets>,
+    /// In seconds
+    pub(crate) lifetime: u32,
+}
+
+impl ProducesTickets for TicketLifetime {
+    fn enabled(&self) -> bool {
+        self.inner.enabled()
+    }
+    fn get_lifetime(&self) -> u32 {
+        self.lifetime
+    }
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.inner.encrypt(plain)
+    }
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.inner.decrypt(cipher)
+    }
+}
@@ -1,5 +1,5 @@
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
 
 use futures::{try_ready, Async, Poll};
 use mio;
@@ -14,26 +14,39 @@ use crate::platform::UdpExt;
 ///
 /// Unlike a standard tokio UDP socket, this allows ECN bits to be read and written on some
 /// platforms.
+///
+/// IPv4-mapped IPv6 addresses, as seen on dual-stack sockets, are presented as plain IPv4 addresses
+/// so that each peer has a single consistent address.
 pub struct UdpSocket {
     io: PollEvented<mio::net::UdpSocket>,
+    /// Whether IPv4 peers must be addressed by their IPv4-mapped IPv6 address
+    ipv6: bool,
 }
 
 impl UdpSocket {
     pub fn from_std(socket: std::net::UdpSocket, handle: &Handle) -> io::Result<UdpSocket> {
+        let ipv6 = socket.local_addr()?.is_ipv6();
         let io = mio::net::UdpSocket::from_socket(socket)?;
         io.init_ext()?;
         let io = PollEvented::new_with_handle(io, handle)?;
-        Ok(UdpSocket { io })
+        Ok(UdpSocket { io, ipv6 })
     }
 
     pub fn poll_send(
         &self,
         remote: &SocketAddr,
+        source: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         msg: &[u8],
     ) -> Poll<usize, io::Error> {
         try_ready!(self.io.poll_write_ready());
-        match self.io.get_ref().send_ext(remote, ecn, msg) {
+        let remote = match *remote {
+            SocketAddr::V4(ref x) if self.ipv6 => {
+                SocketAddrV6::new(x.ip().to_ipv6_mapped(), x.port(), 0, 0).into()
+            }
+            x => x,
+        };
+        match self.io.get_ref().send_ext(&remote, source, ecn, msg) {
             Ok(n) => Ok(Async::Ready(n)),
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 self.io.clear_write_ready()?;
@@ -49,7 +62,7 @@ impl UdpSocket {
     ) -> Poll<(usize, SocketAddr, Option<EcnCodepoint>), io::Error> {
         try_ready!(self.io.poll_read_ready(mio::Ready::readable()));
         match self.io.get_ref().recv_ext(buf) {
-            Ok(n) => Ok(n.into()),
+            Ok((len, remote, ecn)) => Ok(Async::Ready((len, unmap(remote), ecn))),
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 self.io.clear_read_ready(mio::Ready::readable())?;
                 Ok(Async::NotReady)
@@ -58,3 +71,33 @@ impl UdpSocket {
         }
     }
 }
+
+/// Convert an IPv4-mapped IPv6 address to the IPv4 address it represents
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(ref x) if x.ip().segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+            SocketAddr::new(x.ip().to_ipv4().unwrap().into(), x.port())
+        }
+        x => x,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn unmap_addresses() {
+        let ip = Ipv4Addr::new(192, 0, 2, 1);
+        let v4 = SocketAddr::new(ip.into(), 4433);
+        let mapped = SocketAddr::new(ip.to_ipv6_mapped().into(), 4433);
+        assert_eq!(unmap(mapped), v4);
+        assert_eq!(unmap(v4), v4);
+        let v6 = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 4433);
+        assert_eq!(unmap(v6), v6);
+        // IPv4-compatible addresses are deprecated and not treated as IPv4
+        let compatible = SocketAddr::new(ip.to_ipv6_compatible().into(), 4433);
+        assert_eq!(unmap(compatible), compatible);
+    }
+}